@@ -0,0 +1,167 @@
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use log::debug;
+use tempfile::TempDir;
+use zip::ZipArchive;
+use crate::error::{Error, Result};
+use crate::extract::TrackResult;
+use crate::find::FindOptions;
+
+/// Audio files unpacked from a zip archive into a temporary directory
+///
+/// The directory, and the files in it, are removed when this is dropped.
+#[derive(Debug)]
+pub struct UnpackedArchive {
+    dir: TempDir,
+    files: Vec<PathBuf>,
+}
+
+/// Whether `path` names a zip archive that should be unpacked rather than searched
+pub fn is_zip_path(path: &Path) -> bool {
+    path.is_file() && path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("zip"))
+}
+
+/// Unpack the entries of a zip archive that match `options`
+///
+/// Entries in subdirectories of the archive are only included with `options.recursive`, the same
+/// as for a directory on disk.
+pub fn unpack_zip(archive_path: &Path, options: &FindOptions) -> Result<UnpackedArchive> {
+    let zip_error = |source| Error::Zip { path: archive_path.to_path_buf(), source };
+    let file = File::open(archive_path).map_err(|e| Error::io("Failed to open archive", archive_path, e))?;
+    let mut archive = ZipArchive::new(file).map_err(zip_error)?;
+    let dir = TempDir::new().map_err(|e| Error::io("Failed to create a temporary directory for", archive_path, e))?;
+    let mut files = Vec::new();
+
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index).map_err(zip_error)?;
+        if entry.is_dir() {
+            continue;
+        }
+        // Names that would escape the directory (absolute, or with `..`) are skipped
+        let Some(name) = entry.enclosed_name() else {
+            debug!("Skipping unsafe archive entry {}", entry.name());
+            continue;
+        };
+        let nested = name.parent().is_some_and(|parent| parent != Path::new(""));
+        if (nested && !options.recursive) || !name.file_name().is_some_and(|n| options.matcher.is_match(n)) {
+            continue;
+        }
+
+        let target = dir.path().join(&name);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).map_err(|e| Error::io("Failed to unpack", archive_path, e))?;
+        }
+        // Keep the entry's own time, so `--since` sees when it last changed rather than when it was unpacked
+        let modified = entry.last_modified().and_then(entry_time);
+        File::create(&target)
+            .and_then(|mut out| {
+                io::copy(&mut entry, &mut out)?;
+                modified.map_or(Ok(()), |modified| out.set_modified(modified))
+            })
+            .map_err(|e| Error::io("Failed to unpack", archive_path, e))?;
+        debug!("Unpacked {} from {}", name.display(), archive_path.display());
+        files.push(target);
+    }
+
+    Ok(UnpackedArchive { dir, files })
+}
+
+/// The modification time stored for a zip entry
+///
+/// Zip times have no time zone, so they are taken as UTC, which can be off by the zone's offset
+/// from where the archive was made.
+fn entry_time(time: zip::DateTime) -> Option<SystemTime> {
+    let stamp = format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        time.year(),
+        time.month(),
+        time.day(),
+        time.hour(),
+        time.minute(),
+        time.second()
+    );
+    humantime::parse_rfc3339(&stamp).ok()
+}
+
+impl UnpackedArchive {
+    /// The unpacked audio files, in archive order
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    /// The name inside the archive of a file unpacked from it
+    pub fn entry_name(&self, path: &Path) -> Option<PathBuf> {
+        name_in_dir(&self.dir, path)
+    }
+
+    /// Report results for unpacked files under their archive names
+    pub fn relabel(&self, results: &mut [TrackResult]) {
+        relabel_in_dir(&self.dir, results);
+    }
+}
+
+/// The path of a file in a temporary directory relative to that directory, which is the name it
+/// had before it was put there
+pub(crate) fn name_in_dir(dir: &TempDir, path: &Path) -> Option<PathBuf> {
+    path.strip_prefix(dir.path()).ok().map(Path::to_path_buf)
+}
+
+/// Report results for files in a temporary directory under their [`name_in_dir`], keeping where
+/// they were read from in `local_path`
+pub(crate) fn relabel_in_dir(dir: &TempDir, results: &mut [TrackResult]) {
+    for result in results {
+        if let Some(name) = name_in_dir(dir, &result.path) {
+            result.local_path = Some(std::mem::replace(&mut result.path, name));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extract::{extract_all_lyrics, ExtractOptions};
+    use crate::test_util::create_test_mp3;
+    use std::io::Write;
+    use tempfile::tempdir;
+    use zip::write::SimpleFileOptions;
+
+    #[test]
+    fn test_unpack_zip() {
+        let temp_dir = tempdir().unwrap();
+        let mp3_path = create_test_mp3(temp_dir.path(), "song.mp3", Some("Zipped lyrics"));
+        let mp3_bytes = fs::read(&mp3_path).unwrap();
+        let archive_path = temp_dir.path().join("album.zip");
+
+        let mut writer = zip::ZipWriter::new(File::create(&archive_path).unwrap());
+        for name in ["intro.mp3", "disc1/track1.mp3"] {
+            writer.start_file(name, SimpleFileOptions::default()).unwrap();
+            writer.write_all(&mp3_bytes).unwrap();
+        }
+        let dated = zip::DateTime::from_date_and_time(2020, 1, 2, 3, 4, 6).unwrap();
+        writer.start_file("dated.mp3", SimpleFileOptions::default().last_modified_time(dated)).unwrap();
+        writer.write_all(&mp3_bytes).unwrap();
+        writer.start_file("cover.jpg", SimpleFileOptions::default()).unwrap();
+        writer.write_all(b"not audio").unwrap();
+        writer.finish().unwrap();
+        assert!(is_zip_path(&archive_path));
+
+        let top_level = unpack_zip(&archive_path, &FindOptions::default()).unwrap();
+        assert_eq!(top_level.files().len(), 2);
+        // Unpacked files keep the time stored in the archive
+        let modified = fs::metadata(&top_level.files()[1]).unwrap().modified().unwrap();
+        assert_eq!(modified, humantime::parse_rfc3339("2020-01-02T03:04:06Z").unwrap());
+
+        let recursive = FindOptions { recursive: true, ..Default::default() };
+        let unpacked = unpack_zip(&archive_path, &recursive).unwrap();
+        let names: Vec<PathBuf> = unpacked.files().iter().filter_map(|f| unpacked.entry_name(f)).collect();
+        assert_eq!(names, [PathBuf::from("intro.mp3"), Path::new("disc1").join("track1.mp3"), PathBuf::from("dated.mp3")]);
+
+        let mut results = extract_all_lyrics(unpacked.files(), &ExtractOptions::default(), 1).unwrap();
+        unpacked.relabel(&mut results);
+        assert_eq!(results[1].path, Path::new("disc1").join("track1.mp3"));
+        assert_eq!(results[1].lyrics.as_deref(), Some("Zipped lyrics"));
+        assert_eq!(results[1].local_path.as_deref(), Some(unpacked.files()[1].as_path()));
+    }
+}
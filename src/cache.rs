@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::error::{Error, Result};
+use crate::extract::{extract_all_lyrics, ExtractOptions, FileStatus, LyricsFrame, SyncedLyricsInfo, TrackResult};
+
+/// Results of earlier runs, kept in a JSON file so unchanged files don't have to be parsed again
+///
+/// A file's entry is reused while its size and modification time stay the same, and only by runs
+/// with the same extraction options. The other frames the lyrics could have come from, which only
+/// `--interactive` offers, aren't kept. Files that failed aren't cached, so they are tried again. A
+/// cache file that can't be read or parsed is treated as empty and replaced when saved, so
+/// deleting it is always safe.
+#[derive(Debug)]
+pub struct ExtractionCache {
+    path: PathBuf,
+    contents: CacheContents,
+    hits: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct CacheContents {
+    /// Fingerprint of the options the entries were extracted with
+    options: String,
+    entries: HashMap<PathBuf, CacheEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct CacheEntry {
+    size: u64,
+    /// Modification time as seconds and nanoseconds since the Unix epoch
+    modified: (u64, u32),
+    lyrics: Option<String>,
+    title: Option<String>,
+    artist: Option<String>,
+    album: Option<String>,
+    #[serde(default)]
+    track_number: Option<u32>,
+    #[serde(default)]
+    instrumental: bool,
+    lyrics_frame: Option<LyricsFrame>,
+    synced_lyrics: Option<SyncedLyricsInfo>,
+}
+
+impl ExtractionCache {
+    /// Load the cache at `path` for a run with `options`, starting empty if there isn't a usable one
+    pub fn load(path: &Path, options: &ExtractOptions) -> Self {
+        let fingerprint = options_fingerprint(options);
+        let contents = match fs::read(path) {
+            Ok(json) => match serde_json::from_slice::<CacheContents>(&json) {
+                Ok(contents) if contents.options == fingerprint => contents,
+                Ok(_) => {
+                    info!("Ignoring the cache in {} as it was built with different options", path.display());
+                    CacheContents::default()
+                }
+                Err(e) => {
+                    warn!("Ignoring unreadable cache {}, it will be rebuilt: {}", path.display(), e);
+                    CacheContents::default()
+                }
+            },
+            Err(e) => {
+                debug!("Starting a new cache in {}: {}", path.display(), e);
+                CacheContents::default()
+            }
+        };
+        let mut contents = CacheContents { options: fingerprint, ..contents };
+        // Drop files that have gone, including ones unpacked from archives by earlier runs
+        contents.entries.retain(|file, _| file.exists());
+        ExtractionCache { path: path.to_path_buf(), contents, hits: 0 }
+    }
+
+    /// Extract lyrics like [`extract_all_lyrics`], reusing the cached results of unchanged files
+    /// and caching the rest
+    pub fn extract_all(&mut self, audio_files: &[PathBuf], options: &ExtractOptions, jobs: usize) -> Result<Vec<TrackResult>> {
+        let mut results: Vec<Option<TrackResult>> = Vec::with_capacity(audio_files.len());
+        let mut misses = Vec::new();
+        for (index, file) in audio_files.iter().enumerate() {
+            let stamp = file_stamp(file);
+            let entry = self.contents.entries.get(file).filter(|entry| stamp == Some((entry.size, entry.modified)));
+            let result = entry.map(|entry| entry.to_result(file));
+            match &result {
+                Some(result) => {
+                    debug!("Using cached lyrics for {}", file.display());
+                    if let Some(events) = &options.progress {
+                        events.file_done(file, FileStatus::of(result));
+                    }
+                }
+                None => misses.push((index, stamp)),
+            }
+            results.push(result);
+        }
+        self.hits += audio_files.len() - misses.len();
+
+        // Read the rest in one go, so they still spread over the worker threads
+        let files: Vec<PathBuf> = misses.iter().map(|&(index, _)| audio_files[index].clone()).collect();
+        let extracted = extract_all_lyrics(&files, options, jobs)?;
+        for ((index, stamp), result) in misses.into_iter().zip(extracted) {
+            if let (None, Some((size, modified))) = (&result.error, stamp) {
+                self.contents.entries.insert(result.path.clone(), CacheEntry::new(&result, size, modified));
+            }
+            results[index] = Some(result);
+        }
+        Ok(results.into_iter().flatten().collect())
+    }
+
+    /// How many files were answered from the cache rather than read
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+
+    /// Write the cache back to its file
+    ///
+    /// It is written to a temporary file first and then moved into place, so an interrupted save
+    /// leaves the old cache intact.
+    pub fn save(&self) -> Result<()> {
+        let json = serde_json::to_vec(&self.contents)?;
+        let temp_path = self.path.with_extension("tmp");
+        fs::write(&temp_path, json).map_err(|e| Error::io("Failed to write cache", &temp_path, e))?;
+        fs::rename(&temp_path, &self.path).map_err(|e| Error::io("Failed to write cache", &self.path, e))
+    }
+}
+
+impl CacheEntry {
+    fn new(result: &TrackResult, size: u64, modified: (u64, u32)) -> Self {
+        CacheEntry {
+            size,
+            modified,
+            lyrics: result.lyrics.clone(),
+            title: result.title.clone(),
+            artist: result.artist.clone(),
+            album: result.album.clone(),
+            track_number: result.track_number,
+            instrumental: result.instrumental,
+            lyrics_frame: result.lyrics_frame.clone(),
+            synced_lyrics: result.synced_lyrics.clone(),
+        }
+    }
+
+    fn to_result(&self, file: &Path) -> TrackResult {
+        TrackResult {
+            path: file.to_path_buf(),
+            lyrics: self.lyrics.clone(),
+            title: self.title.clone(),
+            artist: self.artist.clone(),
+            album: self.album.clone(),
+            track_number: self.track_number,
+            instrumental: self.instrumental,
+            lyrics_frame: self.lyrics_frame.clone(),
+            synced_lyrics: self.synced_lyrics.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+/// The size and modification time that tell whether a file has changed since it was cached
+fn file_stamp(file: &Path) -> Option<(u64, (u64, u32))> {
+    let metadata = fs::metadata(file).ok()?;
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some((metadata.len(), (modified.as_secs(), modified.subsec_nanos())))
+}
+
+/// A digest of the options that change what is extracted, leaving out ones such as the number of
+/// retries that only affect how
+fn options_fingerprint(options: &ExtractOptions) -> String {
+    let relevant = ExtractOptions {
+        retries: 0,
+        log_interval: 0,
+        concurrency_limit: 0,
+        fail_fast: false,
+        progress: None,
+        ..options.clone()
+    };
+    format!("{:x}", Sha256::digest(format!("{:?}", relevant)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::extract::ProgressEvents;
+    use crate::test_util::create_test_mp3;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_extraction_cache() {
+        let temp_dir = tempdir().unwrap();
+        let cache_path = temp_dir.path().join("cache.json");
+        let files = [
+            create_test_mp3(temp_dir.path(), "a.mp3", Some("First")),
+            create_test_mp3(temp_dir.path(), "b.mp3", Some("Second")),
+        ];
+        let options = ExtractOptions::default();
+        let run = |options: &ExtractOptions| {
+            let mut cache = ExtractionCache::load(&cache_path, options);
+            let results = cache.extract_all(&files, options, 1).unwrap();
+            cache.save().unwrap();
+            (results, cache.hits())
+        };
+
+        let (fresh, hits) = run(&options);
+        assert_eq!(hits, 0);
+        let (cached, hits) = run(&options);
+        assert_eq!(hits, 2);
+        // Only --interactive needs the other frames the lyrics could have come from
+        let fresh: Vec<TrackResult> = fresh.into_iter().map(|result| TrackResult { candidates: Vec::new(), ..result }).collect();
+        assert_eq!(cached, fresh);
+
+        // A changed file is read again, and other options can't reuse the entries
+        create_test_mp3(temp_dir.path(), "b.mp3", Some("Second, rewritten"));
+        let (results, hits) = run(&options);
+        assert_eq!(hits, 1);
+        assert_eq!(results[1].lyrics.as_deref(), Some("Second, rewritten"));
+        assert_eq!(run(&ExtractOptions { strip_html: true, ..Default::default() }).1, 0);
+
+        // A damaged cache counts as empty
+        fs::write(&cache_path, "{ not json").unwrap();
+        assert_eq!(run(&options).1, 0);
+        assert_eq!(run(&options).1, 2);
+
+        // Cache hits count towards the progress events as well as the files read
+        create_test_mp3(temp_dir.path(), "b.mp3", Some("Second, again"));
+        let events = Arc::new(ProgressEvents::new(files.len(), HashMap::new()));
+        let (_, hits) = run(&ExtractOptions { progress: Some(Arc::clone(&events)), ..Default::default() });
+        assert_eq!(hits, 1);
+        assert_eq!(events.files_done(), 2);
+    }
+}
@@ -0,0 +1,96 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use anyhow::{Context, Result};
+use clap::ArgMatches;
+use clap::parser::ValueSource;
+use log::debug;
+use serde::Deserialize;
+
+/// Defaults read from the config file, each overridden by the matching command line flag
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub output: Option<String>,
+    pub recursive: Option<bool>,
+    pub separator: Option<bool>,
+    pub separator_text: Option<String>,
+    pub include_names: Option<bool>,
+}
+
+impl Config {
+    /// Load the config from `path`, or from the default location when no path is given
+    ///
+    /// A missing file at the default location is the same as an empty config, but a path given
+    /// explicitly has to exist.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let (path, explicit) = match path {
+            Some(path) => (path.to_path_buf(), true),
+            None => match default_config_path() {
+                Some(path) => (path, false),
+                None => return Ok(Config::default()),
+            },
+        };
+
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound && !explicit => {
+                debug!("No config file at {}", path.display());
+                return Ok(Config::default());
+            }
+            Err(e) => return Err(e).with_context(|| format!("Failed to read config file {}", path.display())),
+        };
+        debug!("Loaded config from {}", path.display());
+        Config::parse(&contents).with_context(|| format!("Invalid config file {}", path.display()))
+    }
+
+    fn parse(contents: &str) -> Result<Self> {
+        Ok(toml::from_str(contents)?)
+    }
+}
+
+/// `~/.config/mdmp3lyrics/config.toml`
+fn default_config_path() -> Option<PathBuf> {
+    let home = env::var_os("HOME").or_else(|| env::var_os("USERPROFILE"))?;
+    Some(PathBuf::from(home).join(".config").join("mdmp3lyrics").join("config.toml"))
+}
+
+/// Use the config `value` for the argument `id`, unless it was also given on the command line
+pub fn apply<T>(target: &mut T, value: Option<T>, matches: &ArgMatches, id: &str) {
+    if let Some(value) = value {
+        if matches.value_source(id) != Some(ValueSource::CommandLine) {
+            *target = value;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_parse_config() {
+        let config = Config::parse("output = \"all.txt\"\nrecursive = true\nseparator_text = \"***\"\n").unwrap();
+        assert_eq!(config, Config {
+            output: Some("all.txt".to_string()),
+            recursive: Some(true),
+            separator_text: Some("***".to_string()),
+            ..Default::default()
+        });
+
+        assert!(Config::parse("recursive = \"yes\"").is_err());
+        assert!(Config::parse("unknown = 1").is_err());
+    }
+
+    #[test]
+    fn test_load_config() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("config.toml");
+        fs::write(&path, "include_names = true\n").unwrap();
+
+        assert_eq!(Config::load(Some(&path)).unwrap().include_names, Some(true));
+        assert!(Config::load(Some(&temp_dir.path().join("missing.toml"))).is_err());
+    }
+}
@@ -0,0 +1,217 @@
+use std::io;
+use std::path::PathBuf;
+use thiserror::Error;
+
+/// Errors returned by the lyrics extraction library
+#[derive(Error, Debug)]
+pub enum Error {
+    /// The input path is neither a file nor a directory
+    #[error("The specified path does not exist: {}", .0.display())]
+    PathNotFound(PathBuf),
+
+    /// A single file was given that doesn't match the accepted file patterns
+    #[error("The specified file is not a supported audio file: {}", .0.display())]
+    UnsupportedFile(PathBuf),
+
+    /// A `--pattern` or `--ext` glob could not be compiled
+    #[error("Invalid file pattern '{pattern}'")]
+    InvalidPattern {
+        pattern: String,
+        #[source]
+        source: globset::Error,
+    },
+
+    /// A `.lyricsignore` file could not be read or has an invalid pattern
+    #[error("Invalid ignore file {}", path.display())]
+    IgnoreFile {
+        path: PathBuf,
+        #[source]
+        source: ignore::Error,
+    },
+
+    /// A `--template` has an unknown placeholder or unbalanced braces
+    #[error("Invalid template '{template}': {reason}")]
+    InvalidTemplate {
+        template: String,
+        reason: String,
+    },
+
+    /// A `--replace` expression isn't a valid substitution
+    #[error("Invalid substitution '{expression}': {reason}")]
+    InvalidSubstitution {
+        expression: String,
+        reason: String,
+    },
+
+    /// A `--frame-id` isn't a well-formed ID3 frame ID
+    #[error("Invalid frame ID '{frame_id}': {reason}")]
+    InvalidFrameId {
+        frame_id: String,
+        reason: String,
+    },
+
+    /// Reading or writing a file failed
+    #[error("{action} {}", path.display())]
+    Io {
+        action: &'static str,
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    /// A file couldn't be read and `--fail-fast` stopped the run
+    #[error("Failed to extract lyrics from {}", path.display())]
+    Extract {
+        path: PathBuf,
+        #[source]
+        source: Box<Error>,
+    },
+
+    /// An ID3 tag could not be parsed
+    #[error("Failed to read ID3 tag from {}", path.display())]
+    Id3 {
+        path: PathBuf,
+        #[source]
+        source: id3::Error,
+    },
+
+    /// An ID3 header declared a tag above the `--max-tag-bytes` limit
+    #[error("ID3 tag in {} is {size} bytes, over the {limit} byte limit", path.display())]
+    TagTooLarge {
+        path: PathBuf,
+        size: u64,
+        limit: u64,
+    },
+
+    /// FLAC metadata could not be parsed
+    #[error("Failed to read FLAC metadata from {}", path.display())]
+    Flac {
+        path: PathBuf,
+        #[source]
+        source: metaflac::Error,
+    },
+
+    /// SRT output was requested for a file without synchronised lyrics
+    #[error("No synchronised (SYLT) lyrics to build subtitles from in {}", .0.display())]
+    NoSyncedLyrics(PathBuf),
+
+    /// A file read through lofty (e.g. M4A) could not be parsed
+    #[error("Failed to read tags from {}", path.display())]
+    Lofty {
+        path: PathBuf,
+        #[source]
+        source: lofty::error::FileParseError,
+    },
+
+    /// A zip archive given as input could not be read
+    #[error("Failed to read zip archive {}", path.display())]
+    Zip {
+        path: PathBuf,
+        #[source]
+        source: zip::result::ZipError,
+    },
+
+    /// A URL given as input could not be downloaded
+    #[error("Failed to download {url}")]
+    Download {
+        url: String,
+        #[source]
+        source: reqwest::Error,
+    },
+
+    /// A `watch` input could not be watched for changes
+    #[error("Failed to watch {} for changes", path.display())]
+    Watch {
+        path: PathBuf,
+        #[source]
+        source: notify::Error,
+    },
+
+    /// A `--playlist` file isn't a JSON array of paths
+    #[error("Invalid playlist {}", path.display())]
+    Playlist {
+        path: PathBuf,
+        #[source]
+        source: serde_json::Error,
+    },
+
+    /// The `--lookup-db` lyrics database could not be opened or queried
+    #[error("Failed to look up lyrics in {}", path.display())]
+    Lookup {
+        path: PathBuf,
+        #[source]
+        source: rusqlite::Error,
+    },
+
+    /// A sidecar file is in the way and overwriting wasn't allowed
+    #[error("{} already exists (use --overwrite to replace it)", .0.display())]
+    AlreadyExists(PathBuf),
+
+    /// A `split --output-dir` target would land outside the output directory
+    #[error("Can't place lyrics for {} inside the output directory", .0.display())]
+    OutsideOutputDir(PathBuf),
+
+    /// A `split --filename-template` gave a name that can't be used for a file
+    #[error("--filename-template names the lyrics for {} {:?}, which isn't a usable file name", .path.display(), .name)]
+    BadFileName { path: PathBuf, name: String },
+
+    /// Results could not be serialized as JSON
+    #[error("Failed to serialize results as JSON")]
+    Json(#[from] serde_json::Error),
+
+    /// Results could not be written as CSV
+    #[error("Failed to write results as CSV")]
+    Csv(#[from] csv::Error),
+
+    /// The worker threads for parallel extraction could not be started
+    #[error("Failed to start extraction worker threads")]
+    ThreadPool(#[from] rayon::ThreadPoolBuildError),
+}
+
+/// Result type used throughout the library
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// Wrap an I/O error with the action that failed and the path involved
+    pub(crate) fn io(action: &'static str, path: impl Into<PathBuf>, source: io::Error) -> Self {
+        Error::Io { action, path: path.into(), source }
+    }
+
+    /// The kind of the I/O error underneath this one, if it was caused by one
+    pub fn io_kind(&self) -> Option<io::ErrorKind> {
+        let mut source = std::error::Error::source(self);
+        while let Some(error) = source {
+            if let Some(io_error) = error.downcast_ref::<io::Error>() {
+                return Some(io_error.kind());
+            }
+            source = error.source();
+        }
+        None
+    }
+
+    /// Whether reading the file again might succeed, e.g. once a music player releases its lock
+    pub fn is_transient(&self) -> bool {
+        matches!(self.io_kind(), Some(io::ErrorKind::PermissionDenied | io::ErrorKind::WouldBlock))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_transient() {
+        let locked = Error::io("Failed to read", "a.mp3", io::Error::from(io::ErrorKind::PermissionDenied));
+        assert_eq!(locked.io_kind(), Some(io::ErrorKind::PermissionDenied));
+        assert!(locked.is_transient());
+
+        let busy = id3::Error::from(io::Error::from(io::ErrorKind::WouldBlock));
+        assert!(Error::Id3 { path: "a.mp3".into(), source: busy }.is_transient());
+
+        let missing = Error::io("Failed to read", "a.mp3", io::Error::from(io::ErrorKind::NotFound));
+        assert!(!missing.is_transient());
+
+        let parse = id3::Error::new(id3::ErrorKind::Parsing, "bad frame");
+        assert_eq!(Error::Id3 { path: "a.mp3".into(), source: parse }.io_kind(), None);
+    }
+}
@@ -0,0 +1,1924 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, LazyLock, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use chardetng::EncodingDetector;
+use clap::ValueEnum;
+use deunicode::deunicode_char;
+use encoding_rs::{Encoding, WINDOWS_1252};
+use log::{debug, error, info, log_enabled, warn, Level};
+use rayon::prelude::*;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use unicode_normalization::UnicodeNormalization;
+use crate::error::{Error, Result};
+use crate::render::{format_lrc, format_srt, OutputFormat};
+use crate::replace::Substitution;
+use crate::source::source_for_path;
+
+/// How long the last cue of timed output stays up, as it has no following entry to end it
+pub const DEFAULT_TAIL_DURATION: Duration = Duration::from_secs(3);
+
+/// How many files are extracted between verbose progress lines
+pub const DEFAULT_LOG_INTERVAL: usize = 100;
+
+/// How many files can be read at once by default, to stay clear of open file limits
+pub const DEFAULT_CONCURRENCY_LIMIT: usize = 64;
+
+/// Largest ID3 tag that will be parsed, per file
+pub const DEFAULT_MAX_TAG_BYTES: u64 = 10 * 1024 * 1024;
+
+/// Wait before the first retry of a locked file, growing by the same amount for each retry after it
+pub const RETRY_BACKOFF: Duration = Duration::from_millis(200);
+
+/// Settings that control how lyrics are picked out of each file
+#[derive(Debug, Clone)]
+pub struct ExtractOptions {
+    /// Output format the lyrics are destined for
+    pub format: OutputFormat,
+    /// Preferred three-letter language code for USLT frames
+    pub language: Option<String>,
+    /// Charset to re-decode lyrics with, overriding detection
+    pub encoding: Option<&'static Encoding>,
+    /// Display time of the last line in SRT output
+    pub tail_duration: Duration,
+    /// Whether to take the unsynchronised or synchronised lyrics when there are both
+    pub prefer: FramePreference,
+    /// Substitutions applied to each track's lyrics in turn
+    pub replacements: Vec<Substitution>,
+    /// Remove `[mm:ss.xx]` timestamps from the start of each line of unsynchronised lyrics
+    pub strip_timestamps: bool,
+    /// Collapse runs of blank lines into one and drop blank lines at the start and end
+    pub flatten_blank_lines: bool,
+    /// Turn lyrics stored as HTML into plain text, with `<br>` and `<p>` as line breaks
+    pub strip_html: bool,
+    /// Normalize each track's lyrics to this Unicode form, right after any byte order mark is removed
+    pub normalize_unicode: Option<UnicodeForm>,
+    /// Replace non-Latin text with a best-effort ASCII spelling, such as "Tokyo" for "東京"
+    pub transliterate: bool,
+    /// Put every track's lyrics in upper or lower case, after all the other clean-ups
+    pub case: Option<TextCase>,
+    /// Read the lyrics only from this ID3 frame instead of searching the usual ones
+    pub frame: Option<FrameSelector>,
+    /// ID3 frames to search for lyrics, best first; kinds left out aren't searched
+    pub source_priority: Vec<LyricsSourceKind>,
+    /// Join the text of every USLT frame, in tag order and with a blank line between, rather than
+    /// taking just one
+    pub merge_frames: bool,
+    /// Fall back to lyric-like text in the ID3v1 comment when there are no ID3v2 lyrics
+    pub id3v1_comments: bool,
+    /// As a last resort, take multi-line text from any ID3 comment or the title as the lyrics
+    pub desperate: bool,
+    /// Refuse to parse a file whose ID3 header declares a bigger tag than this
+    pub max_tag_bytes: u64,
+    /// Read ID3 tags from a memory map of the file, falling back to ordinary reads if mapping fails
+    pub mmap: bool,
+    /// How many more times to try a file that failed with a transient I/O error
+    pub retries: u32,
+    /// Treat lyrics with fewer characters than this as missing, to skip stubs like a lone URL
+    pub min_lyrics_length: usize,
+    /// Cut each track's lyrics down to this many lines, ending with `...` (0 keeps every line)
+    pub first_n_lines: usize,
+    /// Leave the lyrics exactly as stored, and keep the frame's undecoded bytes in
+    /// [`ExtractedTrack::raw_lyrics`]; every other text option is ignored
+    pub raw: bool,
+    /// Log throughput and an ETA in verbose mode every this many files (0 turns it off)
+    pub log_interval: usize,
+    /// Read at most this many files at once, however many threads there are (0 for no limit)
+    pub concurrency_limit: usize,
+    /// Stop at the first file that can't be read instead of recording the error and carrying on
+    pub fail_fast: bool,
+    /// Print a JSON line to stderr as each file finishes, see [`ProgressEvents`]
+    pub progress: Option<Arc<ProgressEvents>>,
+}
+
+impl Default for ExtractOptions {
+    fn default() -> Self {
+        ExtractOptions {
+            format: OutputFormat::default(),
+            language: None,
+            encoding: None,
+            tail_duration: DEFAULT_TAIL_DURATION,
+            prefer: FramePreference::default(),
+            replacements: Vec::new(),
+            strip_timestamps: false,
+            flatten_blank_lines: false,
+            strip_html: false,
+            normalize_unicode: None,
+            transliterate: false,
+            case: None,
+            frame: None,
+            source_priority: DEFAULT_SOURCE_PRIORITY.to_vec(),
+            merge_frames: false,
+            id3v1_comments: false,
+            desperate: false,
+            mmap: false,
+            max_tag_bytes: DEFAULT_MAX_TAG_BYTES,
+            retries: 0,
+            min_lyrics_length: 0,
+            first_n_lines: 0,
+            raw: false,
+            log_interval: DEFAULT_LOG_INTERVAL,
+            concurrency_limit: DEFAULT_CONCURRENCY_LIMIT,
+            fail_fast: false,
+            progress: None,
+        }
+    }
+}
+
+/// A Unicode normalization form for `--normalize-unicode`
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnicodeForm {
+    /// Canonical composition, so "é" is always the single precomposed character
+    Nfc,
+    /// Compatibility composition, which also folds variants such as "ﬁ" and full-width letters
+    Nfkc,
+}
+
+impl UnicodeForm {
+    /// Rewrite `text` in this form
+    pub fn normalize(self, text: &str) -> String {
+        match self {
+            UnicodeForm::Nfc => text.nfc().collect(),
+            UnicodeForm::Nfkc => text.nfkc().collect(),
+        }
+    }
+}
+
+/// A case to put text in, following Unicode's rules rather than any locale's, so "ß" becomes "SS"
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextCase {
+    Upper,
+    Lower,
+}
+
+impl TextCase {
+    /// Rewrite `text` in this case
+    pub fn apply(self, text: &str) -> String {
+        match self {
+            TextCase::Upper => text.to_uppercase(),
+            TextCase::Lower => text.to_lowercase(),
+        }
+    }
+}
+
+/// Which kind of frame wins when a file has both unsynchronised and synchronised lyrics
+///
+/// Only plain text outputs are affected, as LRC and SRT always use the synchronised lyrics.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FramePreference {
+    /// Unsynchronised lyrics (USLT and the like), ignoring SYLT
+    #[default]
+    Uslt,
+    /// The text of the SYLT frame, falling back to the unsynchronised lyrics
+    Sylt,
+    /// Whichever has more characters
+    Longest,
+}
+
+/// A kind of ID3 frame that unsynchronised lyrics are searched for in
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LyricsSourceKind {
+    /// USLT frames
+    Uslt,
+    /// COMM frames with a lyrics-like description
+    Comm,
+    /// TXXX frames with a lyrics-like description
+    Txxx,
+    /// Non-standard LYRICS frames
+    Lyrics,
+    /// Non-standard LYRW frames
+    Lyrw,
+    /// Non-standard UNSYNCEDLYRICS frames
+    Unsyncedlyrics,
+}
+
+/// Order the ID3 frames are searched for lyrics in when no `--source-priority` is given
+pub const DEFAULT_SOURCE_PRIORITY: &[LyricsSourceKind] = &[
+    LyricsSourceKind::Uslt,
+    LyricsSourceKind::Comm,
+    LyricsSourceKind::Txxx,
+    LyricsSourceKind::Lyrics,
+    LyricsSourceKind::Lyrw,
+    LyricsSourceKind::Unsyncedlyrics,
+];
+
+/// A single entry of synchronised lyrics
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct TimedLine {
+    /// Offset from the start of the track in milliseconds
+    pub start_ms: u32,
+    pub text: String,
+}
+
+/// The tag frame or field that a track's lyrics were read from
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LyricsFrame {
+    /// Frame ID or field name, e.g. "USLT" or "UNSYNCEDLYRICS"
+    pub id: String,
+    /// Language code of the frame, for frames that carry one
+    pub language: Option<String>,
+}
+
+impl LyricsFrame {
+    pub(crate) fn new(id: impl Into<String>, language: Option<&str>) -> Self {
+        let language = language.map(str::trim).filter(|lang| !lang.is_empty()).map(str::to_string);
+        LyricsFrame { id: id.into(), language }
+    }
+}
+
+impl fmt::Display for LyricsFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.language {
+            Some(language) => write!(f, "{} ({})", self.id, language),
+            None => f.write_str(&self.id),
+        }
+    }
+}
+
+/// The descriptive fields of the SYLT frame that synchronised lyrics came from
+///
+/// These tell apart frames such as the lyrics themselves and a transliteration of them.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SyncedLyricsInfo {
+    /// What the frame's text is, such as "lyrics" or "transcription"
+    pub content_type: String,
+    /// The frame's content descriptor
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+}
+
+impl fmt::Display for SyncedLyricsInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.description {
+            Some(description) => write!(f, "{} \"{}\"", self.content_type, description),
+            None => f.write_str(&self.content_type),
+        }
+    }
+}
+
+/// A single ID3 frame to read the lyrics from, such as `USLT`, `COMM:LYRICS` or `TXXX:CUSTOM`
+///
+/// The part after the colon picks frames by their description, ignoring case.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameSelector {
+    pub id: String,
+    pub description: Option<String>,
+}
+
+impl FrameSelector {
+    /// Frames whose description can be given
+    const DESCRIBED_FRAMES: &'static [&'static str] = &["USLT", "COMM", "TXXX"];
+
+    /// Parse a frame ID with an optional `:description`, rejecting malformed IDs up front
+    pub fn parse(selector: &str) -> Result<Self> {
+        let invalid = |reason: &str| Error::InvalidFrameId { frame_id: selector.to_string(), reason: reason.to_string() };
+        let (id, description) = match selector.split_once(':') {
+            Some((id, description)) => (id, Some(description)),
+            None => (selector, None),
+        };
+        let well_formed = matches!(id.len(), 3 | 4)
+            && id.starts_with(|c: char| c.is_ascii_uppercase())
+            && id.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit());
+        if !well_formed {
+            return Err(invalid("expected three or four capital letters and digits, e.g. USLT"));
+        }
+        if description.is_some() && !Self::DESCRIBED_FRAMES.contains(&id) {
+            return Err(invalid("only USLT, COMM and TXXX frames have a description"));
+        }
+        Ok(FrameSelector { id: id.to_string(), description: description.map(str::to_string) })
+    }
+
+    /// Whether a frame's description matches, when one was asked for
+    pub(crate) fn matches_description(&self, description: &str) -> bool {
+        self.description.as_deref().is_none_or(|wanted| description.trim().eq_ignore_ascii_case(wanted.trim()))
+    }
+}
+
+/// One of the frames in a file that could hold its lyrics
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LyricsCandidate {
+    pub frame: LyricsFrame,
+    /// Content descriptor of the frame, which often names the version, e.g. "Romanized"
+    pub description: String,
+    pub text: String,
+}
+
+/// Lyrics and descriptive metadata read from a single file
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ExtractedTrack {
+    /// Lyrics text in the requested output format
+    pub lyrics: Option<String>,
+    /// Where `lyrics` came from
+    pub lyrics_frame: Option<LyricsFrame>,
+    /// Every frame that could hold the lyrics, including the one they were taken from
+    pub candidates: Vec<LyricsCandidate>,
+    /// Whether the file has a tag at all, as opposed to one without lyrics
+    pub has_tag: bool,
+    /// Whether the track is marked as having no lyrics to find, by `INSTRUMENTAL=1` in a TXXX frame or
+    /// Vorbis comment, or by a lyrics frame that is blank or just says `[instrumental]`
+    pub instrumental: bool,
+    /// Timed entries from a SYLT frame, if the file has one
+    pub synced: Option<Vec<TimedLine>>,
+    /// The content type, descriptor and language of that SYLT frame
+    pub synced_info: Option<SyncedLyricsInfo>,
+    /// The lyrics as the bytes stored in the tag, with [`ExtractOptions::raw`]
+    ///
+    /// These keep the frame's own encoding, so they may not be valid UTF-8.
+    pub raw_lyrics: Option<Vec<u8>>,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub track_number: Option<u32>,
+}
+
+/// The outcome of extracting lyrics from a single file
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub struct TrackResult {
+    pub path: PathBuf,
+    pub lyrics: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub artist: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub album: Option<String>,
+    /// The track number tag, for templates' `{track}`
+    #[serde(skip)]
+    pub track_number: Option<u32>,
+    /// Why extraction failed, if it did
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// See [`ExtractedTrack::instrumental`]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub instrumental: bool,
+    /// The frame the lyrics were read from
+    #[serde(skip)]
+    pub lyrics_frame: Option<LyricsFrame>,
+    /// The file's SYLT frame, if it has one, whether or not the lyrics were taken from it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub synced_lyrics: Option<SyncedLyricsInfo>,
+    /// Other frames the lyrics could have been taken from, see [`ExtractedTrack::candidates`]
+    #[serde(skip)]
+    pub candidates: Vec<LyricsCandidate>,
+    /// The lyrics as stored, see [`ExtractedTrack::raw_lyrics`]
+    #[serde(skip)]
+    pub raw_lyrics: Option<Vec<u8>>,
+    /// Where the file was actually read from, when `path` is only its name inside an archive
+    #[serde(skip)]
+    pub local_path: Option<PathBuf>,
+}
+
+/// A single frame (or Vorbis comment field) from a file's tags
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameInfo {
+    /// Frame ID or field name, such as `USLT` or `LYRICS`
+    pub id: String,
+    pub content: String,
+}
+
+impl FrameInfo {
+    /// The content on a single line, cut down to at most `max_chars` characters
+    pub fn preview(&self, max_chars: usize) -> String {
+        preview(&self.content, max_chars)
+    }
+}
+
+impl LyricsCandidate {
+    /// The lyrics on a single line, cut down to at most `max_chars` characters
+    pub fn preview(&self, max_chars: usize) -> String {
+        preview(&self.text, max_chars)
+    }
+}
+
+fn preview(text: &str, max_chars: usize) -> String {
+    let flattened = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if flattened.chars().count() <= max_chars {
+        return flattened;
+    }
+    let mut preview: String = flattened.chars().take(max_chars.saturating_sub(3)).collect();
+    preview.push_str("...");
+    preview
+}
+
+/// Per-run counts of how extraction went
+#[derive(Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ExtractionSummary {
+    pub files: usize,
+    pub with_lyrics: usize,
+    pub without_lyrics: usize,
+    pub errors: usize,
+}
+
+impl ExtractionSummary {
+    /// Tally up a set of extraction results
+    pub fn from_results(results: &[TrackResult]) -> Self {
+        let mut summary = ExtractionSummary { files: results.len(), ..Default::default() };
+        for result in results {
+            match (&result.lyrics, &result.error) {
+                (Some(_), _) => summary.with_lyrics += 1,
+                (None, None) => summary.without_lyrics += 1,
+                (None, Some(_)) => summary.errors += 1,
+            }
+        }
+        summary
+    }
+
+    /// The counts one per line, for the `count` subcommand
+    pub fn render_text(&self) -> String {
+        format!(
+            "Files:          {}\nWith lyrics:    {}\nWithout lyrics: {}\nErrors:         {}\n",
+            self.files, self.with_lyrics, self.without_lyrics, self.errors
+        )
+    }
+
+    /// The counts as a pretty-printed JSON object
+    pub fn render_json(&self) -> Result<String> {
+        let mut json = serde_json::to_string_pretty(self)?;
+        json.push('\n');
+        Ok(json)
+    }
+}
+
+/// Extract lyrics, title, artist and album from a single audio file
+///
+/// LRC output prefers the synchronised lyrics when there are any, and SRT output requires them.
+/// Other outputs take them according to [`ExtractOptions::prefer`].
+pub fn extract_lyrics_from_file(file_path: &Path, options: &ExtractOptions) -> Result<ExtractedTrack> {
+    let mut track = read_track_with_retries(file_path, options)?;
+    if options.raw {
+        // Sources that only store UTF-8 text, such as Vorbis comments, have nothing more exact to give
+        if track.raw_lyrics.is_none() {
+            track.raw_lyrics = track.lyrics.clone().map(String::into_bytes);
+        }
+        return Ok(track);
+    }
+    let clean = |lyrics: &str| clean_lyrics(lyrics, options, file_path);
+    track.lyrics = track.lyrics.as_deref().map(clean);
+    for candidate in &mut track.candidates {
+        candidate.text = clean(&candidate.text);
+    }
+    if let Some(synced) = &mut track.synced {
+        for line in synced.iter_mut() {
+            line.text = fix_encoding(&line.text, options.encoding, file_path);
+            if let Some(form) = options.normalize_unicode {
+                line.text = form.normalize(&line.text);
+            }
+            if options.transliterate {
+                line.text = transliterate(&line.text);
+            }
+            if let Some(case) = options.case {
+                line.text = case.apply(&line.text);
+            }
+        }
+    }
+
+    let sylt_frame = LyricsFrame::new("SYLT", track.synced_info.as_ref().and_then(|info| info.language.as_deref()));
+    match (options.format, &track.synced) {
+        (OutputFormat::Lrc, Some(synced)) => {
+            track.lyrics = Some(format_lrc(synced, options.tail_duration));
+            track.lyrics_frame = Some(sylt_frame.clone());
+            track.candidates.clear();
+        }
+        (OutputFormat::Srt, Some(synced)) => {
+            track.lyrics = Some(format_srt(synced, options.tail_duration));
+            track.lyrics_frame = Some(sylt_frame.clone());
+            track.candidates.clear();
+        }
+        (OutputFormat::Srt, None) => return Err(Error::NoSyncedLyrics(file_path.to_path_buf())),
+        (_, Some(synced)) => {
+            let synced_text = clean(&synced.iter().map(|line| line.text.as_str()).collect::<Vec<_>>().join("\n"));
+            let use_synced = match (options.prefer, &track.lyrics) {
+                (FramePreference::Uslt, _) => false,
+                (_, None) => !synced_text.trim().is_empty(),
+                (FramePreference::Sylt, Some(_)) => true,
+                (FramePreference::Longest, Some(lyrics)) => synced_text.chars().count() > lyrics.chars().count(),
+            };
+            if use_synced {
+                debug!("Using the SYLT frame for {}", file_path.display());
+                track.lyrics = Some(synced_text);
+                track.lyrics_frame = Some(sylt_frame);
+            } else if let Some(frame) = &track.lyrics_frame {
+                debug!("Using the {} frame for {}", frame, file_path.display());
+            }
+        }
+        _ => {}
+    }
+    // The lyrics are kept as they are; only output with a marker for instrumentals replaces them.
+    // Lyrics found elsewhere, such as in a SYLT frame, outweigh a TXXX mark.
+    track.instrumental = match &track.lyrics {
+        Some(lyrics) => is_instrumental_text(lyrics),
+        None => track.instrumental,
+    };
+    if track.instrumental {
+        debug!("Treating {} as instrumental", file_path.display());
+    }
+    
+    if let Some(lyrics) = &track.lyrics {
+        let length = lyrics.trim().chars().count();
+        if length < options.min_lyrics_length {
+            debug!("Ignoring {} character lyrics in {} as too short", length, file_path.display());
+            track.lyrics = None;
+            track.lyrics_frame = None;
+        }
+    }
+    // Cut down only after the length check, so a short preview doesn't count as a stub
+    if options.first_n_lines > 0 {
+        track.lyrics = track.lyrics.map(|lyrics| first_lines(&lyrics, options.first_n_lines));
+        for candidate in &mut track.candidates {
+            candidate.text = first_lines(&candidate.text, options.first_n_lines);
+        }
+    }
+    Ok(track)
+}
+
+/// Decode and clean up one piece of lyrics text from `file_path` as `options` ask, from fixing its
+/// charset through to putting it in one case
+fn clean_lyrics(lyrics: &str, options: &ExtractOptions, file_path: &Path) -> String {
+    let mut lyrics = fix_encoding(lyrics, options.encoding, file_path);
+    // Some Windows taggers start the text with a byte order mark
+    if let Some(stripped) = lyrics.strip_prefix('\u{FEFF}') {
+        lyrics = stripped.to_string();
+    }
+    if options.strip_html {
+        lyrics = strip_html(&lyrics);
+    }
+    // Before the substitutions, so their patterns see one spelling of each character
+    if let Some(form) = options.normalize_unicode {
+        lyrics = form.normalize(&lyrics);
+    }
+    for replacement in &options.replacements {
+        lyrics = replacement.apply(&lyrics).into_owned();
+    }
+    if options.strip_timestamps {
+        lyrics = strip_timestamps(&lyrics);
+    }
+    if options.flatten_blank_lines {
+        lyrics = flatten_blank_lines(&lyrics);
+    }
+    if options.transliterate {
+        lyrics = transliterate(&lyrics);
+    }
+    if let Some(case) = options.case {
+        lyrics = case.apply(&lyrics);
+    }
+    lyrics
+}
+
+/// Clean up lyrics found outside a file's tags, such as in a `.txt` file next to it, the same way
+/// as lyrics read from the tags
+///
+/// Gives `None` for lyrics shorter than [`ExtractOptions::min_lyrics_length`], and cuts them down
+/// to [`ExtractOptions::first_n_lines`].
+pub(crate) fn clean_external_lyrics(lyrics: &str, options: &ExtractOptions, file_path: &Path) -> Option<String> {
+    let lyrics = clean_lyrics(lyrics, options, file_path);
+    let length = lyrics.trim().chars().count();
+    if length < options.min_lyrics_length {
+        debug!("Ignoring {} character lyrics for {} as too short", length, file_path.display());
+        return None;
+    }
+    Some(if options.first_n_lines > 0 { first_lines(&lyrics, options.first_n_lines) } else { lyrics })
+}
+
+/// Whether lyrics are a stand-in for an instrumental track rather than words: blank, or `[instrumental]`
+pub(crate) fn is_instrumental_text(lyrics: &str) -> bool {
+    let lyrics = lyrics.trim();
+    lyrics.is_empty() || lyrics.eq_ignore_ascii_case("[instrumental]")
+}
+
+/// List every frame in an audio file's tags, whether or not it holds lyrics
+///
+/// An ID3 tag over `max_tag_bytes` is an error, as for [`ExtractOptions::max_tag_bytes`].
+pub fn dump_frames(file_path: &Path, max_tag_bytes: u64) -> Result<Vec<FrameInfo>> {
+    source_for_path(file_path).read_frames(file_path, max_tag_bytes)
+}
+
+/// Extract lyrics from all audio files, using `jobs` worker threads
+///
+/// Failures are recorded per track rather than returned, so one bad file doesn't stop the run,
+/// unless [`ExtractOptions::fail_fast`] is set. A `jobs` value of 0 uses one thread per CPU.
+pub fn extract_all_lyrics(audio_files: &[PathBuf], options: &ExtractOptions, jobs: usize) -> Result<Vec<TrackResult>> {
+    let progress = (options.log_interval > 0 && log_enabled!(Level::Debug))
+        .then(|| Progress::new(audio_files.len(), options.log_interval));
+    let open_files = (options.concurrency_limit > 0).then(|| Semaphore::new(options.concurrency_limit));
+    let timings = log_enabled!(Level::Debug).then(|| Mutex::new(Vec::with_capacity(audio_files.len())));
+    let extract = |file_path: &PathBuf| {
+        let permit = open_files.as_ref().map(Semaphore::acquire);
+        let started = Instant::now();
+        let result = extract_track(file_path, options);
+        let elapsed = started.elapsed();
+        drop(permit);
+        if let Some(timings) = &timings {
+            debug!("Read {} in {} ms", file_path.display(), elapsed.as_millis());
+            timings.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).push((elapsed, file_path.clone()));
+        }
+        if let Some(progress) = &progress {
+            progress.file_done();
+        }
+        if let Some(events) = &options.progress {
+            events.file_done(file_path, result.as_ref().map_or(FileStatus::Error, FileStatus::of));
+        }
+        result
+    };
+
+    let results: Result<Vec<TrackResult>> = if jobs == 1 {
+        audio_files.iter().map(extract).collect()
+    } else {
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(jobs).build()?;
+
+        // An indexed parallel collect keeps results in input order
+        pool.install(|| {
+            audio_files
+                .par_iter()
+                .map(extract)
+                .collect()
+        })
+    };
+
+    if let Some(timings) = timings {
+        let timings = timings.into_inner().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if timings.len() > 1 {
+            debug!("Slowest files:");
+            for (elapsed, file_path) in slowest_files(timings, SLOWEST_FILES_LOGGED) {
+                debug!("  {} ms  {}", elapsed.as_millis(), file_path.display());
+            }
+        }
+    }
+    results
+}
+
+/// How many of the slowest files to list at the end of a verbose run
+const SLOWEST_FILES_LOGGED: usize = 5;
+
+/// The `count` files that took longest to read, slowest first
+fn slowest_files(mut timings: Vec<(Duration, PathBuf)>, count: usize) -> Vec<(Duration, PathBuf)> {
+    timings.sort_by(|(a, _), (b, _)| b.cmp(a));
+    timings.truncate(count);
+    timings
+}
+
+/// Limits how many files are open at once, independently of the number of worker threads
+struct Semaphore {
+    available: Mutex<usize>,
+    released: Condvar,
+}
+
+/// A slot taken from a [`Semaphore`], given back when dropped
+struct Permit<'a>(&'a Semaphore);
+
+impl Semaphore {
+    fn new(permits: usize) -> Self {
+        Semaphore { available: Mutex::new(permits), released: Condvar::new() }
+    }
+
+    /// Wait for a free slot and take it
+    fn acquire(&self) -> Permit<'_> {
+        let mut available = self.available.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        while *available == 0 {
+            available = self.released.wait(available).unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+        *available -= 1;
+        Permit(self)
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        *self.0.available.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) += 1;
+        self.0.released.notify_one();
+    }
+}
+
+/// Tracks how quickly files get through extraction, for the verbose progress lines
+struct Progress {
+    total: usize,
+    interval: usize,
+    state: Mutex<ProgressState>,
+}
+
+struct ProgressState {
+    done: usize,
+    /// When each of the last `interval` files finished, preceded by the start of that window
+    finished: VecDeque<Instant>,
+}
+
+impl Progress {
+    fn new(total: usize, interval: usize) -> Self {
+        let state = ProgressState { done: 0, finished: VecDeque::from([Instant::now()]) };
+        Progress { total, interval, state: Mutex::new(state) }
+    }
+
+    /// Count a finished file, logging the progress if it ends an interval
+    fn file_done(&self) {
+        let mut state = self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        state.done += 1;
+        state.finished.push_back(Instant::now());
+        if state.finished.len() > self.interval + 1 {
+            state.finished.pop_front();
+        }
+        if state.done.is_multiple_of(self.interval) {
+            let window = state.finished.len() - 1;
+            let elapsed = state.finished[window].duration_since(state.finished[0]);
+            debug!("{}", progress_message(state.done, self.total, window, elapsed));
+        }
+    }
+}
+
+/// Describe how far through the files extraction is, with the rate averaged over the last
+/// `window` files taking `elapsed`
+fn progress_message(done: usize, total: usize, window: usize, elapsed: Duration) -> String {
+    let rate = window as f64 / elapsed.as_secs_f64().max(f64::EPSILON);
+    let eta_secs = ((total - done) as f64 / rate).round() as u64;
+    format!("Processed {}/{} files ({:.1} files/sec, about {}s left)", done, total, rate, eta_secs)
+}
+
+/// How a file came out of extraction, for `--progress-json`
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FileStatus {
+    /// Lyrics were found
+    Ok,
+    /// The file was read but has no lyrics
+    Empty,
+    /// The file couldn't be read
+    Error,
+}
+
+impl FileStatus {
+    /// How the file behind `result` came out
+    pub fn of(result: &TrackResult) -> Self {
+        if result.error.is_some() {
+            FileStatus::Error
+        } else if result.lyrics.is_none() {
+            FileStatus::Empty
+        } else {
+            FileStatus::Ok
+        }
+    }
+}
+
+/// The JSON line reporting that the `index`th of `total` files has finished, counting from 1 in
+/// the order they finish
+pub fn progress_event(path: &Path, status: FileStatus, index: usize, total: usize) -> String {
+    #[derive(Serialize)]
+    struct Event<'a> {
+        event: &'static str,
+        path: Cow<'a, str>,
+        status: FileStatus,
+        index: usize,
+        total: usize,
+    }
+    serde_json::to_string(&Event { event: "file", path: path.to_string_lossy(), status, index, total }).expect("progress events always serialize")
+}
+
+/// Numbers files as they finish and prints a [`progress_event`] line to stderr for each
+///
+/// One is shared by everything that finishes files in a run, such as the cache as well as
+/// [`extract_all_lyrics`], so the count runs over all of them.
+#[derive(Debug)]
+pub struct ProgressEvents {
+    total: usize,
+    /// Names to report files under, such as for ones unpacked from an archive into a temporary directory
+    names: HashMap<PathBuf, PathBuf>,
+    done: Mutex<usize>,
+}
+
+impl ProgressEvents {
+    /// Count up to `total` files, reporting any in `names` under the name they map to
+    pub fn new(total: usize, names: HashMap<PathBuf, PathBuf>) -> Self {
+        ProgressEvents { total, names, done: Mutex::new(0) }
+    }
+
+    /// Report that `path` has finished
+    pub fn file_done(&self, path: &Path, status: FileStatus) {
+        let name = self.names.get(path).map_or(path, PathBuf::as_path);
+        // Print while holding the count, so the lines come out in index order
+        let mut done = self.done.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        *done += 1;
+        let _ = writeln!(std::io::stderr().lock(), "{}", progress_event(name, status, *done, self.total));
+    }
+
+    /// How many files have been reported so far
+    pub fn files_done(&self) -> usize {
+        *self.done.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Read a file's tags, trying again after a backoff while it fails with a transient I/O error
+///
+/// Anything else, such as a tag that doesn't parse, fails straight away.
+fn read_track_with_retries(file_path: &Path, options: &ExtractOptions) -> Result<ExtractedTrack> {
+    let source = source_for_path(file_path);
+    let mut attempt = 0;
+    loop {
+        match source.read_track(file_path, options) {
+            Err(e) if e.is_transient() && attempt < options.retries => {
+                attempt += 1;
+                let delay = RETRY_BACKOFF * attempt;
+                warn!("{}, retrying in {:?} ({}/{})", e, delay, attempt, options.retries);
+                thread::sleep(delay);
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Extract lyrics from one file for [`extract_all_lyrics`], logging the outcome against its path
+///
+/// This only fails with [`ExtractOptions::fail_fast`]; otherwise the error is kept in the result.
+fn extract_track(file_path: &Path, options: &ExtractOptions) -> Result<TrackResult> {
+    let (track, error) = match extract_lyrics_from_file(file_path, options) {
+        Ok(track) => {
+            if track.lyrics.is_some() {
+                info!("Extracted lyrics from {}", file_path.display());
+            } else {
+                warn!("No lyrics found in {}", file_path.display());
+            }
+            (track, None)
+        }
+        Err(e) if options.fail_fast => {
+            return Err(Error::Extract { path: file_path.to_path_buf(), source: Box::new(e) });
+        }
+        Err(e) => {
+            error!("Failed to extract lyrics from {}: {}", file_path.display(), e);
+            (ExtractedTrack::default(), Some(e.to_string()))
+        }
+    };
+    Ok(TrackResult {
+        path: file_path.to_path_buf(),
+        lyrics: track.lyrics,
+        title: track.title,
+        artist: track.artist,
+        album: track.album,
+        track_number: track.track_number,
+        error,
+        instrumental: track.instrumental,
+        lyrics_frame: track.lyrics_frame,
+        synced_lyrics: track.synced_info,
+        candidates: track.candidates,
+        raw_lyrics: track.raw_lyrics,
+        local_path: None,
+    })
+}
+
+/// Spell every non-ASCII character with its closest ASCII letters, keeping line breaks and other
+/// ASCII as is
+fn transliterate(text: &str) -> String {
+    let mut ascii = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c.is_ascii() {
+            ascii.push(c);
+        } else {
+            ascii.push_str(deunicode_char(c).unwrap_or_default());
+        }
+    }
+    ascii
+}
+
+/// Re-decode lyrics that were stored in a legacy charset but read back as Latin-1
+///
+/// Tags often mark text as Latin-1 when the bytes are really Windows-1251, Shift-JIS or UTF-8,
+/// which shows up as mojibake. Such text only contains code points up to U+00FF, so the original
+/// bytes can be recovered and decoded again with the detected (or `forced`) charset.
+pub(crate) fn fix_encoding(text: &str, forced: Option<&'static Encoding>, file_path: &Path) -> String {
+    if text.is_ascii() {
+        return text.to_string();
+    }
+    let bytes: Option<Vec<u8>> = text.chars().map(|c| u8::try_from(u32::from(c)).ok()).collect();
+    let Some(bytes) = bytes else {
+        debug!("Lyrics in {} are already Unicode, leaving encoding as is", file_path.display());
+        return text.to_string();
+    };
+
+    let encoding = match forced {
+        Some(encoding) => encoding,
+        None => {
+            let mut detector = EncodingDetector::new();
+            detector.feed(&bytes, true);
+            let (encoding, confident) = detector.guess_assess(None, true);
+            debug!("Detected {} lyrics encoding in {}", encoding.name(), file_path.display());
+            if !confident || encoding == WINDOWS_1252 {
+                return text.to_string();
+            }
+            encoding
+        }
+    };
+
+    let (decoded, _, had_errors) = encoding.decode(&bytes);
+    if had_errors {
+        warn!("Lyrics in {} are not valid {}, keeping original text", file_path.display(), encoding.name());
+        return text.to_string();
+    }
+    decoded.into_owned()
+}
+
+/// One or more LRC style timestamps at the start of a line, like `[01:23]` or `[01:23.45]`
+static LEADING_TIMESTAMPS: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?m)^(?:[ \t]*\[\d{1,3}:\d{2}(?:[.:]\d{1,3})?\])+[ \t]*").unwrap());
+
+/// Remove timestamps left in unsynchronised lyrics, keeping tags such as `[Chorus]`
+pub(crate) fn strip_timestamps(lyrics: &str) -> String {
+    LEADING_TIMESTAMPS.replace_all(lyrics, "").into_owned()
+}
+
+static LINE_BREAK_TAG: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?i)<br\s*/?>[ \t]*(?:\r?\n)?").unwrap());
+static PARAGRAPH_END_TAG: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?i)</p\s*>[ \t]*(?:\r?\n)?").unwrap());
+static HTML_TAG: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"</?[A-Za-z][^>]*>").unwrap());
+static HTML_ENTITY: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"&(#[0-9]+|#[xX][0-9a-fA-F]+|[A-Za-z]+);").unwrap());
+
+/// Convert lyrics stored as HTML to plain text
+///
+/// `<br>` ends a line and `</p>` a stanza, other tags are dropped, and entities such as `&amp;`
+/// and `&#233;` are decoded. Unknown entities are left as they are.
+pub(crate) fn strip_html(lyrics: &str) -> String {
+    let text = LINE_BREAK_TAG.replace_all(lyrics, "\n");
+    let text = PARAGRAPH_END_TAG.replace_all(&text, "\n\n");
+    let text = HTML_TAG.replace_all(&text, "");
+    let text = HTML_ENTITY.replace_all(&text, |caps: &regex::Captures| match decode_entity(&caps[1]) {
+        Some(c) => c.to_string(),
+        None => caps[0].to_string(),
+    });
+    text.trim_matches('\n').to_string()
+}
+
+/// The character an HTML entity's name (without `&` and `;`) stands for
+fn decode_entity(entity: &str) -> Option<char> {
+    let code = match entity.strip_prefix('#') {
+        Some(hex) if hex.starts_with(['x', 'X']) => u32::from_str_radix(&hex[1..], 16).ok()?,
+        Some(decimal) => decimal.parse().ok()?,
+        None => {
+            return match entity {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some('\u{A0}'),
+                "hellip" => Some('…'),
+                "rsquo" => Some('’'),
+                "lsquo" => Some('‘'),
+                "rdquo" => Some('”'),
+                "ldquo" => Some('“'),
+                "mdash" => Some('—'),
+                "ndash" => Some('–'),
+                _ => None,
+            }
+        }
+    };
+    char::from_u32(code)
+}
+
+/// Collapse each run of blank lines to a single one, and remove blank lines at either end
+///
+/// Lines holding only whitespace count as blank.
+pub(crate) fn flatten_blank_lines(lyrics: &str) -> String {
+    let mut flattened: Vec<&str> = Vec::new();
+    for line in lyrics.lines() {
+        let blank = line.trim().is_empty();
+        if blank && flattened.last().is_none_or(|last| last.is_empty()) {
+            continue;
+        }
+        flattened.push(if blank { "" } else { line });
+    }
+    if flattened.last() == Some(&"") {
+        flattened.pop();
+    }
+    flattened.join("\n")
+}
+
+/// Keep the first `count` lines of `lyrics`, adding a `...` line if any were cut
+///
+/// The kept lines keep their own line endings, and blank lines at the end don't count as cut.
+pub(crate) fn first_lines(lyrics: &str, count: usize) -> String {
+    let mut lines = lyrics.split_inclusive('\n');
+    let kept: String = lines.by_ref().take(count).collect();
+    if lines.all(|line| line.trim().is_empty()) {
+        return lyrics.to_string();
+    }
+    kept + "..."
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::{render_text, TextOptions};
+    use crate::test_util::{
+        create_corrupt_mp3, create_id3v1_mp3, create_test_flac, create_test_m4a, create_test_mp3, create_test_ogg,
+        create_test_wav,
+    };
+    use id3::frame::{Comment, ExtendedText, Lyrics, SynchronisedLyrics, TimestampFormat};
+    use id3::{Tag, TagLike};
+    use std::fs::{self, File};
+    use std::io::Write;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_extract_lyrics_from_file() {
+        let temp_dir = tempdir().unwrap();
+        let test_lyrics = "This is a test lyric\nSecond line";
+        let mp3_path = create_test_mp3(temp_dir.path(), "test.mp3", Some(test_lyrics));
+        
+        let lyrics = extract_lyrics_from_file(&mp3_path, &ExtractOptions::default()).unwrap().lyrics;
+        
+        assert!(lyrics.is_some());
+        assert_eq!(lyrics.unwrap(), test_lyrics);
+    }
+
+    #[test]
+    fn test_extract_lyrics_no_lyrics() {
+        let temp_dir = tempdir().unwrap();
+        
+        // For this test, we'll create a more valid MP3-like file
+        let file_path = temp_dir.path().join("test.mp3");
+        let mut file = File::create(&file_path).unwrap();
+        
+        // ID3v2 header (10 bytes)
+        let id3_header = [
+            b'I', b'D', b'3',  // ID3 marker
+            0x04, 0x00,         // Version 2.4.0
+            0x00,               // Flags
+            0x00, 0x00, 0x00, 0x0A  // Size (10 bytes following the header)
+        ];
+        
+        // Write headers and some minimal audio-like data
+        file.write_all(&id3_header).unwrap();
+        // Add 10 bytes of empty padding to match the size in the header
+        file.write_all(&[0; 10]).unwrap();
+        // Add some MP3-like frame data
+        file.write_all(&[0xFF, 0xFB, 0x90, 0x44, 0x00]).unwrap();
+        file.flush().unwrap();
+        
+        // Now try to extract lyrics (should be None because we didn't add any)
+        let lyrics = extract_lyrics_from_file(&file_path, &ExtractOptions::default()).unwrap().lyrics;
+        
+        assert!(lyrics.is_none());
+    }
+
+    #[test]
+    fn test_extract_lyrics_no_tag() {
+        let temp_dir = tempdir().unwrap();
+        
+        // Bare MPEG frame data without any ID3 tag
+        let file_path = temp_dir.path().join("untagged.mp3");
+        File::create(&file_path).unwrap().write_all(&[0xFF, 0xFB, 0x90, 0x44, 0x00]).unwrap();
+        
+        let track = extract_lyrics_from_file(&file_path, &ExtractOptions::default()).unwrap();
+        assert_eq!(track.lyrics, None);
+        
+        // It's reported as a file without lyrics, not as a failure
+        let results = extract_all_lyrics(&[file_path], &ExtractOptions::default(), 1).unwrap();
+        assert_eq!(results[0].error, None);
+    }
+
+    #[test]
+    fn test_extract_lyrics_max_tag_bytes() {
+        let temp_dir = tempdir().unwrap();
+        let mp3_path = create_test_mp3(temp_dir.path(), "test.mp3", Some("Lyrics"));
+        
+        // A header claiming a ~256 MB tag, with nothing behind it
+        let huge = temp_dir.path().join("huge.mp3");
+        File::create(&huge).unwrap().write_all(&[b'I', b'D', b'3', 0x04, 0x00, 0x00, 0x7F, 0x7F, 0x7F, 0x7F]).unwrap();
+        
+        let options = ExtractOptions::default();
+        assert!(extract_lyrics_from_file(&mp3_path, &options).is_ok());
+        assert!(matches!(
+            extract_lyrics_from_file(&huge, &options),
+            Err(Error::TagTooLarge { size, .. }) if size == 0x0FFF_FFFF + 10
+        ));
+        // Reading only the metadata or the frame list is guarded too
+        assert!(matches!(dump_frames(&huge, DEFAULT_MAX_TAG_BYTES), Err(Error::TagTooLarge { .. })));
+        assert!(matches!(source_for_path(&huge).read_metadata(&huge, DEFAULT_MAX_TAG_BYTES), Err(Error::TagTooLarge { .. })));
+        
+        let tiny_limit = ExtractOptions { max_tag_bytes: 16, ..Default::default() };
+        assert!(matches!(extract_lyrics_from_file(&mp3_path, &tiny_limit), Err(Error::TagTooLarge { .. })));
+    }
+
+    #[test]
+    fn test_extract_lyrics_not_an_mp3() {
+        let temp_dir = tempdir().unwrap();
+        let html = temp_dir.path().join("404.mp3");
+        fs::write(&html, "<html><body>Not Found</body></html>").unwrap();
+        let jpeg = temp_dir.path().join("cover.mp3");
+        fs::write(&jpeg, [0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x10, b'J', b'F', b'I', b'F']).unwrap();
+        let bare_frames = create_id3v1_mp3(temp_dir.path(), "frames.mp3", "Song", "Starts with a frame sync");
+
+        for path in [&html, &jpeg] {
+            let track = extract_lyrics_from_file(path, &ExtractOptions::default()).unwrap();
+            assert!(track.lyrics.is_none() && !track.has_tag, "{}", path.display());
+        }
+        // No ID3v2 header, but the frame sync is enough to read it
+        let options = ExtractOptions { id3v1_comments: true, ..Default::default() };
+        let track = extract_lyrics_from_file(&bare_frames, &options).unwrap();
+        assert_eq!(track.lyrics.as_deref(), Some("Starts with a frame sync"));
+    }
+
+    #[test]
+    fn test_extract_lyrics_mmap() {
+        let temp_dir = tempdir().unwrap();
+        let mp3_path = create_test_mp3(temp_dir.path(), "test.mp3", Some("Mapped lyrics"));
+        let untagged = temp_dir.path().join("untagged.mp3");
+        File::create(&untagged).unwrap();
+        let corrupt = create_corrupt_mp3(temp_dir.path(), "corrupt.mp3");
+
+        let options = ExtractOptions { mmap: true, ..Default::default() };
+        assert_eq!(extract_lyrics_from_file(&mp3_path, &options).unwrap().lyrics.as_deref(), Some("Mapped lyrics"));
+        assert!(!extract_lyrics_from_file(&untagged, &options).unwrap().has_tag);
+        assert!(extract_lyrics_from_file(&corrupt, &options).is_err());
+    }
+
+    #[test]
+    fn test_extract_lyrics_raw() {
+        // An ID3v2.3 tag written by hand, with timestamped Latin-1 lyrics in the second USLT frame
+        let frame = |id: &[u8], body: &[u8]| [id, &(body.len() as u32).to_be_bytes(), &[0, 0], body].concat();
+        let frames = [
+            frame(b"USLT", b"\0deuBeschreibung\0Andere"),
+            frame(b"USLT", b"\0eng\0[00:01.00]Caf\xE9\r\nline"),
+        ]
+        .concat();
+        let size = frames.len();
+        let synchsafe = [size >> 21, size >> 14, size >> 7, size].map(|bits| (bits & 0x7F) as u8);
+        let temp_dir = tempdir().unwrap();
+        let mp3_path = temp_dir.path().join("raw.mp3");
+        fs::write(&mp3_path, [&b"ID3\x03\0\0"[..], &synchsafe, &frames, &[0xFF, 0xFB, 0x90, 0x00]].concat()).unwrap();
+
+        let language = Some("eng".to_string());
+        let cleaned = ExtractOptions { language, strip_timestamps: true, ..Default::default() };
+        let options = ExtractOptions { raw: true, ..cleaned.clone() };
+        let track = extract_lyrics_from_file(&mp3_path, &options).unwrap();
+        assert_eq!(track.raw_lyrics.as_deref(), Some(&b"[00:01.00]Caf\xE9\r\nline"[..]));
+        assert_eq!(track.lyrics.as_deref(), Some("[00:01.00]Café\r\nline"));
+        let cleaned = extract_lyrics_from_file(&mp3_path, &cleaned).unwrap();
+        assert_eq!(cleaned.lyrics.as_deref(), Some("Café\r\nline"));
+        assert!(cleaned.raw_lyrics.is_none());
+        let tagged = create_test_mp3(temp_dir.path(), "tagged.mp3", Some("Written by id3"));
+        let track = extract_lyrics_from_file(&tagged, &options).unwrap();
+        assert_eq!(track.raw_lyrics.as_deref(), Some(&b"Written by id3"[..]));
+
+        // Sources without an encoding of their own fall back to the text's UTF-8 bytes
+        let flac_path = create_test_flac(temp_dir.path(), "raw.flac", Some(("LYRICS", "Flac\r\n")));
+        let track = extract_lyrics_from_file(&flac_path, &options).unwrap();
+        assert_eq!(track.raw_lyrics.as_deref(), Some(&b"Flac\r\n"[..]));
+    }
+
+    #[test]
+    fn test_extract_lyrics_from_wav() {
+        let temp_dir = tempdir().unwrap();
+        let with_lyrics = create_test_wav(temp_dir.path(), "lyrics.wav", Some("WAV lyrics"));
+        let without_chunk = create_test_wav(temp_dir.path(), "plain.wav", None);
+
+        let track = extract_lyrics_from_file(&with_lyrics, &ExtractOptions::default()).unwrap();
+        assert_eq!(track.lyrics.as_deref(), Some("WAV lyrics"));
+        assert_eq!(track.lyrics_frame.unwrap().to_string(), "USLT (eng)");
+        let mapped = ExtractOptions { mmap: true, ..Default::default() };
+        assert_eq!(extract_lyrics_from_file(&with_lyrics, &mapped).unwrap().lyrics.as_deref(), Some("WAV lyrics"));
+
+        let track = extract_lyrics_from_file(&without_chunk, &ExtractOptions::default()).unwrap();
+        assert!(track.lyrics.is_none());
+        assert!(!track.has_tag);
+    }
+
+    #[test]
+    fn test_extract_lyrics_from_flac() {
+        let temp_dir = tempdir().unwrap();
+        let with_lyrics = create_test_flac(temp_dir.path(), "lyrics.flac", Some(("LYRICS", "FLAC lyrics")));
+        let unsynced = create_test_flac(temp_dir.path(), "unsynced.flac", Some(("UNSYNCEDLYRICS", "Unsynced lyrics")));
+        let without = create_test_flac(temp_dir.path(), "none.flac", None);
+        
+        assert_eq!(extract_lyrics_from_file(&with_lyrics, &ExtractOptions::default()).unwrap().lyrics.unwrap(), "FLAC lyrics");
+        assert_eq!(extract_lyrics_from_file(&unsynced, &ExtractOptions::default()).unwrap().lyrics.unwrap(), "Unsynced lyrics");
+        assert!(extract_lyrics_from_file(&without, &ExtractOptions::default()).unwrap().lyrics.is_none());
+    }
+
+    #[test]
+    fn test_extract_lyrics_from_ogg() {
+        let temp_dir = tempdir().unwrap();
+        let with_lyrics = create_test_ogg(temp_dir.path(), "lyrics.ogg", Some(("LYRICS", "OGG lyrics")));
+        let without = create_test_ogg(temp_dir.path(), "none.ogg", Some(("TITLE", "Episode 1")));
+        
+        let track = extract_lyrics_from_file(&with_lyrics, &ExtractOptions::default()).unwrap();
+        assert_eq!(track.lyrics.as_deref(), Some("OGG lyrics"));
+        let track = extract_lyrics_from_file(&without, &ExtractOptions::default()).unwrap();
+        assert_eq!(track.lyrics, None);
+        assert_eq!(track.title.as_deref(), Some("Episode 1"));
+    }
+
+    #[test]
+    fn test_extract_lyrics_from_m4a() {
+        let temp_dir = tempdir().unwrap();
+        let with_lyrics = create_test_m4a(temp_dir.path(), "lyrics.m4a", Some("M4A lyrics"));
+        let without = create_test_m4a(temp_dir.path(), "none.m4a", None);
+        let broken = temp_dir.path().join("broken.m4a");
+        File::create(&broken).unwrap().write_all(b"not an mp4").unwrap();
+        
+        assert_eq!(extract_lyrics_from_file(&with_lyrics, &ExtractOptions::default()).unwrap().lyrics.unwrap(), "M4A lyrics");
+        assert!(extract_lyrics_from_file(&without, &ExtractOptions::default()).unwrap().lyrics.is_none());
+        assert!(matches!(extract_lyrics_from_file(&broken, &ExtractOptions::default()), Err(Error::Lofty { .. })));
+    }
+
+    #[test]
+    fn test_extract_lyrics_lrc_prefers_sylt() {
+        use id3::frame::SynchronisedLyricsType;
+
+        let temp_dir = tempdir().unwrap();
+        let mp3_path = create_test_mp3(temp_dir.path(), "test.mp3", Some("Plain lyrics"));
+
+        let mut tag = Tag::read_from_path(&mp3_path).unwrap();
+        tag.add_frame(SynchronisedLyrics {
+            lang: "eng".to_string(),
+            timestamp_format: TimestampFormat::Ms,
+            content_type: SynchronisedLyricsType::Lyrics,
+            description: String::new(),
+            content: vec![(1_500, "First line".to_string()), (65_250, "Second line".to_string())],
+        });
+        tag.write_to_path(&mp3_path, id3::Version::Id3v24).unwrap();
+
+        let lrc_options = ExtractOptions { format: OutputFormat::Lrc, ..Default::default() };
+        let lrc = extract_lyrics_from_file(&mp3_path, &lrc_options).unwrap().lyrics;
+        assert_eq!(lrc.unwrap(), "[00:01.50]First line\n[01:05.25]Second line\n[01:08.25]");
+
+        let short_tail = ExtractOptions { tail_duration: Duration::from_millis(500), ..lrc_options };
+        let lrc = extract_lyrics_from_file(&mp3_path, &short_tail).unwrap().lyrics;
+        assert!(lrc.unwrap().ends_with("Second line\n[01:05.75]"));
+
+        // Plain text output keeps using the USLT frame
+        let text = extract_lyrics_from_file(&mp3_path, &ExtractOptions::default()).unwrap().lyrics;
+        assert_eq!(text.unwrap(), "Plain lyrics");
+    }
+
+    #[test]
+    fn test_extract_lyrics_prefer() {
+        use id3::frame::SynchronisedLyricsType;
+
+        let temp_dir = tempdir().unwrap();
+        let both = create_test_mp3(temp_dir.path(), "both.mp3", Some("Plain lyrics"));
+        let sylt_only = create_test_mp3(temp_dir.path(), "sylt.mp3", None);
+        for (path, lines) in [(&both, vec!["First line", "Second line"]), (&sylt_only, vec!["Only synced"])] {
+            let mut tag = Tag::read_from_path(path).unwrap_or_default();
+            tag.add_frame(SynchronisedLyrics {
+                lang: "eng".to_string(),
+                timestamp_format: TimestampFormat::Ms,
+                content_type: SynchronisedLyricsType::Lyrics,
+                description: String::new(),
+                content: lines.iter().enumerate().map(|(i, line)| (i as u32 * 1_000, line.to_string())).collect(),
+            });
+            tag.write_to_path(path, id3::Version::Id3v24).unwrap();
+        }
+
+        let extract = |path: &Path, prefer| {
+            let track = extract_lyrics_from_file(path, &ExtractOptions { prefer, ..Default::default() }).unwrap();
+            (track.lyrics, track.lyrics_frame.map(|frame| frame.to_string()))
+        };
+        assert_eq!(extract(&both, FramePreference::Uslt).0.as_deref(), Some("Plain lyrics"));
+        assert_eq!(extract(&sylt_only, FramePreference::Uslt), (None, None));
+        assert_eq!(
+            extract(&both, FramePreference::Sylt),
+            (Some("First line\nSecond line".to_string()), Some("SYLT (eng)".to_string()))
+        );
+        assert_eq!(extract(&sylt_only, FramePreference::Sylt).0.as_deref(), Some("Only synced"));
+        assert_eq!(extract(&both, FramePreference::Longest).0.as_deref(), Some("First line\nSecond line"));
+        assert_eq!(extract(&sylt_only, FramePreference::Longest).0.as_deref(), Some("Only synced"));
+    }
+
+    #[test]
+    fn test_extract_sylt_info() {
+        use id3::frame::SynchronisedLyricsType;
+
+        let temp_dir = tempdir().unwrap();
+        let mp3_path = create_test_mp3(temp_dir.path(), "test.mp3", None);
+        let mut tag = Tag::new();
+        tag.add_frame(SynchronisedLyrics {
+            lang: "jpn".to_string(),
+            timestamp_format: TimestampFormat::Ms,
+            content_type: SynchronisedLyricsType::Transcription,
+            description: "Romaji".to_string(),
+            content: vec![(0, "Konnichiwa".to_string())],
+        });
+        tag.write_to_path(&mp3_path, id3::Version::Id3v24).unwrap();
+
+        let options = ExtractOptions { format: OutputFormat::Lrc, ..Default::default() };
+        let results = extract_all_lyrics(std::slice::from_ref(&mp3_path), &options, 1).unwrap();
+        let info = results[0].synced_lyrics.clone().unwrap();
+        assert_eq!(info, SyncedLyricsInfo {
+            content_type: "transcription".to_string(),
+            description: Some("Romaji".to_string()),
+            language: Some("jpn".to_string()),
+        });
+        assert_eq!(results[0].lyrics_frame.as_ref().unwrap().to_string(), "SYLT (jpn)");
+
+        let annotated = render_text(&results, &TextOptions { annotate: true, ..Default::default() });
+        assert!(annotated.starts_with("# source: SYLT (jpn), transcription \"Romaji\"\n"));
+        let json: serde_json::Value = serde_json::to_value(&results).unwrap();
+        assert_eq!(json[0]["synced_lyrics"]["description"], "Romaji");
+
+        // Files without a SYLT frame leave it out of the JSON
+        let plain = create_test_mp3(temp_dir.path(), "plain.mp3", Some("Plain"));
+        let results = extract_all_lyrics(&[plain], &ExtractOptions::default(), 1).unwrap();
+        assert!(serde_json::to_value(&results).unwrap()[0].get("synced_lyrics").is_none());
+    }
+
+    #[test]
+    fn test_extract_lyrics_srt() {
+        use id3::frame::SynchronisedLyricsType;
+
+        let temp_dir = tempdir().unwrap();
+        let plain = create_test_mp3(temp_dir.path(), "plain.mp3", Some("Plain lyrics"));
+        let synced = create_test_mp3(temp_dir.path(), "synced.mp3", None);
+
+        let mut tag = Tag::new();
+        tag.add_frame(SynchronisedLyrics {
+            lang: "eng".to_string(),
+            timestamp_format: TimestampFormat::Ms,
+            content_type: SynchronisedLyricsType::Lyrics,
+            description: String::new(),
+            content: vec![(65_250, "Second line".to_string()), (1_500, "First line".to_string())],
+        });
+        tag.write_to_path(&synced, id3::Version::Id3v24).unwrap();
+
+        let srt_options = ExtractOptions { format: OutputFormat::Srt, ..Default::default() };
+        let track = extract_lyrics_from_file(&synced, &srt_options).unwrap();
+        assert_eq!(track.synced.unwrap()[0], TimedLine { start_ms: 1_500, text: "First line".to_string() });
+        assert_eq!(
+            track.lyrics.unwrap(),
+            "1\n00:00:01,500 --> 00:01:05,250\nFirst line\n\n2\n00:01:05,250 --> 00:01:08,250\nSecond line"
+        );
+
+        // Without a SYLT frame there's nothing to time the cues with
+        let err = extract_lyrics_from_file(&plain, &srt_options).unwrap_err();
+        assert!(matches!(err, Error::NoSyncedLyrics(_)));
+    }
+
+    #[test]
+    fn test_extract_lyrics_lrc_falls_back_to_uslt() {
+        let temp_dir = tempdir().unwrap();
+        let mp3_path = create_test_mp3(temp_dir.path(), "test.mp3", Some("Plain lyrics"));
+
+        let lrc_options = ExtractOptions { format: OutputFormat::Lrc, ..Default::default() };
+        let lyrics = extract_lyrics_from_file(&mp3_path, &lrc_options).unwrap().lyrics;
+        assert_eq!(lyrics.unwrap(), "Plain lyrics");
+    }
+
+    #[test]
+    fn test_min_lyrics_length() {
+        let temp_dir = tempdir().unwrap();
+        let stub = create_test_mp3(temp_dir.path(), "stub.mp3", Some("url"));
+        let song = create_test_mp3(temp_dir.path(), "song.mp3", Some("Proper lyrics"));
+        let options = ExtractOptions { min_lyrics_length: 10, ..Default::default() };
+        
+        assert!(extract_lyrics_from_file(&stub, &options).unwrap().lyrics.is_none());
+        assert_eq!(extract_lyrics_from_file(&song, &options).unwrap().lyrics.as_deref(), Some("Proper lyrics"));
+        
+        let results = extract_all_lyrics(&[stub], &options, 1).unwrap();
+        let output = render_text(&results, &TextOptions { include_names: true, ..Default::default() });
+        assert!(output.ends_with("[No lyrics found]\n"));
+    }
+
+    #[test]
+    fn test_lyrics_description_ignores_case() {
+        let temp_dir = tempdir().unwrap();
+        let mut paths = Vec::new();
+        for (name, description) in [("comm.mp3", "Lyrics"), ("txxx.mp3", "unsynced lyrics"), ("other.mp3", "Notes")] {
+            let path = create_test_mp3(temp_dir.path(), name, None);
+            let mut tag = Tag::new();
+            if name == "txxx.mp3" {
+                tag.add_frame(ExtendedText { description: description.to_string(), value: "TXXX lyrics".to_string() });
+            } else {
+                tag.add_frame(Comment {
+                    lang: "eng".to_string(),
+                    description: description.to_string(),
+                    text: "COMM lyrics".to_string(),
+                });
+            }
+            tag.write_to_path(&path, id3::Version::Id3v24).unwrap();
+            paths.push(path);
+        }
+        
+        let lyrics = |path: &Path| extract_lyrics_from_file(path, &ExtractOptions::default()).unwrap().lyrics;
+        assert_eq!(lyrics(&paths[0]).as_deref(), Some("COMM lyrics"));
+        assert_eq!(lyrics(&paths[1]).as_deref(), Some("TXXX lyrics"));
+        assert_eq!(lyrics(&paths[2]), None);
+    }
+
+    #[test]
+    fn test_source_priority() {
+        let temp_dir = tempdir().unwrap();
+        let path = create_test_mp3(temp_dir.path(), "both.mp3", Some("USLT lyrics"));
+        let mut tag = Tag::read_from_path(&path).unwrap();
+        tag.add_frame(ExtendedText { description: "LYRICS".to_string(), value: "TXXX lyrics".to_string() });
+        tag.write_to_path(&path, id3::Version::Id3v24).unwrap();
+
+        let extract = |source_priority: &[LyricsSourceKind]| {
+            let options = ExtractOptions { source_priority: source_priority.to_vec(), ..Default::default() };
+            extract_lyrics_from_file(&path, &options).unwrap()
+        };
+        assert_eq!(extract(DEFAULT_SOURCE_PRIORITY).lyrics.as_deref(), Some("USLT lyrics"));
+        let track = extract(&[LyricsSourceKind::Txxx, LyricsSourceKind::Uslt]);
+        assert_eq!(track.lyrics.as_deref(), Some("TXXX lyrics"));
+        assert_eq!(track.candidates.len(), 2);
+        assert_eq!(extract(&[LyricsSourceKind::Comm]).lyrics, None);
+    }
+
+    #[test]
+    fn test_merge_frames() {
+        let temp_dir = tempdir().unwrap();
+        let path = create_test_mp3(temp_dir.path(), "verses.mp3", Some("First verse"));
+        let mut tag = Tag::read_from_path(&path).unwrap();
+        tag.add_frame(Lyrics {
+            lang: "eng".to_string(),
+            description: "Verse 2".to_string(),
+            text: "Second verse".to_string(),
+        });
+        tag.add_frame(ExtendedText { description: "LYRICS".to_string(), value: "TXXX lyrics".to_string() });
+        tag.write_to_path(&path, id3::Version::Id3v24).unwrap();
+
+        let track = extract_lyrics_from_file(&path, &ExtractOptions { merge_frames: true, ..Default::default() }).unwrap();
+        assert_eq!(track.lyrics.as_deref(), Some("First verse\n\nSecond verse"));
+        assert_eq!(track.lyrics_frame.unwrap().id, "USLT");
+        assert_eq!(track.candidates.len(), 2);
+        let first_only = extract_lyrics_from_file(&path, &ExtractOptions::default()).unwrap();
+        assert_eq!(first_only.lyrics.as_deref(), Some("First verse"));
+    }
+
+    #[test]
+    fn test_desperate() {
+        let temp_dir = tempdir().unwrap();
+        let with_comment = create_test_mp3(temp_dir.path(), "comment.mp3", None);
+        let mut tag = Tag::read_from_path(&with_comment).unwrap();
+        tag.add_frame(Comment { lang: "eng".to_string(), description: "Ripped by".to_string(), text: "Me".to_string() });
+        tag.add_frame(Comment { lang: "eng".to_string(), description: String::new(), text: "One\nTwo\n\nThree\nFour".to_string() });
+        tag.write_to_path(&with_comment, id3::Version::Id3v24).unwrap();
+
+        let in_title = create_test_mp3(temp_dir.path(), "title.mp3", None);
+        let mut tag = Tag::read_from_path(&in_title).unwrap();
+        tag.set_title("Line 1\nLine 2\nLine 3\nLine 4");
+        tag.write_to_path(&in_title, id3::Version::Id3v24).unwrap();
+
+        let short_title = create_test_mp3(temp_dir.path(), "short.mp3", None);
+        let mut tag = Tag::read_from_path(&short_title).unwrap();
+        tag.set_title("Just a\ntwo line title");
+        tag.write_to_path(&short_title, id3::Version::Id3v24).unwrap();
+
+        let desperate = ExtractOptions { desperate: true, ..Default::default() };
+        let track = extract_lyrics_from_file(&with_comment, &desperate).unwrap();
+        assert_eq!(track.lyrics.as_deref(), Some("One\nTwo\n\nThree\nFour"));
+        assert_eq!(track.lyrics_frame.unwrap().id, "COMM");
+        let track = extract_lyrics_from_file(&in_title, &desperate).unwrap();
+        assert_eq!(track.lyrics.as_deref(), Some("Line 1\nLine 2\nLine 3\nLine 4"));
+        assert_eq!(track.title, None);
+        assert_eq!(extract_lyrics_from_file(&short_title, &desperate).unwrap().lyrics, None);
+        assert_eq!(extract_lyrics_from_file(&with_comment, &ExtractOptions::default()).unwrap().lyrics, None);
+    }
+
+    #[test]
+    fn test_instrumental() {
+        let temp_dir = tempdir().unwrap();
+        let marked = create_test_mp3(temp_dir.path(), "marked.mp3", None);
+        let mut tag = Tag::read_from_path(&marked).unwrap();
+        tag.add_frame(ExtendedText { description: "INSTRUMENTAL".to_string(), value: "1".to_string() });
+        tag.write_to_path(&marked, id3::Version::Id3v24).unwrap();
+        let said = create_test_mp3(temp_dir.path(), "said.mp3", Some(" [Instrumental]\n"));
+        let blank = create_test_mp3(temp_dir.path(), "blank.mp3", Some("\n"));
+        let sung = create_test_mp3(temp_dir.path(), "sung.mp3", Some("Words"));
+        let missing = create_test_mp3(temp_dir.path(), "missing.mp3", None);
+
+        let results = extract_all_lyrics(&[marked, said, blank, sung, missing], &ExtractOptions::default(), 1).unwrap();
+        for (file, instrumental) in [
+            (create_test_flac(temp_dir.path(), "marked.flac", Some(("INSTRUMENTAL", "1"))), true),
+            (create_test_flac(temp_dir.path(), "sung.flac", Some(("LYRICS", "Words"))), false),
+            (create_test_ogg(temp_dir.path(), "marked.ogg", Some(("INSTRUMENTAL", "1"))), true),
+            (create_test_ogg(temp_dir.path(), "unmarked.ogg", Some(("INSTRUMENTAL", "0"))), false),
+        ] {
+            let track = extract_lyrics_from_file(&file, &ExtractOptions::default()).unwrap();
+            assert_eq!(track.instrumental, instrumental, "{}", file.display());
+        }
+
+        let instrumental: Vec<(bool, Option<&str>)> =
+            results.iter().map(|result| (result.instrumental, result.lyrics.as_deref())).collect();
+        assert_eq!(
+            instrumental,
+            [(true, None), (true, Some(" [Instrumental]\n")), (true, Some("\n")), (false, Some("Words")), (false, None)]
+        );
+
+        // Without a marker asked for, the output is as it would be for any other track
+        let named = TextOptions { include_names: true, header_format: "{filename}".to_string(), ..Default::default() };
+        assert_eq!(
+            render_text(&results, &named),
+            concat!(
+                "marked.mp3\n\n[No lyrics found]\nsaid.mp3 [eng]\n\n [Instrumental]\n\nblank.mp3 [eng]\n\n\n\n",
+                "sung.mp3 [eng]\n\nWords\nmissing.mp3\n\n[No lyrics found]\n"
+            )
+        );
+        let marked = TextOptions { instrumental_marker: Some("(instrumental)".to_string()), ..Default::default() };
+        assert_eq!(render_text(&results, &marked), "(instrumental)\n(instrumental)\n(instrumental)\nWords\n");
+    }
+
+    #[test]
+    fn test_frame_selector() {
+        let temp_dir = tempdir().unwrap();
+        let path = create_test_mp3(temp_dir.path(), "custom.mp3", Some("USLT lyrics"));
+        let mut tag = Tag::read_from_path(&path).unwrap();
+        tag.add_frame(ExtendedText { description: "Custom".to_string(), value: "Custom lyrics".to_string() });
+        tag.add_frame(ExtendedText { description: "Other".to_string(), value: "Something else".to_string() });
+        tag.write_to_path(&path, id3::Version::Id3v24).unwrap();
+
+        let extract = |selector: &str| {
+            let options = ExtractOptions { frame: Some(FrameSelector::parse(selector).unwrap()), ..Default::default() };
+            extract_lyrics_from_file(&path, &options).unwrap()
+        };
+        let custom = extract("TXXX:CUSTOM");
+        assert_eq!(custom.lyrics.as_deref(), Some("Custom lyrics"));
+        assert_eq!(custom.lyrics_frame.unwrap().id, "TXXX");
+        assert_eq!(custom.candidates.len(), 1);
+        assert_eq!(extract("USLT").lyrics.as_deref(), Some("USLT lyrics"));
+        assert_eq!(extract("COMM:LYRICS").lyrics, None);
+        assert_eq!(extract("TXXX:Missing").lyrics, None);
+
+        for invalid in ["uslt", "LYRICS", "", "TIT2:Title", ":LYRICS"] {
+            assert!(matches!(FrameSelector::parse(invalid), Err(Error::InvalidFrameId { .. })), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_replacements() {
+        let temp_dir = tempdir().unwrap();
+        let path = create_test_mp3(temp_dir.path(), "marked.mp3", Some("Line one\r\nLyrics from example.com\nLine two"));
+        let replacements = ["s/\r//g", "s/(?m)^Lyrics from .*\n//", "s/Line/Verse/g"];
+        let options = ExtractOptions {
+            replacements: replacements.iter().map(|expression| Substitution::parse(expression).unwrap()).collect(),
+            ..Default::default()
+        };
+        let track = extract_lyrics_from_file(&path, &options).unwrap();
+        assert_eq!(track.lyrics.as_deref(), Some("Verse one\nVerse two"));
+    }
+
+    #[test]
+    fn test_strips_byte_order_mark() {
+        let temp_dir = tempdir().unwrap();
+        let path = create_test_mp3(temp_dir.path(), "bom.mp3", Some("\u{FEFF}First line\nSecond line"));
+        let track = extract_lyrics_from_file(&path, &ExtractOptions::default()).unwrap();
+        assert_eq!(track.lyrics.as_deref(), Some("First line\nSecond line"));
+        assert_eq!(track.candidates[0].text, "First line\nSecond line");
+    }
+
+    #[test]
+    fn test_id3v1_comments() {
+        let temp_dir = tempdir().unwrap();
+        let lyrical = create_id3v1_mp3(temp_dir.path(), "old.mp3", "Old Song", "Hello darkness my old friend");
+        let rip_note = create_id3v1_mp3(temp_dir.path(), "ripped.mp3", "Old Song", "www.example.com");
+        // An ID3v2 tag without lyrics still falls back to the ID3v1 comment
+        let both = create_test_mp3(temp_dir.path(), "both.mp3", None);
+        let mut tag = Tag::new();
+        tag.set_title("New Title");
+        tag.write_to_path(&both, id3::Version::Id3v24).unwrap();
+        let mut bytes = fs::read(&both).unwrap();
+        bytes.extend_from_slice(&fs::read(&lyrical).unwrap()[5..]);
+        fs::write(&both, bytes).unwrap();
+
+        let v1 = ExtractOptions { id3v1_comments: true, ..Default::default() };
+        assert_eq!(extract_lyrics_from_file(&lyrical, &ExtractOptions::default()).unwrap().lyrics, None);
+        let track = extract_lyrics_from_file(&lyrical, &v1).unwrap();
+        assert_eq!(track.lyrics.as_deref(), Some("Hello darkness my old friend"));
+        assert_eq!(track.title.as_deref(), Some("Old Song"));
+        assert_eq!(extract_lyrics_from_file(&rip_note, &v1).unwrap().lyrics, None);
+        let track = extract_lyrics_from_file(&both, &v1).unwrap();
+        assert_eq!(track.lyrics.as_deref(), Some("Hello darkness my old friend"));
+        assert_eq!(track.title.as_deref(), Some("New Title"));
+    }
+
+    #[test]
+    fn test_lyrics_frame() {
+        let temp_dir = tempdir().unwrap();
+        let uslt = create_test_mp3(temp_dir.path(), "uslt.mp3", Some("USLT lyrics"));
+        let comm = create_test_mp3(temp_dir.path(), "comm.mp3", None);
+        let mut tag = Tag::read_from_path(&comm).unwrap();
+        tag.add_frame(Comment {
+            lang: "deu".to_string(),
+            description: "LYRICS".to_string(),
+            text: "COMM lyrics".to_string(),
+        });
+        tag.write_to_path(&comm, id3::Version::Id3v24).unwrap();
+        let flac = create_test_flac(temp_dir.path(), "unsynced.flac", Some(("UNSYNCEDLYRICS", "FLAC lyrics")));
+        
+        let frame = |path: &Path| extract_lyrics_from_file(path, &ExtractOptions::default()).unwrap().lyrics_frame;
+        assert_eq!(frame(&uslt).unwrap().to_string(), "USLT (eng)");
+        assert_eq!(frame(&comm).unwrap().to_string(), "COMM (deu)");
+        assert_eq!(frame(&flac), Some(LyricsFrame { id: "UNSYNCEDLYRICS".to_string(), language: None }));
+        assert_eq!(frame(&create_test_mp3(temp_dir.path(), "none.mp3", None)), None);
+    }
+
+    #[test]
+    fn test_extract_lyrics_by_language() {
+        let temp_dir = tempdir().unwrap();
+        let mp3_path = create_test_mp3(temp_dir.path(), "test.mp3", Some("English lyrics"));
+        
+        let mut tag = Tag::read_from_path(&mp3_path).unwrap();
+        tag.add_frame(Lyrics {
+            lang: "jpn".to_string(),
+            description: "Japanese".to_string(),
+            text: "Japanese lyrics".to_string(),
+        });
+        tag.write_to_path(&mp3_path, id3::Version::Id3v24).unwrap();
+        
+        let with_language = |language: Option<&str>| {
+            let options = ExtractOptions { language: language.map(str::to_string), ..Default::default() };
+            extract_lyrics_from_file(&mp3_path, &options).unwrap().lyrics.unwrap()
+        };
+        
+        assert_eq!(with_language(None), "English lyrics");
+        assert_eq!(with_language(Some("JPN")), "Japanese lyrics");
+        assert_eq!(with_language(Some("eng")), "English lyrics");
+        // Unknown languages fall back to the first frame
+        assert_eq!(with_language(Some("fra")), "English lyrics");
+        
+        let candidates = extract_lyrics_from_file(&mp3_path, &ExtractOptions::default()).unwrap().candidates;
+        let texts: Vec<&str> = candidates.iter().map(|candidate| candidate.text.as_str()).collect();
+        assert_eq!(texts, ["English lyrics", "Japanese lyrics"]);
+        assert_eq!(candidates[1].description, "Japanese");
+    }
+
+    #[test]
+    fn test_extract_title_and_artist() {
+        let temp_dir = tempdir().unwrap();
+        let mp3_path = create_test_mp3(temp_dir.path(), "test.mp3", Some("Lyrics"));
+        
+        let mut tag = Tag::read_from_path(&mp3_path).unwrap();
+        tag.set_title("Song");
+        tag.set_artist("Band");
+        tag.set_album("Record");
+        tag.set_track(7);
+        tag.write_to_path(&mp3_path, id3::Version::Id3v24).unwrap();
+        
+        let track = extract_lyrics_from_file(&mp3_path, &ExtractOptions::default()).unwrap();
+        assert_eq!(track.title.as_deref(), Some("Song"));
+        assert_eq!(track.artist.as_deref(), Some("Band"));
+        assert_eq!(track.album.as_deref(), Some("Record"));
+        assert_eq!(track.track_number, Some(7));
+        let flac_path = create_test_flac(temp_dir.path(), "track.flac", Some(("TRACKNUMBER", "3/12")));
+        assert_eq!(extract_lyrics_from_file(&flac_path, &ExtractOptions::default()).unwrap().track_number, Some(3));
+        
+        let results = extract_all_lyrics(&[mp3_path], &ExtractOptions::default(), 1).unwrap();
+        let output = render_text(&results, &TextOptions { include_names: true, ..Default::default() });
+        // The fixture's USLT frame is tagged as English
+        assert!(output.starts_with("Band - Song [eng]\n\n"));
+        
+        let output = render_text(&results, &TextOptions {
+            include_names: true,
+            header_format: "{title} ({filename})".to_string(),
+            ..Default::default()
+        });
+        assert!(output.starts_with("Song (test.mp3) [eng]\n\n"));
+    }
+
+    #[test]
+    fn test_fix_encoding() {
+        let path = Path::new("test.mp3");
+        let original = "Я помню чудное мгновенье: передо мной явилась ты, как мимолетное виденье, как гений чистой красоты.";
+        let (bytes, _, _) = encoding_rs::WINDOWS_1251.encode(original);
+        // What a tag reader produces when it treats those bytes as Latin-1
+        let mojibake: String = bytes.iter().map(|&b| char::from(b)).collect();
+        
+        assert_eq!(fix_encoding(&mojibake, None, path), original);
+        assert_eq!(fix_encoding(&mojibake, Some(encoding_rs::WINDOWS_1251), path), original);
+        
+        // Plain ASCII, real Latin-1 text and proper Unicode are left alone
+        assert_eq!(fix_encoding("Plain lyrics", None, path), "Plain lyrics");
+        assert_eq!(fix_encoding("Café crème", None, path), "Café crème");
+        assert_eq!(fix_encoding(original, None, path), original);
+    }
+
+    #[test]
+    fn test_strip_timestamps() {
+        let lyrics = "[00:12]First line\n[00:15.50] Second line\n[01:02.345][01:30.00]Repeated\n[Chorus]\nNo stamp [00:20] inside";
+        assert_eq!(
+            strip_timestamps(lyrics),
+            "First line\nSecond line\nRepeated\n[Chorus]\nNo stamp [00:20] inside"
+        );
+        assert_eq!(strip_timestamps("[Verse 1]\n[ab:cd]\n[1:2]"), "[Verse 1]\n[ab:cd]\n[1:2]");
+        
+        let temp_dir = tempdir().unwrap();
+        let mp3_path = create_test_mp3(temp_dir.path(), "test.mp3", Some("[00:01.00]Hello\n[Chorus]"));
+        let options = ExtractOptions { strip_timestamps: true, ..Default::default() };
+        assert_eq!(extract_lyrics_from_file(&mp3_path, &options).unwrap().lyrics.unwrap(), "Hello\n[Chorus]");
+    }
+
+    #[test]
+    fn test_strip_html() {
+        let html = "<p>Rock &amp; roll<br>all night<BR/>\nlong</p>\n<p class=\"verse\"><i>Caf&#233;</i> &lt;3 &#x2764; &bogus;</p>";
+        assert_eq!(strip_html(html), "Rock & roll\nall night\nlong\n\nCafé <3 ❤ &bogus;");
+        assert_eq!(strip_html("No markup <3 here"), "No markup <3 here");
+        
+        let temp_dir = tempdir().unwrap();
+        let mp3_path = create_test_mp3(temp_dir.path(), "test.mp3", Some("One<br />Two &amp; three"));
+        let options = ExtractOptions { strip_html: true, ..Default::default() };
+        assert_eq!(extract_lyrics_from_file(&mp3_path, &options).unwrap().lyrics.unwrap(), "One\nTwo & three");
+        let original = extract_lyrics_from_file(&mp3_path, &ExtractOptions::default()).unwrap();
+        assert_eq!(original.lyrics.unwrap(), "One<br />Two &amp; three");
+    }
+
+    #[test]
+    fn test_semaphore() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let semaphore = Semaphore::new(2);
+        let (running, peak) = (AtomicUsize::new(0), AtomicUsize::new(0));
+        thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    let _permit = semaphore.acquire();
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(5));
+                    running.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+        });
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+        assert_eq!(*semaphore.available.lock().unwrap(), 2);
+
+        // More threads than permits still gets through every file, in order
+        let temp_dir = tempdir().unwrap();
+        let files: Vec<PathBuf> = (0..6)
+            .map(|i| create_test_mp3(temp_dir.path(), &format!("{}.mp3", i), Some(&format!("Lyrics {}", i))))
+            .collect();
+        let options = ExtractOptions { concurrency_limit: 1, ..Default::default() };
+        let results = extract_all_lyrics(&files, &options, 4).unwrap();
+        let lyrics: Vec<_> = results.iter().map(|result| result.lyrics.clone().unwrap()).collect();
+        assert_eq!(lyrics, (0..6).map(|i| format!("Lyrics {}", i)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_slowest_files() {
+        let timing = |ms, path: &str| (Duration::from_millis(ms), PathBuf::from(path));
+        let timings = vec![timing(5, "a.mp3"), timing(30, "b.mp3"), timing(12, "c.mp3")];
+        assert_eq!(slowest_files(timings.clone(), 2), [timing(30, "b.mp3"), timing(12, "c.mp3")]);
+        assert_eq!(slowest_files(timings, 5).len(), 3);
+    }
+
+    #[test]
+    fn test_progress_message() {
+        let message = progress_message(200, 1000, 100, Duration::from_secs(4));
+        assert_eq!(message, "Processed 200/1000 files (25.0 files/sec, about 32s left)");
+        assert!(progress_message(5, 5, 5, Duration::ZERO).ends_with("about 0s left)"));
+    }
+
+    #[test]
+    fn test_first_n_lines() {
+        let temp_dir = tempdir().unwrap();
+        let mp3_path = create_test_mp3(temp_dir.path(), "test.mp3", Some("One\nTwo\nThree\nFour\nFive"));
+
+        let extract = |first_n_lines| {
+            let options = ExtractOptions { first_n_lines, ..Default::default() };
+            extract_lyrics_from_file(&mp3_path, &options).unwrap().lyrics.unwrap()
+        };
+        assert_eq!(extract(2), "One\nTwo\n...");
+        assert_eq!(extract(5), "One\nTwo\nThree\nFour\nFive");
+        assert_eq!(extract(0), "One\nTwo\nThree\nFour\nFive");
+        assert_eq!(first_lines("Verse\r\n", 1), "Verse\r\n");
+        assert_eq!(first_lines("One\r\nTwo\r\nThree\r\n", 2), "One\r\nTwo\r\n...");
+        assert_eq!(first_lines("One\nTwo\n\n \n", 2), "One\nTwo\n\n \n");
+    }
+
+    #[test]
+    fn test_flatten_blank_lines() {
+        assert_eq!(flatten_blank_lines("One\n\n\n\nTwo\n \n\t\nThree"), "One\n\nTwo\n\nThree");
+        assert_eq!(flatten_blank_lines("\n\n  \nOne\nTwo\n\n\n"), "One\nTwo");
+        assert_eq!(flatten_blank_lines("Verse\r\n\r\n\r\nChorus\r\n"), "Verse\n\nChorus");
+        assert_eq!(flatten_blank_lines("\n \n"), "");
+        
+        let temp_dir = tempdir().unwrap();
+        let mp3_path = create_test_mp3(temp_dir.path(), "test.mp3", Some("\nVerse\n\n\n\nChorus\n\n"));
+        let options = ExtractOptions { flatten_blank_lines: true, ..Default::default() };
+        assert_eq!(extract_lyrics_from_file(&mp3_path, &options).unwrap().lyrics.unwrap(), "Verse\n\nChorus");
+    }
+
+    #[test]
+    fn test_extract_lyrics_transliterate() {
+        let temp_dir = tempdir().unwrap();
+        let mp3_path = create_test_mp3(temp_dir.path(), "test.mp3", Some("Привет\nCafé"));
+
+        let options = ExtractOptions { transliterate: true, ..Default::default() };
+        assert_eq!(extract_lyrics_from_file(&mp3_path, &options).unwrap().lyrics.unwrap(), "Privet\nCafe");
+        let original = extract_lyrics_from_file(&mp3_path, &ExtractOptions::default()).unwrap();
+        assert_eq!(original.lyrics.unwrap(), "Привет\nCafé");
+    }
+
+    #[test]
+    fn test_extract_lyrics_case() {
+        let temp_dir = tempdir().unwrap();
+        let mp3_path = create_test_mp3(temp_dir.path(), "test.mp3", Some("Ça va, Straße\nΟΔΟΣ Éclair İ"));
+
+        let cased = |case| {
+            let options = ExtractOptions { case: Some(case), ..Default::default() };
+            extract_lyrics_from_file(&mp3_path, &options).unwrap().lyrics.unwrap()
+        };
+        assert_eq!(cased(TextCase::Upper), "ÇA VA, STRASSE\nΟΔΟΣ ÉCLAIR İ");
+        // Lower case Greek ends words with a final sigma, and the dotted I keeps its dot
+        assert_eq!(cased(TextCase::Lower), "ça va, straße\nοδος éclair i\u{307}");
+    }
+
+    #[test]
+    fn test_extract_lyrics_normalize_unicode() {
+        let temp_dir = tempdir().unwrap();
+        // A decomposed "é" and a "ﬁ" ligature, with a BOM that has to go first
+        let mp3_path = create_test_mp3(temp_dir.path(), "test.mp3", Some("\u{FEFF}Cafe\u{301} ﬁne"));
+
+        let normalized = |form| {
+            let options = ExtractOptions { normalize_unicode: Some(form), ..Default::default() };
+            extract_lyrics_from_file(&mp3_path, &options).unwrap().lyrics.unwrap()
+        };
+        assert_eq!(normalized(UnicodeForm::Nfc), "Caf\u{E9} ﬁne");
+        assert_eq!(normalized(UnicodeForm::Nfkc), "Caf\u{E9} fine");
+        let original = extract_lyrics_from_file(&mp3_path, &ExtractOptions::default()).unwrap();
+        assert_eq!(original.lyrics.unwrap(), "Cafe\u{301} ﬁne");
+    }
+
+    #[test]
+    fn test_dump_frames() {
+        let temp_dir = tempdir().unwrap();
+        let mp3_path = create_test_mp3(temp_dir.path(), "test.mp3", Some("First line\nSecond line"));
+        let mut tag = Tag::read_from_path(&mp3_path).unwrap();
+        tag.set_title("Song");
+        tag.write_to_path(&mp3_path, id3::Version::Id3v24).unwrap();
+        let flac_path = create_test_flac(temp_dir.path(), "test.flac", Some(("LYRICS", "FLAC lyrics")));
+        
+        let frames = dump_frames(&mp3_path, DEFAULT_MAX_TAG_BYTES).unwrap();
+        let ids: Vec<&str> = frames.iter().map(|frame| frame.id.as_str()).collect();
+        assert!(ids.contains(&"USLT"));
+        assert!(ids.contains(&"TIT2"));
+        let uslt = frames.iter().find(|frame| frame.id == "USLT").unwrap();
+        assert_eq!(uslt.preview(60), "First line Second line");
+        assert_eq!(uslt.preview(10), "First l...");
+        
+        assert_eq!(dump_frames(&flac_path, DEFAULT_MAX_TAG_BYTES).unwrap(), vec![FrameInfo { id: "LYRICS".to_string(), content: "FLAC lyrics".to_string() }]);
+    }
+
+    #[test]
+    fn test_extraction_summary() {
+        let temp_dir = tempdir().unwrap();
+        let with_lyrics = create_test_mp3(temp_dir.path(), "test1.mp3", Some("Lyrics for song 1"));
+        let without = create_test_mp3(temp_dir.path(), "test2.mp3", None);
+        let broken = create_corrupt_mp3(temp_dir.path(), "broken.mp3");
+        
+        let results = extract_all_lyrics(&[with_lyrics, without, broken], &ExtractOptions::default(), 1).unwrap();
+        let summary = ExtractionSummary::from_results(&results);
+        
+        assert_eq!(summary, ExtractionSummary { files: 3, with_lyrics: 1, without_lyrics: 1, errors: 1 });
+        assert_eq!(summary.render_text(), "Files:          3\nWith lyrics:    1\nWithout lyrics: 1\nErrors:         1\n");
+        let json: serde_json::Value = serde_json::from_str(&summary.render_json().unwrap()).unwrap();
+        assert_eq!(json, serde_json::json!({ "files": 3, "with_lyrics": 1, "without_lyrics": 1, "errors": 1 }));
+    }
+
+    #[test]
+    fn test_extract_all_lyrics_fail_fast() {
+        let temp_dir = tempdir().unwrap();
+        let with_lyrics = create_test_mp3(temp_dir.path(), "test1.mp3", Some("Lyrics for song 1"));
+        let broken = create_corrupt_mp3(temp_dir.path(), "broken.mp3");
+        let files = [with_lyrics.clone(), broken.clone()];
+        
+        let options = ExtractOptions { fail_fast: true, ..Default::default() };
+        for jobs in [1, 2] {
+            let error = extract_all_lyrics(&files, &options, jobs).unwrap_err();
+            assert!(matches!(&error, Error::Extract { path, .. } if *path == broken), "{:?}", error);
+        }
+        assert_eq!(extract_all_lyrics(&[with_lyrics], &options, 1).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_progress_event() {
+        let temp_dir = tempdir().unwrap();
+        let with_lyrics = create_test_mp3(temp_dir.path(), "test1.mp3", Some("Lyrics for song 1"));
+        let without_lyrics = create_test_mp3(temp_dir.path(), "test2.mp3", None);
+        let broken = create_corrupt_mp3(temp_dir.path(), "broken.mp3");
+        let statuses: Vec<FileStatus> = [with_lyrics, without_lyrics, broken]
+            .iter()
+            .map(|file| FileStatus::of(&extract_track(file, &ExtractOptions::default()).unwrap()))
+            .collect();
+        assert_eq!(statuses, [FileStatus::Ok, FileStatus::Empty, FileStatus::Error]);
+
+        assert_eq!(
+            progress_event(Path::new("music/a \"b\".mp3"), FileStatus::Empty, 2, 9),
+            r#"{"event":"file","path":"music/a \"b\".mp3","status":"empty","index":2,"total":9}"#
+        );
+    }
+
+    #[test]
+    fn test_extract_all_lyrics_parallel_keeps_order() {
+        let temp_dir = tempdir().unwrap();
+        let mp3_files: Vec<PathBuf> = (0..16)
+            .map(|i| create_test_mp3(temp_dir.path(), &format!("test{}.mp3", i), Some(&format!("Lyrics for song {}", i))))
+            .collect();
+        
+        let serial = extract_all_lyrics(&mp3_files, &ExtractOptions::default(), 1).unwrap();
+        let parallel = extract_all_lyrics(&mp3_files, &ExtractOptions::default(), 4).unwrap();
+        
+        assert_eq!(serial, parallel);
+        for (i, result) in parallel.iter().enumerate() {
+            assert_eq!(result.path, mp3_files[i]);
+        }
+    }
+}
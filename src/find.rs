@@ -0,0 +1,924 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::SystemTime;
+use clap::ValueEnum;
+use encoding_rs::WINDOWS_1252;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::{WalkBuilder, WalkState};
+use log::{debug, info, warn};
+use crate::error::{Error, Result};
+use crate::extract::TrackResult;
+use crate::source::{source_for_path, TrackMetadata};
+
+/// Extensions matched when neither `--pattern` nor `--ext` is given
+pub const DEFAULT_EXTENSIONS: &[&str] = &["mp3", "flac", "m4a", "ogg", "wav"];
+
+/// Name of the gitignore-style file in an input directory listing paths to skip
+pub const IGNORE_FILE_NAME: &str = ".lyricsignore";
+
+/// Settings that control which files are picked up from the input path
+#[derive(Debug, Clone)]
+pub struct FindOptions {
+    /// Descend into subdirectories
+    pub recursive: bool,
+    /// Follow symlinked directories, skipping any that lead to a cycle
+    pub follow_symlinks: bool,
+    /// Skip paths listed in a [`IGNORE_FILE_NAME`] file in the searched directory
+    pub use_ignore_file: bool,
+    /// File name matcher, usually built with [`build_matcher`]
+    pub matcher: GlobSet,
+}
+
+impl Default for FindOptions {
+    fn default() -> Self {
+        FindOptions {
+            recursive: false,
+            follow_symlinks: false,
+            use_ignore_file: true,
+            matcher: build_matcher(&[], &[]).expect("default patterns are valid globs"),
+        }
+    }
+}
+
+/// Order in which found files are processed and written out
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SortOrder {
+    /// By file name, ignoring the directory
+    #[default]
+    Name,
+    /// By full path
+    Path,
+    /// By directory, then by the track number in the tags
+    Track,
+    /// As found, which is by full path for a directory (the search is always sorted so runs are
+    /// repeatable) and as listed for a file list or playlist
+    None,
+}
+
+/// Tag that `--group-by` gathers tracks under
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GroupBy {
+    Artist,
+    Album,
+}
+
+impl GroupBy {
+    /// Name of the group for tracks without the tag, which comes after every other group
+    pub const UNKNOWN: &'static str = "Unknown";
+
+    /// The group a track's result belongs in, or `None` for the unknown group
+    pub fn key(self, result: &TrackResult) -> Option<&str> {
+        match self {
+            GroupBy::Artist => result.artist.as_deref(),
+            GroupBy::Album => result.album.as_deref(),
+        }
+    }
+
+    /// Order results by group, keeping their order within each one
+    ///
+    /// Files were already grouped by [`group_audio_files`], so this only moves a file whose tags
+    /// read differently the second time, such as one that failed to extract, to where its heading is.
+    pub fn sort_results(self, results: &mut [TrackResult]) {
+        results.sort_by(|a, b| {
+            let (a, b) = (self.key(a), self.key(b));
+            a.is_none().cmp(&b.is_none()).then(a.cmp(&b))
+        });
+    }
+}
+
+/// Build the file name matcher from `--pattern` globs and extra `--ext` extensions
+pub fn build_matcher(patterns: &[String], extensions: &[String]) -> Result<GlobSet> {
+    let mut globs: Vec<String> = if patterns.is_empty() {
+        DEFAULT_EXTENSIONS.iter().map(|ext| format!("*.{}", ext)).collect()
+    } else {
+        patterns.to_vec()
+    };
+    globs.extend(extensions.iter().map(|ext| format!("*.{}", ext.trim_start_matches('.'))));
+    compile_globs(&globs)
+}
+
+/// Build the matcher for `--exclude` globs, which matches nothing when there are none
+pub fn build_exclude_matcher(patterns: &[String]) -> Result<GlobSet> {
+    compile_globs(patterns)
+}
+
+/// Compile `globs` into one set, naming the bad pattern if any fails to parse
+fn compile_globs(globs: &[String]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs {
+        let compiled = Glob::new(glob)
+            .map_err(|source| Error::InvalidPattern { pattern: glob.clone(), source })?;
+        builder.add(compiled);
+    }
+    builder
+        .build()
+        .map_err(|source| Error::InvalidPattern { pattern: globs.join(", "), source })
+}
+
+/// Check a file's name against the configured matcher
+fn matches_file_name(matcher: &GlobSet, path: &Path) -> bool {
+    path.file_name().is_some_and(|name| matcher.is_match(name))
+}
+
+/// Load the ignore file from `dir`, if it has one
+///
+/// Patterns are matched relative to `dir`, as git does for a `.gitignore`.
+fn load_ignore_file(dir: &Path) -> Result<Option<Gitignore>> {
+    let ignore_path = dir.join(IGNORE_FILE_NAME);
+    if !ignore_path.is_file() {
+        return Ok(None);
+    }
+    let ignore_error = |source| Error::IgnoreFile { path: ignore_path.clone(), source };
+    let mut builder = GitignoreBuilder::new(dir);
+    if let Some(e) = builder.add(&ignore_path) {
+        return Err(ignore_error(e));
+    }
+    let ignore = builder.build().map_err(ignore_error)?;
+    debug!("Loaded {} patterns from {}", ignore.num_ignores(), ignore_path.display());
+    Ok(Some(ignore))
+}
+
+/// The symlink and the directory it leads back to, if a walk error is a symlink cycle
+fn symlink_loop(error: &ignore::Error) -> Option<(&Path, &Path)> {
+    match error {
+        ignore::Error::Loop { ancestor, child } => Some((child, ancestor)),
+        ignore::Error::WithPath { err, .. }
+        | ignore::Error::WithDepth { err, .. }
+        | ignore::Error::WithLineNumber { err, .. } => symlink_loop(err),
+        _ => None,
+    }
+}
+
+/// Lock state shared between walker threads, carrying on if another thread panicked
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Find supported audio files in the given path
+///
+/// Files in a directory come back sorted by path, compared component by component, so the order
+/// is the same whatever filesystem or platform the directory is on.
+pub fn find_audio_files(path: &Path, options: &FindOptions) -> Result<Vec<PathBuf>> {
+    let mut audio_files = Vec::new();
+    let follow_symlinks = options.follow_symlinks;
+
+    if path.is_file() {
+        if matches_file_name(&options.matcher, path) {
+            audio_files.push(path.to_path_buf());
+        } else {
+            return Err(Error::UnsupportedFile(path.to_path_buf()));
+        }
+    } else if path.is_dir() {
+        let mut walker = WalkBuilder::new(path);
+        walker
+            .standard_filters(false)
+            .follow_links(follow_symlinks)
+            .max_depth(if options.recursive { None } else { Some(1) });
+        if let Some(ignore) = if options.use_ignore_file { load_ignore_file(path)? } else { None } {
+            walker.filter_entry(move |entry| {
+                let is_dir = entry.file_type().is_some_and(|file_type| file_type.is_dir());
+                let ignored = entry.depth() > 0 && ignore.matched(entry.path(), is_dir).is_ignore();
+                if ignored {
+                    debug!("Skipping ignored {}", entry.path().display());
+                }
+                !ignored
+            });
+        }
+
+        // Directories are read on several threads, which helps most on network mounts
+        let found = Mutex::new(Vec::new());
+        let visited_dirs = Mutex::new(HashSet::new());
+        walker.build_parallel().run(|| {
+            Box::new(|entry| {
+                let entry = match entry {
+                    Ok(entry) => entry,
+                    Err(e) => {
+                        if let Some((child, ancestor)) = symlink_loop(&e) {
+                            warn!("Skipping symlink cycle at {} (points back to {})", child.display(), ancestor.display());
+                        }
+                        return WalkState::Continue;
+                    }
+                };
+                let path = entry.path();
+
+                // Guard against entering the same directory twice through different links
+                if follow_symlinks && entry.file_type().is_some_and(|file_type| file_type.is_dir()) {
+                    let canonical = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+                    if !lock(&visited_dirs).insert(canonical) {
+                        warn!("Skipping already visited directory {}", path.display());
+                        return WalkState::Skip;
+                    }
+                }
+
+                if path.is_file() && matches_file_name(&options.matcher, path) {
+                    lock(&found).push(path.to_path_buf());
+                    debug!("Found audio file: {}", path.display());
+                }
+                WalkState::Continue
+            })
+        });
+
+        // Threads finish in no particular order, so sort to keep runs repeatable
+        audio_files = found.into_inner().unwrap_or_else(PoisonError::into_inner);
+        audio_files.sort();
+    } else {
+        return Err(Error::PathNotFound(path.to_path_buf()));
+    }
+
+    Ok(dedup_audio_files(audio_files))
+}
+
+/// Drop paths that lead to a file already in the list, keeping the first path seen for each
+///
+/// Paths are compared by their canonical form, which catches links and `..` components as well as
+/// different spellings of the same name on case-insensitive filesystems.
+pub fn dedup_audio_files(paths: Vec<PathBuf>) -> Vec<PathBuf> {
+    let mut seen = HashSet::new();
+    paths
+        .into_iter()
+        .filter(|path| {
+            let canonical = fs::canonicalize(path).unwrap_or_else(|_| path.clone());
+            let first = seen.insert(canonical);
+            if !first {
+                debug!("Skipping duplicate {}", path.display());
+            }
+            first
+        })
+        .collect()
+}
+
+/// Keep the candidate paths that exist and match the configured file patterns
+///
+/// Used for externally generated file lists, so unusable entries are skipped with a warning
+/// instead of failing the whole run.
+pub fn filter_audio_files<I>(candidates: I, options: &FindOptions) -> Vec<PathBuf>
+where
+    I: IntoIterator<Item = PathBuf>,
+{
+    let files = candidates
+        .into_iter()
+        .filter(|path| {
+            if !path.is_file() {
+                warn!("Skipping {}: not an existing file", path.display());
+                false
+            } else if !matches_file_name(&options.matcher, path) {
+                warn!("Skipping {}: not a supported audio file", path.display());
+                false
+            } else {
+                true
+            }
+        })
+        .collect();
+    dedup_audio_files(files)
+}
+
+/// Artist and genre values a file's tags must contain to be processed
+///
+/// Values of the same kind are OR'd together, and a file must match both kinds when both are given.
+/// Matching is a case-insensitive substring search.
+#[derive(Debug, Default, Clone)]
+pub struct TagFilter {
+    pub artists: Vec<String>,
+    pub genres: Vec<String>,
+}
+
+impl TagFilter {
+    /// Whether the filter lets every file through
+    pub fn is_empty(&self) -> bool {
+        self.artists.is_empty() && self.genres.is_empty()
+    }
+
+    fn matches(&self, metadata: &TrackMetadata) -> bool {
+        let contains_any = |value: &Option<String>, needles: &[String]| {
+            if needles.is_empty() {
+                return true;
+            }
+            let Some(value) = value else { return false };
+            let value = value.to_lowercase();
+            needles.iter().any(|needle| value.contains(&needle.to_lowercase()))
+        };
+        contains_any(&metadata.artist, &self.artists) && contains_any(&metadata.genre, &self.genres)
+    }
+}
+
+/// Keep the files whose artist and genre tags match `filter`, reading ID3 tags of up to
+/// `max_tag_bytes`
+///
+/// Returns the files kept and how many were dropped because their tags couldn't be read.
+pub fn filter_by_tags(files: Vec<PathBuf>, filter: &TagFilter, max_tag_bytes: u64) -> (Vec<PathBuf>, usize) {
+    if filter.is_empty() {
+        return (files, 0);
+    }
+    let mut unreadable = 0;
+    let kept = files
+        .into_iter()
+        .filter(|path| match source_for_path(path).read_metadata(path, max_tag_bytes) {
+            Ok(metadata) if filter.matches(&metadata) => true,
+            Ok(_) => {
+                debug!("Skipping {}: artist or genre doesn't match the filter", path.display());
+                false
+            }
+            Err(e) => {
+                warn!("Skipping {}: can't read its tags to filter by them: {}", path.display(), e);
+                unreadable += 1;
+                false
+            }
+        })
+        .collect();
+    (kept, unreadable)
+}
+
+/// Drop files bigger than `max_bytes`, returning the files kept and how many were dropped
+///
+/// Only the file size is looked at, so this is cheap to run before anything reads the tags.
+pub fn filter_by_size(files: Vec<PathBuf>, max_bytes: u64) -> (Vec<PathBuf>, usize) {
+    let (kept, skipped): (Vec<PathBuf>, Vec<PathBuf>) = files.into_iter().partition(|path| {
+        match fs::metadata(path) {
+            Ok(metadata) if metadata.len() > max_bytes => {
+                info!("Skipping {}: {} bytes is over the size limit", path.display(), metadata.len());
+                false
+            }
+            // Unreadable files are left for extraction to report
+            _ => true,
+        }
+    });
+    (kept, skipped.len())
+}
+
+/// Drop files whose name matches `exclude`, returning the files kept and how many were dropped
+pub fn filter_excluded(files: Vec<PathBuf>, exclude: &GlobSet) -> (Vec<PathBuf>, usize) {
+    let (kept, excluded): (Vec<PathBuf>, Vec<PathBuf>) = files.into_iter().partition(|path| {
+        let matched = matches_file_name(exclude, path);
+        if matched {
+            debug!("Excluding {}", path.display());
+        }
+        !matched
+    });
+    (kept, excluded.len())
+}
+
+/// Drop files last modified before `since`, returning the files kept and how many were dropped
+pub fn filter_by_mtime(files: Vec<PathBuf>, since: SystemTime) -> (Vec<PathBuf>, usize) {
+    let (kept, skipped): (Vec<PathBuf>, Vec<PathBuf>) = files.into_iter().partition(|path| {
+        match fs::metadata(path).and_then(|metadata| metadata.modified()) {
+            Ok(modified) if modified < since => {
+                debug!("Skipping {}: not modified since {}", path.display(), humantime::format_rfc3339_seconds(since));
+                false
+            }
+            // Files whose modification time can't be read are kept, as for filter_by_size
+            _ => true,
+        }
+    });
+    (kept, skipped.len())
+}
+
+/// Sort audio files in place
+///
+/// Ties are broken by the full path, and files without a track number sort after those with one.
+/// Sorting by track reads ID3 tags of up to `max_tag_bytes`.
+pub fn sort_audio_files(files: &mut [PathBuf], order: SortOrder, max_tag_bytes: u64) {
+    match order {
+        SortOrder::Name => files.sort_by(|a, b| a.file_name().cmp(&b.file_name()).then_with(|| a.cmp(b))),
+        SortOrder::Path => files.sort(),
+        SortOrder::Track => files.sort_by_cached_key(|path| {
+            let track = source_for_path(path).read_metadata(path, max_tag_bytes).ok().and_then(|m| m.track_number);
+            (path.parent().map(Path::to_path_buf), track.is_none(), track, path.clone())
+        }),
+        SortOrder::None => {}
+    }
+}
+
+/// Sort audio files into `group_by` groups, ordered by name, and by track number then title within
+/// each group
+///
+/// Files without the tag go last, and ties are broken by the full path. ID3 tags of up to
+/// `max_tag_bytes` are read.
+pub fn group_audio_files(files: &mut [PathBuf], group_by: GroupBy, max_tag_bytes: u64) {
+    files.sort_by_cached_key(|path| {
+        let metadata = source_for_path(path).read_metadata(path, max_tag_bytes).unwrap_or_default();
+        let group = match group_by {
+            GroupBy::Artist => metadata.artist,
+            GroupBy::Album => metadata.album,
+        };
+        let track = metadata.track_number;
+        (group.is_none(), group, track.is_none(), track, metadata.title, path.clone())
+    });
+}
+
+/// Read a list of paths from `list_path` (or stdin when it is `-`)
+///
+/// Entries are one per line, or NUL-terminated as produced by `find -print0` when
+/// `null_delimited` is set.
+pub fn read_file_list(list_path: &Path, null_delimited: bool) -> Result<Vec<PathBuf>> {
+    let paths = if list_path == Path::new("-") {
+        parse_file_list(io::stdin().lock(), null_delimited)
+    } else {
+        fs::File::open(list_path).and_then(|file| parse_file_list(BufReader::new(file), null_delimited))
+    };
+    paths.map_err(|e| Error::io("Failed to read file list from", list_path, e))
+}
+
+/// Read a playlist exported as a JSON array of paths, keeping its order
+///
+/// Relative entries are taken from the playlist's own folder.
+pub fn read_playlist(playlist_path: &Path) -> Result<Vec<PathBuf>> {
+    let json = fs::read(playlist_path).map_err(|e| Error::io("Failed to read playlist", playlist_path, e))?;
+    let entries: Vec<PathBuf> =
+        serde_json::from_slice(&json).map_err(|source| Error::Playlist { path: playlist_path.to_path_buf(), source })?;
+    let base = playlist_path.parent().unwrap_or(Path::new(""));
+    Ok(entries.into_iter().map(|entry| base.join(entry)).collect())
+}
+
+/// Read the entries of an `.m3u` or `.m3u8` playlist, keeping its order
+///
+/// Comment and directive lines such as `#EXTINF` are skipped, and relative entries are taken from
+/// the playlist's own folder. Playlists that aren't UTF-8 are read as Windows-1252, as older
+/// players write them.
+pub fn read_m3u_playlist(playlist_path: &Path) -> Result<Vec<PathBuf>> {
+    let bytes = fs::read(playlist_path).map_err(|e| Error::io("Failed to read playlist", playlist_path, e))?;
+    let text = match std::str::from_utf8(&bytes) {
+        Ok(text) => Cow::Borrowed(text),
+        Err(_) => WINDOWS_1252.decode_without_bom_handling(&bytes).0,
+    };
+    let base = playlist_path.parent().unwrap_or(Path::new(""));
+    Ok(text
+        .trim_start_matches('\u{FEFF}')
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|entry| base.join(entry))
+        .collect())
+}
+
+/// Split a file list into paths on newlines or NUL bytes
+fn parse_file_list(mut reader: impl BufRead, null_delimited: bool) -> io::Result<Vec<PathBuf>> {
+    let delimiter = if null_delimited { b'\0' } else { b'\n' };
+    let mut paths = Vec::new();
+    let mut entry = Vec::new();
+
+    loop {
+        entry.clear();
+        if reader.read_until(delimiter, &mut entry)? == 0 {
+            break;
+        }
+        if entry.last() == Some(&delimiter) {
+            entry.pop();
+        }
+        if !null_delimited && entry.last() == Some(&b'\r') {
+            entry.pop();
+        }
+        // Names may contain spaces, so only completely empty entries are ignored
+        if !entry.is_empty() {
+            paths.push(path_from_bytes(&entry));
+        }
+    }
+
+    Ok(paths)
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStringExt;
+    PathBuf::from(OsString::from_vec(bytes.to_vec()))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(OsString::from(String::from_utf8_lossy(bytes).into_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extract::DEFAULT_MAX_TAG_BYTES;
+    use crate::test_util::{create_test_flac, create_test_m4a, create_test_mp3, create_test_ogg, create_test_wav};
+    use std::fs::File;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_find_audio_files_single_file() {
+        let temp_dir = tempdir().unwrap();
+        let mp3_path = create_test_mp3(temp_dir.path(), "test.mp3", None);
+        
+        let files = find_audio_files(&mp3_path, &FindOptions::default()).unwrap();
+        
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0], mp3_path);
+    }
+
+    #[test]
+    fn test_find_audio_files_directory() {
+        let temp_dir = tempdir().unwrap();
+        let mp3_path1 = create_test_mp3(temp_dir.path(), "test1.mp3", None);
+        let mp3_path2 = create_test_mp3(temp_dir.path(), "test2.mp3", None);
+        
+        // Create a non-MP3 file
+        let txt_path = temp_dir.path().join("test.txt");
+        File::create(&txt_path).unwrap();
+        
+        let files = find_audio_files(temp_dir.path(), &FindOptions::default()).unwrap();
+        
+        assert_eq!(files, [mp3_path1, mp3_path2]);
+    }
+
+    #[test]
+    fn test_find_audio_files_large_tree() {
+        // Enough directories for several walker threads to be busy at once
+        let temp_dir = tempdir().unwrap();
+        let mut expected = Vec::new();
+        let mut top_level = Vec::new();
+        for artist in 0..8 {
+            let artist_dir = temp_dir.path().join(format!("artist{}", artist));
+            top_level.push(create_test_mp3(temp_dir.path(), &format!("single{}.mp3", artist), None));
+            for album in 0..8 {
+                let album_dir = artist_dir.join(format!("album{}", album)).join("disc1");
+                fs::create_dir_all(&album_dir).unwrap();
+                for track in 0..6 {
+                    expected.push(create_test_mp3(&album_dir, &format!("track{}.mp3", track), None));
+                }
+                fs::write(album_dir.join("cover.jpg"), b"not audio").unwrap();
+            }
+        }
+        expected.extend(top_level.iter().cloned());
+        expected.sort();
+        top_level.sort();
+
+        let recursive = FindOptions { recursive: true, ..Default::default() };
+        let files = find_audio_files(temp_dir.path(), &recursive).unwrap();
+        assert_eq!(files.len(), 8 * 8 * 6 + 8);
+        assert_eq!(files, expected);
+        assert_eq!(find_audio_files(temp_dir.path(), &recursive).unwrap(), files);
+        assert_eq!(find_audio_files(temp_dir.path(), &FindOptions::default()).unwrap(), top_level);
+    }
+
+    #[test]
+    fn test_find_audio_files_recursive() {
+        let temp_dir = tempdir().unwrap();
+        let mp3_path1 = create_test_mp3(temp_dir.path(), "test1.mp3", None);
+        
+        // Create a subdirectory
+        let sub_dir = temp_dir.path().join("subdir");
+        fs::create_dir(&sub_dir).unwrap();
+        let mp3_path2 = create_test_mp3(&sub_dir, "test2.mp3", None);
+        
+        // Test non-recursive (should find only one file)
+        let files_non_recursive = find_audio_files(temp_dir.path(), &FindOptions::default()).unwrap();
+        assert_eq!(files_non_recursive, vec![mp3_path1.clone()]);
+        
+        // Test recursive (should find both files)
+        let recursive = FindOptions { recursive: true, ..Default::default() };
+        let files_recursive = find_audio_files(temp_dir.path(), &recursive).unwrap();
+        assert_eq!(files_recursive, [mp3_path2, mp3_path1]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_find_audio_files_follow_symlinks() {
+        use std::os::unix::fs::symlink;
+        
+        let temp_dir = tempdir().unwrap();
+        let library = temp_dir.path().join("library");
+        let album = library.join("album");
+        fs::create_dir_all(&album).unwrap();
+        create_test_mp3(&album, "test1.mp3", None);
+        
+        // A linked folder outside the library, plus a link back to the library root
+        let elsewhere = temp_dir.path().join("elsewhere");
+        fs::create_dir(&elsewhere).unwrap();
+        create_test_mp3(&elsewhere, "test2.mp3", None);
+        symlink(&elsewhere, library.join("linked")).unwrap();
+        symlink(&library, album.join("cycle")).unwrap();
+        
+        let recursive = FindOptions { recursive: true, ..Default::default() };
+        let without_links = find_audio_files(&library, &recursive).unwrap();
+        assert_eq!(without_links.len(), 1);
+        
+        let follow = FindOptions { recursive: true, follow_symlinks: true, ..Default::default() };
+        let with_links = find_audio_files(&library, &follow).unwrap();
+        assert_eq!(with_links, [album.join("test1.mp3"), library.join("linked/test2.mp3")]);
+    }
+
+    #[test]
+    fn test_find_audio_files_ignore_file() {
+        let temp_dir = tempdir().unwrap();
+        let keep = create_test_mp3(temp_dir.path(), "keep.mp3", None);
+        create_test_mp3(temp_dir.path(), "demo.mp3", None);
+        let scratch = temp_dir.path().join("scratch");
+        fs::create_dir(&scratch).unwrap();
+        create_test_mp3(&scratch, "take1.mp3", None);
+        let album = temp_dir.path().join("album");
+        fs::create_dir(&album).unwrap();
+        let track = create_test_mp3(&album, "track.mp3", None);
+        fs::write(temp_dir.path().join(IGNORE_FILE_NAME), "/scratch/\ndemo.mp3\n").unwrap();
+
+        let recursive = FindOptions { recursive: true, ..Default::default() };
+        let files = find_audio_files(temp_dir.path(), &recursive).unwrap();
+        assert_eq!(files, [track, keep]);
+
+        let no_ignore = FindOptions { recursive: true, use_ignore_file: false, ..Default::default() };
+        assert_eq!(find_audio_files(temp_dir.path(), &no_ignore).unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_find_audio_files_errors() {
+        let temp_dir = tempdir().unwrap();
+        let missing = temp_dir.path().join("missing");
+        let txt_path = temp_dir.path().join("notes.txt");
+        File::create(&txt_path).unwrap();
+        
+        let options = FindOptions::default();
+        assert!(matches!(find_audio_files(&missing, &options), Err(Error::PathNotFound(p)) if p == missing));
+        assert!(matches!(find_audio_files(&txt_path, &options), Err(Error::UnsupportedFile(p)) if p == txt_path));
+    }
+
+    #[test]
+    fn test_parse_file_list() {
+        let newline_list = "music/My Song.mp3\r\nother/track.flac\n\n  spaced  .mp3\n";
+        let paths = parse_file_list(newline_list.as_bytes(), false).unwrap();
+        assert_eq!(paths, vec![
+            PathBuf::from("music/My Song.mp3"),
+            PathBuf::from("other/track.flac"),
+            PathBuf::from("  spaced  .mp3"),
+        ]);
+        
+        let null_list = "line\nbreak.mp3\0plain.mp3\0";
+        let paths = parse_file_list(null_list.as_bytes(), true).unwrap();
+        assert_eq!(paths, vec![PathBuf::from("line\nbreak.mp3"), PathBuf::from("plain.mp3")]);
+    }
+
+    #[test]
+    fn test_read_playlist() {
+        let temp_dir = tempdir().unwrap();
+        let absolute = create_test_mp3(temp_dir.path(), "b.mp3", None);
+        let playlist = temp_dir.path().join("playlist.json");
+        let json = serde_json::json!(["sub/z.mp3", absolute, "a.mp3"]);
+        fs::write(&playlist, json.to_string()).unwrap();
+        
+        let paths = read_playlist(&playlist).unwrap();
+        assert_eq!(paths, vec![temp_dir.path().join("sub/z.mp3"), absolute, temp_dir.path().join("a.mp3")]);
+        
+        fs::write(&playlist, r#"{"tracks": []}"#).unwrap();
+        assert!(matches!(read_playlist(&playlist), Err(Error::Playlist { .. })));
+    }
+
+    #[test]
+    fn test_read_m3u_playlist() {
+        let temp_dir = tempdir().unwrap();
+        let absolute = create_test_mp3(temp_dir.path(), "b.mp3", None);
+        let playlist = temp_dir.path().join("playlist.m3u8");
+        let m3u = format!(
+            "\u{FEFF}#EXTM3U\r\n#EXTINF:215,Band - Second\r\nsub/z.mp3\r\n\r\n#EXTINF:180,Band - First\r\n{}\r\nCafé.mp3\r\n",
+            absolute.display()
+        );
+        fs::write(&playlist, m3u).unwrap();
+        let expected = vec![temp_dir.path().join("sub/z.mp3"), absolute, temp_dir.path().join("Café.mp3")];
+        assert_eq!(read_m3u_playlist(&playlist).unwrap(), expected);
+
+        // Older players write the local code page rather than UTF-8
+        let legacy = temp_dir.path().join("legacy.m3u");
+        fs::write(&legacy, b"#EXTM3U\nCaf\xe9.mp3\n").unwrap();
+        assert_eq!(read_m3u_playlist(&legacy).unwrap(), vec![temp_dir.path().join("Café.mp3")]);
+    }
+
+    #[test]
+    fn test_filter_audio_files() {
+        let temp_dir = tempdir().unwrap();
+        let mp3_path = create_test_mp3(temp_dir.path(), "with space.mp3", None);
+        let txt_path = temp_dir.path().join("notes.txt");
+        File::create(&txt_path).unwrap();
+        let missing = temp_dir.path().join("missing.mp3");
+        
+        let files = filter_audio_files(vec![missing, txt_path, mp3_path.clone()], &FindOptions::default());
+        assert_eq!(files, vec![mp3_path]);
+    }
+
+    #[test]
+    fn test_dedup_audio_files() {
+        let temp_dir = tempdir().unwrap();
+        let sub_dir = temp_dir.path().join("sub");
+        fs::create_dir(&sub_dir).unwrap();
+        let first = create_test_mp3(temp_dir.path(), "a.mp3", None);
+        let second = create_test_mp3(temp_dir.path(), "b.mp3", None);
+        
+        // The same files spelled differently, which works regardless of filesystem case sensitivity
+        let again = sub_dir.join("..").join("a.mp3");
+        let dotted = temp_dir.path().join(".").join("b.mp3");
+        
+        let files = filter_audio_files(vec![first.clone(), again, second.clone(), dotted, first.clone()], &FindOptions::default());
+        assert_eq!(files, vec![first, second]);
+    }
+
+    #[test]
+    fn test_find_audio_files_pattern_and_ext() {
+        let temp_dir = tempdir().unwrap();
+        let live = create_test_mp3(temp_dir.path(), "Live at Home.mp3", None);
+        let studio = create_test_mp3(temp_dir.path(), "Studio.mp3", None);
+        let mp2 = create_test_mp3(temp_dir.path(), "Old.mp2", None);
+        let dir = temp_dir.path();
+        
+        let with_matcher = |patterns: &[&str], extensions: &[&str]| {
+            let patterns: Vec<String> = patterns.iter().map(|p| p.to_string()).collect();
+            let extensions: Vec<String> = extensions.iter().map(|e| e.to_string()).collect();
+            let options = FindOptions { matcher: build_matcher(&patterns, &extensions).unwrap(), ..Default::default() };
+            find_audio_files(dir, &options).unwrap()
+        };
+        
+        let live_only = with_matcher(&["Live*.mp3"], &[]);
+        assert_eq!(live_only, vec![live.clone()]);
+        
+        let with_mp2 = with_matcher(&[], &["mp2"]);
+        assert_eq!(with_mp2, [live, mp2, studio]);
+        
+        assert!(build_matcher(&["[".to_string()], &[]).is_err());
+    }
+
+    #[test]
+    fn test_filter_excluded() {
+        let paths = vec![
+            PathBuf::from("album/Song.mp3"),
+            PathBuf::from("album/Song (Remix).mp3"),
+            PathBuf::from("remixes/Other.mp3"),
+            PathBuf::from("album/Live.flac"),
+        ];
+
+        let exclude = build_exclude_matcher(&["*Remix*".to_string(), "*.flac".to_string()]).unwrap();
+        let (kept, excluded) = filter_excluded(paths.clone(), &exclude);
+        assert_eq!(kept, [paths[0].clone(), paths[2].clone()]);
+        assert_eq!(excluded, 2);
+
+        let (kept, excluded) = filter_excluded(paths.clone(), &build_exclude_matcher(&[]).unwrap());
+        assert_eq!((kept, excluded), (paths, 0));
+        assert!(build_exclude_matcher(&["[".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_sort_audio_files() {
+        use id3::TagLike;
+
+        let temp_dir = tempdir().unwrap();
+        let album = temp_dir.path().join("album");
+        fs::create_dir(&album).unwrap();
+        let mut paths = Vec::new();
+        for (filename, track) in [("b.mp3", Some(1)), ("a.mp3", Some(2)), ("c.mp3", None)] {
+            let path = create_test_mp3(&album, filename, None);
+            if let Some(track) = track {
+                let mut tag = id3::Tag::new();
+                tag.set_track(track);
+                tag.write_to_path(&path, id3::Version::Id3v24).unwrap();
+            }
+            paths.push(path);
+        }
+        let flac_path = create_test_flac(temp_dir.path(), "a.flac", Some(("TRACKNUMBER", "7/12")));
+        paths.push(flac_path.clone());
+        let names = |files: &[PathBuf]| -> Vec<String> {
+            files.iter().map(|p| p.file_name().unwrap().to_string_lossy().into_owned()).collect()
+        };
+
+        let mut files = paths.clone();
+        sort_audio_files(&mut files, SortOrder::None, DEFAULT_MAX_TAG_BYTES);
+        assert_eq!(files, paths);
+
+        sort_audio_files(&mut files, SortOrder::Name, DEFAULT_MAX_TAG_BYTES);
+        assert_eq!(names(&files), ["a.flac", "a.mp3", "b.mp3", "c.mp3"]);
+
+        sort_audio_files(&mut files, SortOrder::Path, DEFAULT_MAX_TAG_BYTES);
+        assert_eq!(files[0], flac_path);
+        assert_eq!(names(&files[1..]), ["a.mp3", "b.mp3", "c.mp3"]);
+
+        sort_audio_files(&mut files, SortOrder::Track, DEFAULT_MAX_TAG_BYTES);
+        assert_eq!(names(&files), ["a.flac", "b.mp3", "a.mp3", "c.mp3"]);
+    }
+
+    #[test]
+    fn test_group_audio_files() {
+        use id3::TagLike;
+
+        let temp_dir = tempdir().unwrap();
+        let mut paths = Vec::new();
+        for (filename, artist, track, title) in [
+            ("1.mp3", Some("Zed"), Some(2), "Second"),
+            ("2.mp3", None, Some(1), "Untagged"),
+            ("3.mp3", Some("Abba"), None, "B side"),
+            ("4.mp3", Some("Zed"), Some(1), "First"),
+            ("5.mp3", Some("Abba"), None, "A side"),
+        ] {
+            let path = create_test_mp3(temp_dir.path(), filename, None);
+            let mut tag = id3::Tag::new();
+            tag.set_title(title);
+            if let Some(artist) = artist {
+                tag.set_artist(artist);
+            }
+            if let Some(track) = track {
+                tag.set_track(track);
+            }
+            tag.write_to_path(&path, id3::Version::Id3v24).unwrap();
+            paths.push(path);
+        }
+        let names = |files: &[PathBuf]| -> Vec<String> {
+            files.iter().map(|p| p.file_name().unwrap().to_string_lossy().into_owned()).collect()
+        };
+
+        group_audio_files(&mut paths, GroupBy::Artist, DEFAULT_MAX_TAG_BYTES);
+        assert_eq!(names(&paths), ["5.mp3", "3.mp3", "4.mp3", "1.mp3", "2.mp3"]);
+
+        let result = |artist: Option<&str>| TrackResult { artist: artist.map(str::to_string), ..Default::default() };
+        let mut results = [result(None), result(Some("Zed")), result(Some("Abba"))];
+        GroupBy::Artist.sort_results(&mut results);
+        let keys: Vec<Option<&str>> = results.iter().map(|result| GroupBy::Artist.key(result)).collect();
+        assert_eq!(keys, [Some("Abba"), Some("Zed"), None]);
+    }
+
+    #[test]
+    fn test_filter_by_size() {
+        let temp_dir = tempdir().unwrap();
+        let small = temp_dir.path().join("small.mp3");
+        let large = temp_dir.path().join("large.mp3");
+        fs::write(&small, [0; 100]).unwrap();
+        fs::write(&large, [0; 101]).unwrap();
+        let missing = temp_dir.path().join("missing.mp3");
+
+        let (kept, skipped) = filter_by_size(vec![small.clone(), large, missing.clone()], 100);
+        assert_eq!(kept, vec![small, missing]);
+        assert_eq!(skipped, 1);
+    }
+
+    #[test]
+    fn test_filter_by_mtime() {
+        use std::time::Duration;
+
+        let temp_dir = tempdir().unwrap();
+        let old = temp_dir.path().join("old.mp3");
+        let new = temp_dir.path().join("new.mp3");
+        fs::write(&old, [0; 10]).unwrap();
+        fs::write(&new, [0; 10]).unwrap();
+        let now = SystemTime::now();
+        fs::File::options().write(true).open(&old).unwrap().set_modified(now - Duration::from_secs(3600)).unwrap();
+        let missing = temp_dir.path().join("missing.mp3");
+
+        let (kept, skipped) = filter_by_mtime(vec![old, new.clone(), missing.clone()], now - Duration::from_secs(60));
+        assert_eq!(kept, vec![new, missing]);
+        assert_eq!(skipped, 1);
+    }
+
+    #[test]
+    fn test_filter_by_tags() {
+        use id3::TagLike;
+
+        let temp_dir = tempdir().unwrap();
+        let mut paths = Vec::new();
+        for (filename, artist, genre) in [("a.mp3", "The Beatles", "Rock"), ("b.mp3", "Björk", "(52)")] {
+            let path = create_test_mp3(temp_dir.path(), filename, None);
+            let mut tag = id3::Tag::new();
+            tag.set_artist(artist);
+            tag.set_genre(genre);
+            tag.write_to_path(&path, id3::Version::Id3v24).unwrap();
+            paths.push(path);
+        }
+        let flac_path = create_test_flac(temp_dir.path(), "c.flac", Some(("ARTIST", "Beatles tribute")));
+        paths.push(flac_path.clone());
+        let untagged = create_test_mp3(temp_dir.path(), "d.mp3", None);
+        paths.push(untagged);
+
+        let tag_filter = |artists: &[&str], genres: &[&str]| TagFilter {
+            artists: artists.iter().map(|a| a.to_string()).collect(),
+            genres: genres.iter().map(|g| g.to_string()).collect(),
+        };
+        let filter = |artists: &[&str], genres: &[&str]| {
+            let (kept, unreadable) = filter_by_tags(paths.clone(), &tag_filter(artists, genres), DEFAULT_MAX_TAG_BYTES);
+            assert_eq!(unreadable, 0);
+            kept
+        };
+
+        assert_eq!(filter(&[], &[]), paths);
+        assert_eq!(filter(&["beatles"], &[]), vec![paths[0].clone(), flac_path.clone()]);
+        assert_eq!(filter(&["BJÖRK", "tribute"], &[]), vec![paths[1].clone(), flac_path]);
+        // Numeric ID3v1 genres are matched by name
+        assert_eq!(filter(&[], &["electronic"]), vec![paths[1].clone()]);
+        assert_eq!(filter(&["beatles"], &["rock"]), vec![paths[0].clone()]);
+
+        // Tags are read up to the given limit, so raising it keeps a file whose tag is over a lower one
+        let beatles = tag_filter(&["beatles"], &["rock"]);
+        assert_eq!(filter_by_tags(paths[..1].to_vec(), &beatles, 16), (vec![], 1));
+        assert_eq!(filter_by_tags(paths[..1].to_vec(), &beatles, 1 << 10), (vec![paths[0].clone()], 0));
+    }
+
+    #[test]
+    fn test_find_audio_files_includes_flac_m4a_ogg_and_wav() {
+        let temp_dir = tempdir().unwrap();
+        let mp3_path = create_test_mp3(temp_dir.path(), "test.mp3", None);
+        let flac_path = create_test_flac(temp_dir.path(), "test.flac", None);
+        let m4a_path = create_test_m4a(temp_dir.path(), "test.m4a", None);
+        let ogg_path = create_test_ogg(temp_dir.path(), "test.ogg", None);
+        let wav_path = create_test_wav(temp_dir.path(), "test.wav", None);
+        
+        let files = find_audio_files(temp_dir.path(), &FindOptions::default()).unwrap();
+        
+        assert_eq!(files, [flac_path, m4a_path, mp3_path, ogg_path, wav_path]);
+    }
+}
@@ -0,0 +1,57 @@
+//! Extract lyrics from MP3, FLAC, M4A, OGG and WAV files.
+//!
+//! [`find_audio_files`] collects the files to process, [`extract_all_lyrics`] reads the lyrics and
+//! metadata from each of them, and the `render_*` functions turn the results into output text.
+
+mod archive;
+mod cache;
+mod error;
+mod extract;
+mod find;
+mod lookup;
+mod output;
+mod remote;
+mod replace;
+mod render;
+mod source;
+mod stats;
+mod template;
+mod validate;
+mod watch;
+
+#[cfg(test)]
+mod test_util;
+
+pub use archive::{is_zip_path, unpack_zip, UnpackedArchive};
+pub use cache::ExtractionCache;
+pub use error::{Error, Result};
+pub use extract::{
+    dump_frames, extract_all_lyrics, extract_lyrics_from_file, progress_event, ExtractOptions, ExtractedTrack,
+    ExtractionSummary, FileStatus, FrameInfo, FramePreference, FrameSelector, LyricsCandidate, LyricsFrame,
+    LyricsSourceKind, ProgressEvents, SyncedLyricsInfo, TimedLine, TextCase, TrackResult, UnicodeForm,
+    DEFAULT_CONCURRENCY_LIMIT, DEFAULT_LOG_INTERVAL, DEFAULT_MAX_TAG_BYTES, DEFAULT_SOURCE_PRIORITY, DEFAULT_TAIL_DURATION,
+    RETRY_BACKOFF,
+};
+pub use find::{
+    build_exclude_matcher, build_matcher, dedup_audio_files, filter_audio_files, filter_by_mtime, filter_by_size,
+    filter_by_tags, filter_excluded, find_audio_files, group_audio_files, read_file_list, read_m3u_playlist, read_playlist,
+    sort_audio_files, FindOptions, GroupBy, SortOrder, TagFilter, DEFAULT_EXTENSIONS, IGNORE_FILE_NAME,
+};
+pub use lookup::{
+    fill_from_sidecars, fill_missing_lyrics, normalize_key, LyricsLookup, SqliteLookup, EXTERNAL_FRAME_ID,
+};
+pub use output::{
+    is_gzip_path, normalize_line_endings, sidecar_path, split_lyrics, write_duplicates_report, write_error_log,
+    write_manifest, write_tag_summary, write_to, write_to_file, LineEnding, LineEndingStream, MirroredDir, OutputWriter,
+    STDOUT_PATH,
+};
+pub use remote::{download, is_remote_url, DownloadedFile, DEFAULT_DOWNLOAD_TIMEOUT};
+pub use replace::Substitution;
+pub use render::{
+    format_header, render_csv, render_json, render_markdown, render_text, render_text_pieces, render_yaml_frontmatter,
+    OutputFormat, TextOptions, DEFAULT_HEADER_FORMAT, DEFAULT_PAGE_WIDTH,
+};
+pub use stats::{LyricsStats, StatsFormat, StatsReport, TrackStats};
+pub use template::Template;
+pub use validate::{FileHealth, HealthCounts, TagHealth, ValidationReport};
+pub use watch::{InputWatcher, DEFAULT_DEBOUNCE};
@@ -0,0 +1,246 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use log::{debug, info, warn};
+use rusqlite::functions::FunctionFlags;
+use rusqlite::{Connection, OpenFlags, OptionalExtension};
+use crate::error::{Error, Result};
+use crate::extract::{clean_external_lyrics, is_instrumental_text, ExtractOptions, LyricsFrame, TrackResult};
+use crate::output::sidecar_path;
+
+/// Frame ID given to lyrics that were filled in from a [`LyricsLookup`], shown by `--annotate`
+pub const EXTERNAL_FRAME_ID: &str = "external";
+
+/// Somewhere to look up lyrics for tracks whose files don't have any
+pub trait LyricsLookup {
+    /// Find the lyrics for a track by its artist and title, as read from the tags
+    fn lookup(&self, artist: &str, title: &str) -> Result<Option<String>>;
+}
+
+/// Looks up lyrics in a SQLite database with a `lyrics (artist, title, lyrics)` table
+///
+/// Artists and titles are compared after [`normalize_key`], so differences in case, punctuation
+/// and spacing don't stop a match.
+pub struct SqliteLookup {
+    connection: Connection,
+    path: PathBuf,
+}
+
+impl SqliteLookup {
+    /// Open the database at `path` read-only
+    pub fn open(path: &Path) -> Result<Self> {
+        let lookup_error = |source| Error::Lookup { path: path.to_path_buf(), source };
+        let connection = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY).map_err(lookup_error)?;
+        let flags = FunctionFlags::SQLITE_DETERMINISTIC | FunctionFlags::SQLITE_UTF8;
+        connection
+            .create_scalar_function("normalize_key", 1, flags, |context| {
+                Ok(context.get::<Option<String>>(0)?.map(|value| normalize_key(&value)))
+            })
+            .map_err(lookup_error)?;
+        Ok(SqliteLookup { connection, path: path.to_path_buf() })
+    }
+}
+
+impl LyricsLookup for SqliteLookup {
+    fn lookup(&self, artist: &str, title: &str) -> Result<Option<String>> {
+        self.connection
+            .query_row(
+                "SELECT lyrics FROM lyrics WHERE normalize_key(artist) = ?1 AND normalize_key(title) = ?2 LIMIT 1",
+                [normalize_key(artist), normalize_key(title)],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(|source| Error::Lookup { path: self.path.clone(), source })
+    }
+}
+
+/// Reduce an artist or title to lowercase words, dropping punctuation, so that e.g. "Hey Jude!"
+/// and "hey  jude" compare equal
+pub fn normalize_key(value: &str) -> String {
+    value
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Fill in lyrics from `lookup` for tracks without any, returning how many were found
+///
+/// Only tracks with both an artist and a title are looked up, and lyrics read from a file are
+/// never replaced. The lyrics found are cleaned up with `options`, the same as ones read from tags.
+pub fn fill_missing_lyrics(
+    results: &mut [TrackResult],
+    lookup: &dyn LyricsLookup,
+    options: &ExtractOptions,
+) -> Result<usize> {
+    let mut filled = 0;
+    for result in results.iter_mut().filter(|result| result.lyrics.is_none() && result.error.is_none()) {
+        let (Some(artist), Some(title)) = (&result.artist, &result.title) else {
+            continue;
+        };
+        let Some(lyrics) = lookup.lookup(artist, title)? else {
+            debug!("No lyrics for {} - {} in the lookup database", artist, title);
+            continue;
+        };
+        if fill_in(result, &lyrics, options) {
+            info!("Found lyrics for {} in the lookup database", result.path.display());
+            filled += 1;
+        }
+    }
+    Ok(filled)
+}
+
+/// Give a track lyrics found outside its file, once cleaned up with `options`, returning whether
+/// any were left to fill in
+fn fill_in(result: &mut TrackResult, lyrics: &str, options: &ExtractOptions) -> bool {
+    let path = result.local_path.as_deref().unwrap_or(&result.path);
+    let Some(lyrics) = clean_external_lyrics(lyrics, options, path) else {
+        return false;
+    };
+    // As for tags, lyrics outweigh an instrumental mark
+    result.instrumental = is_instrumental_text(&lyrics);
+    result.lyrics = Some(lyrics);
+    result.lyrics_frame = Some(LyricsFrame::new(EXTERNAL_FRAME_ID, None));
+    true
+}
+
+/// Fill in lyrics for tracks without any from a `.txt` file of the same name next to the audio
+/// file, returning how many were found
+///
+/// Like [`fill_missing_lyrics`], lyrics read from a file are never replaced, the filled in lyrics
+/// are cleaned up with `options` and they are marked as coming from [`EXTERNAL_FRAME_ID`]. Text
+/// that isn't UTF-8 has its charset detected the same way as tag text, or `options.encoding` used.
+pub fn fill_from_sidecars(results: &mut [TrackResult], options: &ExtractOptions) -> usize {
+    let mut filled = 0;
+    for result in results.iter_mut().filter(|result| result.lyrics.is_none() && result.error.is_none()) {
+        let sidecar = sidecar_path(result.local_path.as_deref().unwrap_or(&result.path));
+        if !sidecar.is_file() {
+            continue;
+        }
+        match fs::read(&sidecar) {
+            Ok(bytes) => {
+                let text = sidecar_text(bytes, options);
+                let lyrics = text.trim_end();
+                if lyrics.trim_start_matches('\u{FEFF}').is_empty() {
+                    debug!("Ignoring empty {}", sidecar.display());
+                    continue;
+                }
+                if fill_in(result, lyrics, options) {
+                    info!("Read lyrics for {} from {}", result.path.display(), sidecar.display());
+                    filled += 1;
+                }
+            }
+            Err(e) => warn!("Failed to read {}: {}", sidecar.display(), e),
+        }
+    }
+    filled
+}
+
+/// The text of a `.txt` file, left for [`clean_external_lyrics`] to decode as it does tag text
+///
+/// UTF-8 is taken as it is, unless another charset was asked for. Anything else is spelled out a
+/// byte per character, like a tag that claims to be Latin-1, so the charset can be detected.
+fn sidecar_text(bytes: Vec<u8>, options: &ExtractOptions) -> String {
+    match String::from_utf8(bytes) {
+        Ok(text) if options.encoding.is_none() => text,
+        Ok(text) => text.bytes().map(char::from).collect(),
+        Err(e) => e.into_bytes().into_iter().map(char::from).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extract::TextCase;
+    use crate::replace::Substitution;
+    use crate::test_util::track;
+    use encoding_rs::WINDOWS_1251;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_normalize_key() {
+        assert_eq!(normalize_key("  Hey Jude! "), "hey jude");
+        assert_eq!(normalize_key("AC/DC"), "ac dc");
+        assert_eq!(normalize_key("Björk"), "björk");
+    }
+
+    #[test]
+    fn test_fill_missing_lyrics() {
+        let temp_dir = tempdir().unwrap();
+        let db_path = temp_dir.path().join("lyrics.db");
+        let connection = Connection::open(&db_path).unwrap();
+        connection.execute_batch(
+            "CREATE TABLE lyrics (artist TEXT, title TEXT, lyrics TEXT);
+             INSERT INTO lyrics VALUES ('The Beatles', 'Hey Jude!', 'Hey Jude, don''t make it bad');",
+        ).unwrap();
+        drop(connection);
+
+        let song = |title: &str, lyrics: Option<&str>| TrackResult {
+            title: Some(title.to_string()),
+            artist: Some("the beatles".to_string()),
+            ..track(format!("{}.mp3", title), lyrics)
+        };
+        let marked = TrackResult { instrumental: true, ..song("Hey Jude", None) };
+        let mut results = [marked, song("Hey Jude", Some("From the file")), song("Help", None)];
+
+        let lookup = SqliteLookup::open(&db_path).unwrap();
+        let options = ExtractOptions::default();
+        assert_eq!(fill_missing_lyrics(&mut results, &lookup, &options).unwrap(), 1);
+        assert_eq!(results[0].lyrics.as_deref(), Some("Hey Jude, don't make it bad"));
+        assert_eq!(results[0].lyrics_frame.as_ref().unwrap().to_string(), EXTERNAL_FRAME_ID);
+        assert!(!results[0].instrumental);
+        assert_eq!(results[1].lyrics.as_deref(), Some("From the file"));
+        assert_eq!(results[2].lyrics, None);
+
+        // The database's lyrics go through the same clean-up as lyrics from tags
+        let options = ExtractOptions { case: Some(TextCase::Upper), first_n_lines: 1, ..Default::default() };
+        let mut results = [song("Hey Jude", None)];
+        fill_missing_lyrics(&mut results, &lookup, &options).unwrap();
+        assert_eq!(results[0].lyrics.as_deref(), Some("HEY JUDE, DON'T MAKE IT BAD"));
+        let too_short = ExtractOptions { min_lyrics_length: 100, ..Default::default() };
+        assert_eq!(fill_missing_lyrics(&mut [song("Hey Jude", None)], &lookup, &too_short).unwrap(), 0);
+
+        assert!(SqliteLookup::open(&temp_dir.path().join("missing.db")).is_err());
+    }
+
+    #[test]
+    fn test_fill_from_sidecars() {
+        let temp_dir = tempdir().unwrap();
+        let in_dir = |name: &str, lyrics: Option<&str>| track(temp_dir.path().join(name), lyrics);
+        fs::write(temp_dir.path().join("one.txt"), "\u{FEFF}From the sidecar\n").unwrap();
+        fs::write(temp_dir.path().join("two.txt"), "Not used").unwrap();
+        fs::write(temp_dir.path().join("blank.txt"), "\n\n").unwrap();
+        let mut results = [
+            in_dir("one.mp3", None),
+            in_dir("two.mp3", Some("From the file")),
+            in_dir("none.mp3", None),
+            in_dir("blank.mp3", None),
+        ];
+
+        let options = ExtractOptions::default();
+        assert_eq!(fill_from_sidecars(&mut results, &options), 1);
+        assert_eq!(results[0].lyrics.as_deref(), Some("From the sidecar"));
+        assert_eq!(results[0].lyrics_frame.as_ref().unwrap().to_string(), EXTERNAL_FRAME_ID);
+        assert_eq!(results[1].lyrics.as_deref(), Some("From the file"));
+        assert_eq!(results[2].lyrics, None);
+        assert_eq!(results[3].lyrics, None);
+
+        // Sidecar lyrics are cleaned up like tag lyrics, and outweigh an instrumental mark
+        let mut results = [TrackResult { instrumental: true, ..in_dir("one.mp3", None) }];
+        let options = ExtractOptions { replacements: vec![Substitution::parse("s/sidecar/txt file/").unwrap()], ..options };
+        assert_eq!(fill_from_sidecars(&mut results, &options), 1);
+        assert_eq!(results[0].lyrics.as_deref(), Some("From the txt file"));
+        assert!(!results[0].instrumental);
+
+        // Other charsets are detected, or can be given
+        let russian = "Я помню чудное мгновенье: передо мной явилась ты, как мимолетное виденье, как гений чистой красоты.";
+        fs::write(temp_dir.path().join("russian.txt"), WINDOWS_1251.encode(russian).0).unwrap();
+        let mut results = [in_dir("russian.mp3", None)];
+        fill_from_sidecars(&mut results, &ExtractOptions::default());
+        assert_eq!(results[0].lyrics.as_deref(), Some(russian));
+        fs::write(temp_dir.path().join("short.txt"), WINDOWS_1251.encode("Привет").0).unwrap();
+        let mut results = [in_dir("short.mp3", None)];
+        fill_from_sidecars(&mut results, &ExtractOptions { encoding: Some(WINDOWS_1251), ..Default::default() });
+        assert_eq!(results[0].lyrics.as_deref(), Some("Привет"));
+    }
+}
@@ -1,34 +1,90 @@
-use std::fs::File;
-use std::io::Write;
+mod config;
+// The library's fixtures, compiled into the binary's tests too; not all of them are used here
+#[cfg(test)]
+#[allow(dead_code)]
+#[path = "test_util.rs"]
+mod test_util;
+
+use std::io::{self, BufRead, Write};
+use std::iter;
 use std::path::{Path, PathBuf};
-use id3::{Tag, TagLike};
-use clap::{Parser, Subcommand};
-use walkdir::WalkDir;
-use anyhow::{Result, Context, bail};
-use log::{info, warn, error, debug};
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand};
+use anyhow::{Result, bail};
+use log::{error, info, warn};
 use env_logger::Env;
+use encoding_rs::{Encoding, UTF_8};
+use config::Config;
+use mdmp3lyrics2txt::{
+    build_exclude_matcher, build_matcher, dedup_audio_files, download, dump_frames, extract_all_lyrics,
+    fill_from_sidecars, fill_missing_lyrics, filter_audio_files, filter_by_mtime, filter_by_size, filter_by_tags,
+    filter_excluded, find_audio_files, group_audio_files, is_remote_url, is_zip_path, read_file_list, read_m3u_playlist,
+    read_playlist, render_csv, render_json, render_markdown, render_text_pieces, render_yaml_frontmatter,
+    sort_audio_files, split_lyrics, unpack_zip, write_duplicates_report, write_error_log, write_manifest,
+    write_tag_summary, DownloadedFile, ExtractOptions, ExtractionCache, ExtractionSummary, FindOptions, FramePreference,
+    FrameSelector, GroupBy, InputWatcher, LineEnding, LineEndingStream, LyricsSourceKind, MirroredDir, OutputFormat,
+    OutputWriter, ProgressEvents, SortOrder, SqliteLookup, StatsFormat, StatsReport, Substitution, TagFilter, Template,
+    TextCase, TextOptions, TrackResult, UnicodeForm, UnpackedArchive, ValidationReport, DEFAULT_CONCURRENCY_LIMIT,
+    DEFAULT_DEBOUNCE, DEFAULT_DOWNLOAD_TIMEOUT, DEFAULT_HEADER_FORMAT, DEFAULT_LOG_INTERVAL, DEFAULT_MAX_TAG_BYTES,
+    DEFAULT_PAGE_WIDTH, DEFAULT_SOURCE_PRIORITY, STDOUT_PATH,
+};
+
+/// Exit status when files were found but none of them had lyrics
+const EXIT_NO_LYRICS: u8 = 2;
+
+/// Exit status when no audio files were found to extract from
+const EXIT_NO_FILES: u8 = 3;
+
+/// Exit status when --max-output-bytes was reached and tracks were left out of the output
+const EXIT_TRUNCATED: u8 = 4;
 
-/// A tool that extracts lyrics from MP3 files and concatenates them into a text file
+/// Exit codes of a lyrics extraction run, shown at the end of `--help`
+const EXIT_CODES_HELP: &str = "Exit codes:
+  0  Lyrics were extracted from at least one file
+  1  An error stopped the run, e.g. the input or output couldn't be read or written
+  2  Audio files were found, but none of them had lyrics
+  3  No audio files were found
+  4  The output reached --max-output-bytes, so some tracks were left out";
+
+/// Longest frame content shown by `dump-frames`
+const FRAME_PREVIEW_CHARS: usize = 60;
+
+/// A tool that extracts lyrics from MP3, FLAC, M4A, OGG and WAV files and concatenates them into a text file
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, subcommand_negates_reqs = true, after_help = EXIT_CODES_HELP)]
 struct Args {
-    /// Directory containing MP3 files or path to a single MP3 file
-    #[arg(short, long)]
-    input: String,
+    /// Directories or zip archives containing audio files, paths or http(s) URLs of single audio files,
+    /// or "-" to read a file list from stdin
+    #[arg(short, long, num_args = 1.., required_unless_present_any = ["files_from", "playlist", "playlist_m3u"])]
+    input: Vec<String>,
 
-    /// Output file path
+    /// Output file path, or "-" to write to stdout
     #[arg(short, long, default_value = "output.txt")]
     output: String,
 
-    /// Recursively search directories
-    #[arg(short, long, default_value_t = false)]
-    recursive: bool,
+    /// Read default options from this file instead of ~/.config/mdmp3lyrics/config.toml
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    #[command(flatten)]
+    find: FindArgs,
 
     /// Verbose output
     #[arg(short, long, default_value_t = false)]
     verbose: bool,
 
-    /// Include file names in output
+    /// Only log errors, leaving out the end-of-run summary too
+    #[arg(short, long, default_value_t = false, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Show paths in "File:" headers relative to the input they were found under
+    #[arg(long, default_value_t = false)]
+    relative_paths: bool,
+
+    /// Include file names in output, followed by the lyrics' language such as "[eng]" when the frame
+    /// has one (adds artist, title and album columns with --format csv)
     #[arg(short = 'n', long, default_value_t = false)]
     include_names: bool,
 
@@ -40,362 +96,1394 @@ struct Args {
     #[arg(long, default_value = "---")]
     separator_text: String,
 
+    /// Preferred lyrics language as a three-letter code, e.g. "eng" (falls back to the first frame)
+    #[arg(short, long)]
+    language: Option<String>,
+
+    /// Only read lyrics from this ID3 frame, e.g. "USLT", "COMM:LYRICS" or "TXXX:CUSTOM" (the part after the
+    /// colon matches the frame's description)
+    #[arg(long, value_parser = FrameSelector::parse)]
+    frame_id: Option<FrameSelector>,
+
+    /// ID3 frames to search for lyrics, best first, e.g. "txxx,uslt"; frames left out aren't searched
+    #[arg(long, value_enum, value_delimiter = ',', default_values_t = DEFAULT_SOURCE_PRIORITY.to_vec())]
+    source_priority: Vec<LyricsSourceKind>,
+
+    /// Join the lyrics of every USLT frame, in tag order with a blank line between, for files that
+    /// store a verse per frame (otherwise only one frame is used)
+    #[arg(long, default_value_t = false)]
+    merge_frames: bool,
+
+    /// For files without ID3v2 lyrics, use the ID3v1 comment if it looks like lyrics
+    #[arg(long, default_value_t = false)]
+    id3v1_comments: bool,
+
+    /// As a last resort for files without lyrics frames, use a comment (of any description) or the
+    /// title if it holds four or more lines; a heuristic that can mistake other notes for lyrics
+    #[arg(long, default_value_t = false)]
+    desperate: bool,
+
+    /// Whether text output takes the USLT or SYLT lyrics when a file has both
+    #[arg(long, value_enum, default_value_t = FramePreference::Uslt)]
+    prefer: FramePreference,
+
+    /// Decode lyrics with this charset (e.g. "windows-1251") instead of guessing
+    #[arg(long, value_parser = parse_encoding)]
+    encoding: Option<&'static Encoding>,
+
+    /// Rewrite each track's lyrics with a sed-style substitution, e.g. 's/www\.[^ ]*//g' (repeatable, applied in order)
+    #[arg(long, value_parser = Substitution::parse)]
+    replace: Vec<Substitution>,
+
+    /// Remove [mm:ss.xx] timestamps from the start of lyric lines, keeping tags like [Chorus]
+    #[arg(long, default_value_t = false)]
+    strip_timestamps: bool,
+
+    /// Collapse runs of blank lines in each track's lyrics into one, trimming them from the start and end
+    #[arg(long, default_value_t = false)]
+    flatten_blank_lines: bool,
+
+    /// Convert lyrics stored as HTML to plain text: <br> and <p> become line breaks, other tags are
+    /// removed and entities such as &amp; are decoded
+    #[arg(long, default_value_t = false)]
+    strip_html: bool,
+
+    /// Normalize lyrics to one Unicode form, so "é" is spelled the same way in every track; applied
+    /// before --replace and the other clean-ups, with line endings converted last
+    #[arg(long, value_enum, ignore_case = true)]
+    normalize_unicode: Option<UnicodeForm>,
+
+    /// Spell non-Latin lyrics in ASCII letters, e.g. for karaoke; this is a best-effort
+    /// character-by-character transliteration, not proper romaji or pinyin
+    #[arg(long, default_value_t = false)]
+    transliterate: bool,
+
+    /// Put all lyrics in upper case, after every other clean-up
+    #[arg(long, default_value_t = false, conflicts_with = "lowercase")]
+    uppercase: bool,
+
+    /// Put all lyrics in lower case, after every other clean-up
+    #[arg(long, default_value_t = false)]
+    lowercase: bool,
+
+    /// Put the --include-names headers in the same case as the lyrics
+    #[arg(long, default_value_t = false)]
+    case_headers: bool,
+
+    /// Treat lyrics shorter than this many characters as missing
+    #[arg(long, default_value_t = 0)]
+    min_lyrics_length: usize,
+
+    /// Only keep the first N lines of each track's lyrics, followed by "..." (0 keeps them all)
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    first_n_lines: usize,
+
+    /// Skip files whose ID3 tag is larger than this many bytes (checked per file)
+    #[arg(long, default_value_t = DEFAULT_MAX_TAG_BYTES)]
+    max_tag_bytes: u64,
+
+    /// Read ID3 tags through a memory map, which can be faster for very large files
+    #[arg(long, default_value_t = false)]
+    mmap: bool,
+
+    /// Write each track's lyrics exactly as the bytes stored in the tag, one after another, for
+    /// reporting tagger bugs; no decoding, clean-up or layout is applied, so the output may not be
+    /// valid UTF-8
+    #[arg(long, default_value_t = false)]
+    raw: bool,
+
+    /// Try files that are locked by another program (permission denied) up to this many more times
+    #[arg(long, default_value_t = 0)]
+    retries: u32,
+
+    /// With --verbose, log throughput and an ETA every this many files (0 turns it off)
+    #[arg(long, default_value_t = DEFAULT_LOG_INTERVAL)]
+    log_interval: usize,
+
+    /// Start each track's lyrics with a "# source: USLT (eng)" line naming the frame they came from
+    #[arg(long, default_value_t = false)]
+    annotate: bool,
+
+    /// Text to put before each track's lyrics, where \n and \t stand for a newline and a tab
+    #[arg(long, default_value = "", value_parser = parse_escapes)]
+    track_prefix: String,
+
+    /// Text to put after each track's lyrics, where \n and \t stand for a newline and a tab
+    #[arg(long, default_value = "", value_parser = parse_escapes)]
+    track_suffix: String,
+
+    /// Write this line for each track without lyrics, with or without --include-names (replaces the
+    /// "[No lyrics found]" marker)
+    #[arg(long, value_parser = parse_escapes)]
+    placeholder: Option<String>,
+
+    /// Write this line for each instrumental track in place of its lyrics, the "[No lyrics found]"
+    /// marker or --placeholder; tracks count as instrumental for a TXXX INSTRUMENTAL=1 frame, or a lyrics
+    /// frame that is blank or says "[instrumental]"
+    #[arg(long, value_name = "TEXT", value_parser = parse_escapes)]
+    include_instrumental_marker: Option<String>,
+
+    /// Lay out each track with a template using {filename}, {stem}, {artist}, {album}, {title}, {lyrics},
+    /// {index} and {track}, where numbers take a width as in {track:02} (replaces --include-names, --separator and --annotate)
+    #[arg(long, value_parser = Template::parse)]
+    template: Option<Template>,
+
+    /// Start the output with a numbered list of the tracks and the line each one starts on
+    #[arg(long, default_value_t = false)]
+    toc: bool,
+
+    /// Lay each track's lyrics out in this many side-by-side columns of equal height, e.g. for a
+    /// printed songbook
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    columns: usize,
+
+    /// Page width in characters for --columns; lines too long for a column are wrapped
+    #[arg(long, default_value_t = DEFAULT_PAGE_WIDTH)]
+    width: usize,
+
+    /// Ask which frame to use for files with more than one that could hold the lyrics
+    #[arg(long, default_value_t = false)]
+    interactive: bool,
+
+    /// Header template using {artist}, {title} and {filename} (used with --include-names and --format markdown)
+    #[arg(long, default_value = DEFAULT_HEADER_FORMAT)]
+    header_format: String,
+
+    /// Output format
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+
+    /// How many seconds the last SYLT line stays up in SRT and LRC output, which has no next line to
+    /// end it
+    #[arg(long, value_name = "SECONDS", default_value = "3", value_parser = parse_tail_duration)]
+    tail_duration: Duration,
+
+    /// Line endings to use throughout the output file
+    #[arg(long, value_enum, default_value_t = LineEnding::Lf)]
+    line_endings: LineEnding,
+
+    /// Number of files to extract in parallel (0 uses one thread per CPU)
+    #[arg(short, long, default_value_t = 1)]
+    jobs: usize,
+
+    /// Most files to have open at once (0 for no limit); only matters when --jobs is higher, as each
+    /// thread reads one file at a time
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_CONCURRENCY_LIMIT)]
+    concurrency_limit: usize,
+
+    /// Stop with an error at the first file that can't be read, instead of reporting it and carrying on
+    #[arg(long, default_value_t = false)]
+    fail_fast: bool,
+
+    /// Print a JSON line to stderr as each file finishes, such as
+    /// {"event":"file","path":"a.mp3","status":"ok","index":1,"total":9}, for a frontend to show
+    /// progress with; log lines, which don't start with '{', are still printed alongside
+    #[arg(long, default_value_t = false)]
+    progress_json: bool,
+
+    /// Keep extracted lyrics in this JSON file and reuse them for files whose size and modification
+    /// time haven't changed since; a damaged cache is ignored and rebuilt
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["raw", "interactive"])]
+    cache: Option<PathBuf>,
+
+    /// For files without lyrics, use the text of a .txt file with the same name next to them instead
+    /// (checked before --lookup)
+    #[arg(long, default_value_t = false)]
+    include_txt: bool,
+
+    /// Fill in missing lyrics from the --lookup-db database, matching on artist and title
+    #[arg(long, default_value_t = false, requires = "lookup_db")]
+    lookup: bool,
+
+    /// SQLite database with a lyrics (artist, title, lyrics) table, used with --lookup
+    #[arg(long)]
+    lookup_db: Option<PathBuf>,
+
+    /// Add to the end of the output file instead of replacing it
+    #[arg(long, default_value_t = false)]
+    append: bool,
+
+    /// Don't print the summary of files and lyrics to stderr at the end of the run (--quiet leaves it out too)
+    #[arg(long, default_value_t = false)]
+    no_summary: bool,
+
+    /// Gzip the output file (always done for an output path ending in .gz)
+    #[arg(long, default_value_t = false)]
+    gzip: bool,
+
+    /// Write the output in this charset (e.g. "shift_jis"), with '?' for characters it can't represent
+    #[arg(long, value_parser = parse_output_encoding, default_value = "utf-8")]
+    output_encoding: &'static Encoding,
+
+    /// Also write a TSV manifest of each source file's SHA-256 and extracted lyrics size
+    #[arg(long)]
+    manifest: Option<PathBuf>,
+
+    /// Also write a TSV count of the tracks by each distinct artist and on each distinct album,
+    /// whether or not they have lyrics
+    #[arg(long, value_name = "PATH")]
+    tag_summary: Option<PathBuf>,
+
+    /// Also write a report of artist and title pairs found in more than one file, listing the files,
+    /// to help clean up duplicates
+    #[arg(long, value_name = "PATH")]
+    report_duplicates: Option<PathBuf>,
+
+    /// Also write a JSON-lines log with the path and error message of each file that couldn't be read
+    /// (empty when none failed)
+    #[arg(long, value_name = "PATH")]
+    error_log: Option<PathBuf>,
+
+    /// Stop adding tracks before the output would grow past this size, e.g. "1MB" (K, M and G are
+    /// powers of 1024); only whole tracks are written
+    #[arg(long, value_name = "N", value_parser = parse_size)]
+    max_output_bytes: Option<u64>,
+
+    /// Report what would be written without touching the output file
+    #[arg(long, default_value_t = false)]
+    dry_run: bool,
+
+    /// Write JSON on a single line instead of pretty-printing it (used with --format json)
+    #[arg(long, default_value_t = false)]
+    compact: bool,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
 
+/// Options that control which files are picked up from the input path
+#[derive(clap::Args, Debug)]
+struct FindArgs {
+    /// Recursively search directories
+    #[arg(short, long, default_value_t = false)]
+    recursive: bool,
+
+    /// Follow symlinked directories while searching, skipping any cycles
+    #[arg(long, default_value_t = false)]
+    follow_symlinks: bool,
+
+    /// Don't skip the paths listed in a .lyricsignore file in the input directory
+    #[arg(long, default_value_t = false)]
+    no_ignore: bool,
+
+    /// Only include files whose name matches this glob, e.g. "Live*.mp3" (repeatable)
+    #[arg(long)]
+    pattern: Vec<String>,
+
+    /// Also include files with this extension (repeatable)
+    #[arg(long)]
+    ext: Vec<String>,
+
+    /// Skip files whose name matches this glob, e.g. "*Remix*", even if --pattern matches (repeatable)
+    #[arg(long)]
+    exclude: Vec<String>,
+
+    /// Read the files to process from this list instead of searching a directory
+    #[arg(long, conflicts_with = "input")]
+    files_from: Option<PathBuf>,
+
+    /// Process the files in this playlist, a JSON array of paths, in its order; missing files are
+    /// reported and skipped
+    #[arg(long, value_name = "FILE.json", conflicts_with_all = ["input", "files_from"])]
+    playlist: Option<PathBuf>,
+
+    /// Process the files in this .m3u or .m3u8 playlist, in its order; relative entries are found
+    /// from the playlist's folder, and missing files are reported and skipped
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["input", "files_from", "playlist"])]
+    playlist_m3u: Option<PathBuf>,
+
+    /// Entries in the file list are NUL-terminated, as produced by `find -print0`
+    #[arg(long, default_value_t = false)]
+    null: bool,
+
+    /// Only process files whose artist contains this text, ignoring case (repeatable)
+    #[arg(long)]
+    filter_artist: Vec<String>,
+
+    /// Only process files whose genre contains this text, ignoring case (repeatable)
+    #[arg(long)]
+    filter_genre: Vec<String>,
+
+    /// Skip files bigger than this, e.g. "50MB" (K, M and G are powers of 1024)
+    #[arg(long, value_parser = parse_size)]
+    max_file_size: Option<u64>,
+
+    /// Seconds to wait for each http(s) input to download
+    #[arg(long, default_value_t = DEFAULT_DOWNLOAD_TIMEOUT.as_secs())]
+    timeout: u64,
+
+    /// Only process files modified since this long ago (e.g. "7d" or "12h") or this RFC 3339 time;
+    /// files in a zip archive go by the time stored for them, and http(s) inputs are always processed
+    #[arg(long, value_parser = parse_since)]
+    since: Option<SystemTime>,
+
+    /// Order to process files in ("track" sorts by directory, then track number)
+    #[arg(long, value_enum, default_value_t = SortOrder::Name)]
+    sort: SortOrder,
+
+    /// Gather tracks under a heading per artist or album, ordered by track number then title within
+    /// each (tracks without the tag go under "Unknown" at the end); overrides --sort
+    #[arg(long, value_enum)]
+    group_by: Option<GroupBy>,
+}
+
+impl Args {
+    /// Fill in the options that weren't given on the command line from the config file
+    fn apply_config(&mut self, config: Config, matches: &ArgMatches) {
+        config::apply(&mut self.output, config.output, matches, "output");
+        config::apply(&mut self.separator, config.separator, matches, "separator");
+        config::apply(&mut self.separator_text, config.separator_text, matches, "separator_text");
+        config::apply(&mut self.include_names, config.include_names, matches, "include_names");
+        
+        // Subcommands have their own search options
+        let (find, find_matches) = match (&mut self.command, matches.subcommand()) {
+            (
+                Some(
+                    Commands::List { find, .. }
+                    | Commands::Split { find, .. }
+                    | Commands::Stats { find, .. }
+                    | Commands::Count { find, .. }
+                    | Commands::DumpFrames { find, .. }
+                    | Commands::Validate { find, .. }
+                    | Commands::Watch { find, .. },
+                ),
+                Some((_, sub_matches)),
+            ) => (find, sub_matches),
+            _ => (&mut self.find, matches),
+        };
+        config::apply(&mut find.recursive, config.recursive, find_matches, "recursive");
+    }
+}
+
+impl FindArgs {
+    fn to_options(&self) -> Result<FindOptions> {
+        Ok(FindOptions {
+            recursive: self.recursive,
+            follow_symlinks: self.follow_symlinks,
+            use_ignore_file: !self.no_ignore,
+            matcher: build_matcher(&self.pattern, &self.ext)?,
+        })
+    }
+
+    /// Collect the files to process, either from a file list or playlist or by searching each of `inputs`
+    ///
+    /// Inputs keep the order they were given in, and `--sort` applies to the files within each one.
+    /// Playlists keep their own order.
+    /// `--group-by` instead sorts every file together, as groups can span inputs.
+    /// Zip archives are unpacked to temporary directories that last as long as the returned files.
+    /// Filtering, sorting and grouping by tags read ID3 tags of up to `max_tag_bytes`.
+    fn collect_files(&self, inputs: &[String], max_tag_bytes: u64) -> Result<InputFiles> {
+        let list_path = self.files_from.as_ref().or(self.playlist.as_ref()).or(self.playlist_m3u.as_ref());
+        let inputs: Vec<&Path> = match list_path {
+            Some(list_path) => vec![list_path.as_path()],
+            None if inputs.is_empty() => bail!("No input path given"),
+            None => inputs.iter().map(Path::new).collect(),
+        };
+        let options = self.to_options()?;
+        let exclude = build_exclude_matcher(&self.exclude)?;
+        let filter = TagFilter { artists: self.filter_artist.clone(), genres: self.filter_genre.clone() };
+        
+        let mut all_files = Vec::new();
+        let mut archives = Vec::new();
+        let mut downloads = Vec::new();
+        let mut excluded = 0;
+        let mut skipped = 0;
+        let mut unmodified = 0;
+        let mut unreadable = 0;
+        for input in inputs {
+            let downloads_before = downloads.len();
+            let files = if self.playlist.is_some() {
+                filter_audio_files(read_playlist(input)?, &options)
+            } else if self.playlist_m3u.is_some() {
+                filter_audio_files(read_m3u_playlist(input)?, &options)
+            } else if self.files_from.is_some() || input == Path::new("-") {
+                filter_audio_files(read_file_list(input, self.null)?, &options)
+            } else if let Some(url) = input.to_str().filter(|input| is_remote_url(input)) {
+                let downloaded = download(url, Duration::from_secs(self.timeout), &options)?;
+                let files = vec![downloaded.path().to_path_buf()];
+                downloads.push(downloaded);
+                files
+            } else if is_zip_path(input) {
+                let archive = unpack_zip(input, &options)?;
+                let files = archive.files().to_vec();
+                archives.push(archive);
+                files
+            } else {
+                find_audio_files(input, &options)?
+            };
+            let (files, matched) = filter_excluded(files, &exclude);
+            excluded += matched;
+            let files = match self.max_file_size {
+                Some(max_bytes) => {
+                    let (files, too_large) = filter_by_size(files, max_bytes);
+                    skipped += too_large;
+                    files
+                }
+                None => files,
+            };
+            // A download is always new on disk, and its time on the server isn't known
+            let downloaded = downloads.len() > downloads_before;
+            let files = match self.since.filter(|_| !downloaded) {
+                Some(since) => {
+                    let (files, too_old) = filter_by_mtime(files, since);
+                    unmodified += too_old;
+                    files
+                }
+                None => files,
+            };
+            let (mut files, untagged) = filter_by_tags(files, &filter, max_tag_bytes);
+            unreadable += untagged;
+            if self.group_by.is_none() && !self.is_playlist() {
+                sort_audio_files(&mut files, self.sort, max_tag_bytes);
+            }
+            all_files.extend(files);
+        }
+        // The same file can turn up under more than one input
+        let mut files = dedup_audio_files(all_files);
+        if let Some(group_by) = self.group_by {
+            group_audio_files(&mut files, group_by, max_tag_bytes);
+        }
+        Ok(InputFiles { files, archives, downloads, excluded, skipped, unmodified, unreadable })
+    }
+
+    /// Whether the files come from a playlist, which sets their order
+    fn is_playlist(&self) -> bool {
+        self.playlist.is_some() || self.playlist_m3u.is_some()
+    }
+
+    /// The directories that found files' paths start from: each input directory, the folder of
+    /// each single file, or the current directory for file lists
+    fn input_roots(&self, inputs: &[String]) -> Vec<PathBuf> {
+        if self.files_from.is_some() || self.is_playlist() {
+            return vec![PathBuf::new()];
+        }
+        inputs
+            .iter()
+            .map(Path::new)
+            .map(|input| match input {
+                _ if input == Path::new("-") => PathBuf::new(),
+                _ if input.is_file() => input.parent().map(Path::to_path_buf).unwrap_or_default(),
+                _ => input.to_path_buf(),
+            })
+            .collect()
+    }
+}
+
+/// The files to process, along with any archives they were unpacked from and URLs they were downloaded from
+#[derive(Default)]
+struct InputFiles {
+    files: Vec<PathBuf>,
+    archives: Vec<UnpackedArchive>,
+    downloads: Vec<DownloadedFile>,
+    /// Files left out for matching an `--exclude` glob
+    excluded: usize,
+    /// Files left out for being over `--max-file-size`
+    skipped: usize,
+    /// Files left out for not having changed since `--since`
+    unmodified: usize,
+    /// Files left out because their tags couldn't be read for `--filter-artist` or `--filter-genre`
+    unreadable: usize,
+}
+
+impl InputFiles {
+    /// The path to show for `file`, which is its archive name if it came from an archive, or the
+    /// name from its URL if it was downloaded
+    fn display_path(&self, file: &Path) -> PathBuf {
+        self.archives
+            .iter()
+            .find_map(|archive| archive.entry_name(file))
+            .or_else(|| self.downloads.iter().find_map(|downloaded| downloaded.entry_name(file)))
+            .unwrap_or_else(|| file.to_path_buf())
+    }
+
+    /// Progress reporting for every file, naming archived and downloaded files as they'll be shown
+    fn progress_events(&self) -> ProgressEvents {
+        let names = self
+            .files
+            .iter()
+            .map(|file| (file.clone(), self.display_path(file)))
+            .filter(|(file, name)| file != name)
+            .collect();
+        ProgressEvents::new(self.files.len(), names)
+    }
+
+    /// Extract lyrics from every file, reporting archived and downloaded files under their own names
+    ///
+    /// With a `cache`, files that haven't changed since it was saved aren't read again.
+    fn extract(&self, options: &ExtractOptions, jobs: usize, cache: Option<&mut ExtractionCache>) -> Result<Vec<TrackResult>> {
+        let mut results = match cache {
+            Some(cache) => cache.extract_all(&self.files, options, jobs)?,
+            None => extract_all_lyrics(&self.files, options, jobs)?,
+        };
+        for archive in &self.archives {
+            archive.relabel(&mut results);
+        }
+        for downloaded in &self.downloads {
+            downloaded.relabel(&mut results);
+        }
+        Ok(results)
+    }
+}
+
 #[derive(Subcommand, Debug)]
 enum Commands {
-    /// List all MP3 files found but don't extract lyrics
+    /// List all audio files found but don't extract lyrics
     List {
-        /// Directory containing MP3 files
-        #[arg(short, long)]
-        input: String,
+        /// Directories or zip archives containing audio files, or "-" to read a file list from stdin
+        #[arg(short, long, num_args = 1.., required_unless_present_any = ["files_from", "playlist", "playlist_m3u"])]
+        input: Vec<String>,
+        
+        #[command(flatten)]
+        find: FindArgs,
+    },
+    /// Write each file's lyrics to a .txt file next to it
+    Split {
+        /// Directories or zip archives containing audio files, paths or http(s) URLs of single audio files,
+        /// or "-" to read a file list from stdin
+        #[arg(short, long, num_args = 1.., required_unless_present_any = ["files_from", "playlist", "playlist_m3u"])]
+        input: Vec<String>,
+        
+        #[command(flatten)]
+        find: FindArgs,
+
+        /// Write an empty .txt file for tracks without lyrics
+        #[arg(long, default_value_t = false)]
+        include_empty: bool,
+
+        /// Replace .txt files that already exist
+        #[arg(long, default_value_t = false)]
+        overwrite: bool,
+
+        /// Write the .txt files under this directory, recreating the input folders, instead of next to each file
+        #[arg(long)]
+        output_dir: Option<PathBuf>,
+
+        /// Name each .txt file with a template such as "{track:02} - {title}.txt" instead of after its
+        /// audio file, using {stem}, {filename}, {artist}, {album}, {title}, {track} and {index};
+        /// tracks that would get the same name are told apart with a " (2)" suffix
+        #[arg(long, value_parser = Template::parse)]
+        filename_template: Option<Template>,
+    },
+    /// Print line and word counts for each file's lyrics
+    Stats {
+        /// Directories or zip archives containing audio files, paths or http(s) URLs of single audio files,
+        /// or "-" to read a file list from stdin
+        #[arg(short, long, num_args = 1.., required_unless_present_any = ["files_from", "playlist", "playlist_m3u"])]
+        input: Vec<String>,
         
-        /// Recursively search directories
-        #[arg(short, long, default_value_t = false)]
-        recursive: bool,
+        #[command(flatten)]
+        find: FindArgs,
+
+        /// Report format
+        #[arg(long, value_enum, default_value_t = StatsFormat::Text)]
+        format: StatsFormat,
+    },
+    /// Print how many files have lyrics, don't, or couldn't be read, without writing any lyrics
+    Count {
+        /// Directories or zip archives containing audio files, paths or http(s) URLs of single audio files,
+        /// or "-" to read a file list from stdin
+        #[arg(short, long, num_args = 1.., required_unless_present_any = ["files_from", "playlist", "playlist_m3u"])]
+        input: Vec<String>,
+        
+        #[command(flatten)]
+        find: FindArgs,
+
+        /// Report format
+        #[arg(long, value_enum, default_value_t = StatsFormat::Text)]
+        format: StatsFormat,
+    },
+    /// Report which files have lyrics, tags without lyrics, no tags, or tags that can't be read
+    Validate {
+        /// Directories or zip archives containing audio files, paths or http(s) URLs of single audio files,
+        /// or "-" to read a file list from stdin
+        #[arg(short, long, num_args = 1.., required_unless_present_any = ["files_from", "playlist", "playlist_m3u"])]
+        input: Vec<String>,
+        
+        #[command(flatten)]
+        find: FindArgs,
+
+        /// Report format
+        #[arg(long, value_enum, default_value_t = StatsFormat::Text)]
+        format: StatsFormat,
+    },
+    /// List every tag frame in each file with a preview of its content
+    DumpFrames {
+        /// Directories or zip archives containing audio files, paths or http(s) URLs of single audio files,
+        /// or "-" to read a file list from stdin
+        #[arg(short, long, num_args = 1.., required_unless_present_any = ["files_from", "playlist", "playlist_m3u"])]
+        input: Vec<String>,
+        
+        #[command(flatten)]
+        find: FindArgs,
+    },
+    /// Write the output file, then write it again whenever an audio file is added or changed, until
+    /// stopped with Ctrl-C
+    Watch {
+        /// Directories or audio files to watch
+        #[arg(short, long, num_args = 1.., required = true)]
+        input: Vec<String>,
+
+        #[command(flatten)]
+        find: FindArgs,
+
+        /// Wait until the files have been left alone this long before regenerating, so a burst of
+        /// changes only regenerates once
+        #[arg(long, value_name = "MILLISECONDS", default_value_t = DEFAULT_DEBOUNCE.as_millis() as u64)]
+        debounce_ms: u64,
     },
 }
 
-fn main() -> Result<()> {
+fn main() -> Result<ExitCode> {
     // Initialize logger with custom environment
     let env = Env::default().filter_or("RUST_LOG", "info");
     env_logger::init_from_env(env);
 
     // Parse command line arguments
-    let args = Args::parse();
+    let matches = Args::command().get_matches();
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
     
     // Set log level
     if args.verbose {
         log::set_max_level(log::LevelFilter::Debug);
+    } else if args.quiet {
+        log::set_max_level(log::LevelFilter::Error);
     } else {
         log::set_max_level(log::LevelFilter::Info);
     }
+    
+    // Command line flags take precedence over the config file
+    let config = Config::load(args.config.as_deref())?;
+    args.apply_config(config, &matches);
 
     // Process subcommands
-    if let Some(cmd) = args.command {
+    if let Some(cmd) = args.command.take() {
         match cmd {
-            Commands::List { input, recursive } => {
-                let mp3_files = find_mp3_files(&input, recursive)?;
-                for file in mp3_files {
-                    println!("{}", file.display());
+            Commands::List { input, find } => {
+                let audio_files = find.collect_files(&input, args.max_tag_bytes)?;
+                for file in &audio_files.files {
+                    println!("{}", audio_files.display_path(file).display());
+                }
+                return Ok(ExitCode::SUCCESS);
+            }
+            Commands::Split { input, find, include_empty, overwrite, output_dir, filename_template } => {
+                check_subcommand_options(&args, "split")?;
+                let audio_files = find.collect_files(&input, args.max_tag_bytes)?;
+                if !audio_files.archives.is_empty() {
+                    bail!("split can't write lyrics files next to files inside a zip archive");
+                }
+                if !audio_files.downloads.is_empty() {
+                    bail!("split can't write lyrics files next to files downloaded from a URL");
+                }
+                let results = audio_files.extract(&extract_options(&args, &audio_files), args.jobs, None)?;
+                let mirror = output_dir.map(|output_dir| MirroredDir { output_dir, input_roots: find.input_roots(&input) });
+                let written = split_lyrics(&results, include_empty, overwrite, mirror.as_ref(), filename_template.as_ref())?;
+                info!("Wrote {} lyrics file(s)", written);
+                return Ok(ExitCode::SUCCESS);
+            }
+            Commands::Stats { input, find, format } => {
+                check_subcommand_options(&args, "stats")?;
+                let audio_files = find.collect_files(&input, args.max_tag_bytes)?;
+                let results = audio_files.extract(&extract_options(&args, &audio_files), args.jobs, None)?;
+                let report = StatsReport::from_results(&results);
+                match format {
+                    StatsFormat::Text => print!("{}", report.render_text()),
+                    StatsFormat::Json => print!("{}", report.render_json()?),
+                }
+                return Ok(ExitCode::SUCCESS);
+            }
+            Commands::Count { input, find, format } => {
+                check_subcommand_options(&args, "count")?;
+                let audio_files = find.collect_files(&input, args.max_tag_bytes)?;
+                let results = audio_files.extract(&extract_options(&args, &audio_files), args.jobs, None)?;
+                let summary = ExtractionSummary::from_results(&results);
+                match format {
+                    StatsFormat::Text => print!("{}", summary.render_text()),
+                    StatsFormat::Json => print!("{}", summary.render_json()?),
                 }
-                return Ok(());
+                return Ok(ExitCode::SUCCESS);
+            }
+            Commands::Validate { input, find, format } => {
+                let audio_files = find.collect_files(&input, args.max_tag_bytes)?;
+                let mut report = ValidationReport::from_files(&audio_files.files, args.max_tag_bytes);
+                for file in &mut report.files {
+                    file.path = audio_files.display_path(&file.path);
+                }
+                match format {
+                    StatsFormat::Text => print!("{}", report.render_text()),
+                    StatsFormat::Json => print!("{}", report.render_json()?),
+                }
+                return Ok(ExitCode::SUCCESS);
+            }
+            Commands::DumpFrames { input, find } => {
+                let audio_files = find.collect_files(&input, args.max_tag_bytes)?;
+                for file in &audio_files.files {
+                    let name = audio_files.display_path(file);
+                    println!("{}", name.display());
+                    match dump_frames(file, args.max_tag_bytes) {
+                        Ok(frames) if frames.is_empty() => println!("  (no tags)"),
+                        Ok(frames) => {
+                            for frame in frames {
+                                println!("  {:<6} {}", frame.id, frame.preview(FRAME_PREVIEW_CHARS));
+                            }
+                        }
+                        Err(e) => error!("Failed to read frames from {}: {}", name.display(), e),
+                    }
+                }
+                return Ok(ExitCode::SUCCESS);
+            }
+            Commands::Watch { input, find, debounce_ms } => {
+                if args.interactive {
+                    bail!("--interactive needs someone to answer, so it can't be used with watch");
+                }
+                if args.append {
+                    bail!("--append would add the whole output again on every change, so it can't be used with watch");
+                }
+                let inputs: Vec<PathBuf> = input.iter().map(PathBuf::from).collect();
+                if find.files_from.is_some() || find.is_playlist() || inputs.iter().any(|input| !input.exists()) {
+                    bail!("watch only works with directories and files on disk");
+                }
+                let watcher = InputWatcher::new(&inputs, &find.to_options()?, Duration::from_millis(debounce_ms))?;
+                ctrlc::set_handler(watcher.stopper())?;
+                let regenerate = || {
+                    let written = find
+                        .collect_files(&input, args.max_tag_bytes)
+                        .and_then(|files| write_lyrics(&args, &find, &input, &files));
+                    if let Err(e) = written {
+                        error!("Failed to regenerate {}: {:#}", args.output, e);
+                    }
+                };
+
+                regenerate();
+                info!("Watching for changes, press Ctrl-C to stop");
+                while let Some(changed) = watcher.next_change() {
+                    match &changed[..] {
+                        [file] => info!("{} changed, regenerating the output", file.display()),
+                        [file, others @ ..] => info!(
+                            "{} and {} other file(s) changed, regenerating the output",
+                            file.display(),
+                            others.len()
+                        ),
+                        [] => {}
+                    }
+                    regenerate();
+                }
+                info!("Stopped watching");
+                return Ok(ExitCode::SUCCESS);
             }
         }
     }
 
     // Default behavior: extract lyrics and write to output file
-    let mp3_files = find_mp3_files(&args.input, args.recursive)?;
-    
-    if mp3_files.is_empty() {
-        bail!("No MP3 files found");
+    if args.interactive && args.input.iter().any(|input| input == "-") {
+        bail!("--interactive reads answers from stdin, so it can't be combined with a file list on stdin");
+    }
+    let audio_files = args.find.collect_files(&args.input, args.max_tag_bytes)?;
+    write_lyrics(&args, &args.find, &args.input, &audio_files)
+}
+
+/// Extract the lyrics from `audio_files` and write them to the output, as set up by `args`
+///
+/// `find` and `inputs` are the search options and inputs the files were collected with, which
+/// belong to the subcommand rather than `args` under `watch`.
+fn write_lyrics(args: &Args, find: &FindArgs, inputs: &[String], audio_files: &InputFiles) -> Result<ExitCode> {
+    if args.raw && args.format != OutputFormat::Text {
+        bail!("--raw writes the lyrics as stored, so it only works with --format text");
+    }
+    if args.raw && args.output_encoding != UTF_8 {
+        bail!("--raw writes the lyrics as stored, so it can't be combined with --output-encoding");
+    }
+    let laid_out_whole = matches!(
+        args.format,
+        OutputFormat::Json | OutputFormat::Csv | OutputFormat::Markdown | OutputFormat::YamlFrontmatter
+    );
+    if args.columns > 1 && (args.format != OutputFormat::Text || args.template.is_some()) {
+        bail!("--columns lays out plain lyrics, so it only works with --format text and without --template");
+    }
+    if args.max_output_bytes.is_some() && laid_out_whole {
+        bail!("--max-output-bytes stops between tracks, so it only works with text, LRC and SRT output");
+    }
+    if args.max_output_bytes.is_some() && args.toc && !args.raw {
+        bail!("--toc lists every track before --max-output-bytes decides which fit, so they can't be combined");
+    }
+    if audio_files.files.is_empty() {
+        error!("No audio files found");
+        return Ok(ExitCode::from(EXIT_NO_FILES));
     }
     
-    info!("Found {} MP3 file(s)", mp3_files.len());
+    info!("Found {} audio file(s)", audio_files.files.len());
+    if audio_files.excluded > 0 {
+        info!("Skipped {} file(s) matching --exclude", audio_files.excluded);
+    }
+    if audio_files.skipped > 0 {
+        info!("Skipped {} file(s) over --max-file-size", audio_files.skipped);
+    }
+    if audio_files.unmodified > 0 {
+        info!("Skipped {} file(s) not modified since --since", audio_files.unmodified);
+    }
+    if audio_files.unreadable > 0 {
+        info!("Skipped {} file(s) with unreadable tags", audio_files.unreadable);
+    }
     
-    let lyrics = extract_all_lyrics(&mp3_files, args.include_names, args.separator, &args.separator_text)?;
-    write_to_file(&args.output, &lyrics)?;
+    let options = extract_options(args, audio_files);
+    let mut cache = args.cache.as_deref().map(|cache_path| ExtractionCache::load(cache_path, &options));
+    let mut results = audio_files.extract(&options, args.jobs, cache.as_mut())?;
+    if let (Some(cache), Some(cache_path)) = (&cache, &args.cache) {
+        cache.save()?;
+        info!("Reused {} cached result(s) from {}", cache.hits(), cache_path.display());
+    }
+    if args.include_txt {
+        let filled = fill_from_sidecars(&mut results, &options);
+        info!("Filled in lyrics for {} file(s) from .txt files", filled);
+    }
+    if let Some(db_path) = args.lookup_db.as_ref().filter(|_| args.lookup) {
+        let filled = fill_missing_lyrics(&mut results, &SqliteLookup::open(db_path)?, &options)?;
+        info!("Filled in lyrics for {} file(s) from {}", filled, db_path.display());
+    }
+    if args.interactive {
+        pick_lyrics(&mut results, &mut io::stdin().lock(), &mut io::stderr())?;
+    }
+    if let Some(group_by) = find.group_by {
+        group_by.sort_results(&mut results);
+    }
+    report_failures(&results);
+    let summary = ExtractionSummary::from_results(&results);
+    let text_options = TextOptions {
+        include_names: args.include_names,
+        add_separator: args.separator,
+        separator_text: args.separator_text.clone(),
+        header_format: args.header_format.clone(),
+        header_case: text_case(args).filter(|_| args.case_headers),
+        path_roots: if args.relative_paths { find.input_roots(inputs) } else { Vec::new() },
+        annotate: args.annotate,
+        track_prefix: args.track_prefix.clone(),
+        track_suffix: args.track_suffix.clone(),
+        placeholder: args.placeholder.clone(),
+        instrumental_marker: args.include_instrumental_marker.clone(),
+        template: args.template.clone(),
+        toc: args.toc,
+        columns: args.columns,
+        page_width: args.width,
+        group_by: find.group_by,
+    };
+    // Text is laid out a track at a time and written as it goes, rather than built up in memory
+    let pieces: Box<dyn Iterator<Item = String>> = match args.format {
+        OutputFormat::Json => Box::new(iter::once(render_json(&results, args.compact)?)),
+        OutputFormat::Csv => Box::new(iter::once(render_csv(&results, args.include_names)?)),
+        OutputFormat::Markdown => Box::new(iter::once(render_markdown(&results, &args.header_format))),
+        OutputFormat::YamlFrontmatter => Box::new(iter::once(render_yaml_frontmatter(&results))),
+        _ => Box::new(render_text_pieces(&results, &text_options)),
+    };
+    let mut line_endings = LineEndingStream::new(args.line_endings);
+    let raw_lyrics = results.iter().filter_map(|result| result.raw_lyrics.as_deref());
+    let mut truncated = false;
     
-    info!("Lyrics written to {}", args.output);
-    Ok(())
-}
-
-/// Find MP3 files in the given path
-fn find_mp3_files(input_path: &str, recursive: bool) -> Result<Vec<PathBuf>> {
-    let path = Path::new(input_path);
-    let mut mp3_files = Vec::new();
-
-    if path.is_file() {
-        if path.extension().and_then(|ext| ext.to_str()) == Some("mp3") {
-            mp3_files.push(path.to_path_buf());
+    if args.dry_run {
+        let characters: usize = if args.raw {
+            raw_lyrics.map(|lyrics| String::from_utf8_lossy(lyrics).chars().count() + 1).sum()
+        } else {
+            pieces.map(|piece| line_endings.push(&piece).chars().count()).sum::<usize>()
+                + line_endings.finish().chars().count()
+        };
+        print_dry_run(&summary, audio_files, characters);
+    } else {
+        let mut output =
+            OutputWriter::open(Path::new(&args.output), args.append, args.gzip)?.with_encoding(args.output_encoding);
+        let fits = |output: &OutputWriter, len: usize| {
+            args.max_output_bytes.is_none_or(|max| (output.bytes_written() + len) as u64 <= max)
+        };
+        // Pieces are whole tracks, as there is no table of contents with a size limit
+        let mut pieces_written = 0;
+        if args.raw {
+            for lyrics in raw_lyrics {
+                if !fits(&output, lyrics.len() + 1) {
+                    truncated = true;
+                    break;
+                }
+                output.write_bytes(lyrics)?;
+                output.write_bytes(b"\n")?;
+                pieces_written += 1;
+            }
         } else {
-            bail!("The specified file is not an MP3 file");
+            for piece in pieces {
+                // Checked once encoded, as that can make the piece longer
+                if !output.write_str_within(&line_endings.push(&piece), args.max_output_bytes)? {
+                    truncated = true;
+                    break;
+                }
+                pieces_written += 1;
+            }
+            output.write_str(&line_endings.finish())?;
+        }
+        if output.unmappable() > 0 {
+            warn!(
+                "{} character(s) can't be written in {} and were replaced with '?'",
+                output.unmappable(),
+                args.output_encoding.name()
+            );
+        }
+        let output_bytes = output.finish()?;
+        if truncated {
+            warn!(
+                "Output reached --max-output-bytes: included {} track(s), left out {}",
+                pieces_written,
+                tracks_left_out(args, &results, pieces_written)
+            );
         }
-    } else if path.is_dir() {
-        let walker = if recursive {
-            WalkDir::new(path).into_iter()
+        
+        // Logging goes to stderr, so this doesn't end up in piped output
+        if args.output == STDOUT_PATH {
+            info!("Lyrics written to stdout");
         } else {
-            WalkDir::new(path).max_depth(1).into_iter()
-        };
+            info!("Lyrics written to {}", args.output);
+        }
+        
+        if let Some(manifest_path) = &args.manifest {
+            write_manifest(manifest_path, &results)?;
+            info!("Manifest written to {}", manifest_path.display());
+        }
+        
+        if let Some(summary_path) = &args.tag_summary {
+            write_tag_summary(summary_path, &results)?;
+            info!("Tag summary written to {}", summary_path.display());
+        }
+        
+        if let Some(report_path) = &args.report_duplicates {
+            write_duplicates_report(report_path, &results)?;
+            info!("Duplicates report written to {}", report_path.display());
+        }
+        
+        if let Some(log_path) = &args.error_log {
+            write_error_log(log_path, &results)?;
+            info!("Error log written to {}", log_path.display());
+        }
+        
+        if !args.no_summary && !args.quiet {
+            eprintln!("{}", format_summary(&summary, audio_files, output_bytes));
+        }
+    }
+    
+    if summary.with_lyrics == 0 {
+        error!("No lyrics found in any file");
+        return Ok(ExitCode::from(EXIT_NO_LYRICS));
+    }
+    if truncated {
+        return Ok(ExitCode::from(EXIT_TRUNCATED));
+    }
+    Ok(ExitCode::SUCCESS)
+}
 
-        for entry in walker.filter_map(|e| e.ok()) {
-            let path = entry.path();
-            if path.is_file() && path.extension().and_then(|ext| ext.to_str()) == Some("mp3") {
-                mp3_files.push(path.to_path_buf());
-                debug!("Found MP3: {}", path.display());
-            }
+/// Refuse options that only apply when writing the output file, rather than ignore them under a
+/// subcommand that extracts lyrics for something else
+fn check_subcommand_options(args: &Args, command: &str) -> Result<()> {
+    let used = [
+        ("--interactive", args.interactive),
+        ("--cache", args.cache.is_some()),
+        ("--include-txt", args.include_txt),
+        ("--lookup", args.lookup),
+    ];
+    match used.iter().find(|(_, used)| *used) {
+        Some((flag, _)) => {
+            bail!("{} only applies when writing the output file, so it can't be used with {}", flag, command)
         }
-    } else {
-        bail!("The specified path does not exist");
+        None => Ok(()),
     }
+}
 
-    Ok(mp3_files)
+/// How to extract lyrics from `audio_files`, as set up by `args`, the same for every command
+fn extract_options(args: &Args, audio_files: &InputFiles) -> ExtractOptions {
+    ExtractOptions {
+        format: args.format,
+        tail_duration: args.tail_duration,
+        language: args.language.clone(),
+        encoding: args.encoding,
+        frame: args.frame_id.clone(),
+        source_priority: args.source_priority.clone(),
+        merge_frames: args.merge_frames,
+        id3v1_comments: args.id3v1_comments,
+        desperate: args.desperate,
+        prefer: args.prefer,
+        replacements: args.replace.clone(),
+        strip_timestamps: args.strip_timestamps,
+        flatten_blank_lines: args.flatten_blank_lines,
+        strip_html: args.strip_html,
+        normalize_unicode: args.normalize_unicode,
+        transliterate: args.transliterate,
+        case: text_case(args),
+        max_tag_bytes: args.max_tag_bytes,
+        mmap: args.mmap,
+        retries: args.retries,
+        min_lyrics_length: args.min_lyrics_length,
+        first_n_lines: args.first_n_lines,
+        raw: args.raw,
+        log_interval: args.log_interval,
+        concurrency_limit: args.concurrency_limit,
+        fail_fast: args.fail_fast,
+        progress: args.progress_json.then(|| Arc::new(audio_files.progress_events())),
+    }
 }
 
-/// Extract lyrics from all MP3 files
-fn extract_all_lyrics(
-    mp3_files: &[PathBuf], 
-    include_names: bool, 
-    add_separator: bool, 
-    separator_text: &str
-) -> Result<String> {
-    let mut all_lyrics = String::new();
-
-    for (index, file_path) in mp3_files.iter().enumerate() {
-        if index > 0 && add_separator {
-            all_lyrics.push_str(&format!("\n{}\n", separator_text));
-        }
+/// How many tracks didn't make it into output that stopped after `written` of them
+///
+/// With `--raw`, only tracks that have raw lyrics were ever going to be written.
+fn tracks_left_out(args: &Args, results: &[TrackResult], written: usize) -> usize {
+    let tracks = if args.raw { results.iter().filter(|r| r.raw_lyrics.is_some()).count() } else { results.len() };
+    tracks - written
+}
 
-        if include_names {
-            all_lyrics.push_str(&format!("File: {}\n\n", file_path.display()));
+/// Ask which frame to take each track's lyrics from, for tracks that have a choice
+///
+/// An empty answer, or the end of `input`, keeps the frame that was picked automatically.
+fn pick_lyrics(results: &mut [TrackResult], input: &mut impl BufRead, prompt: &mut impl Write) -> Result<()> {
+    for result in results.iter_mut().filter(|result| result.candidates.len() > 1) {
+        let count = result.candidates.len();
+        let current = result.candidates
+            .iter()
+            .position(|candidate| result.lyrics_frame.as_ref() == Some(&candidate.frame))
+            .unwrap_or(0);
+        writeln!(prompt, "{} has {} lyrics frames:", result.path.display(), count)?;
+        for (index, candidate) in result.candidates.iter().enumerate() {
+            let description = match candidate.description.as_str() {
+                "" => String::new(),
+                description => format!(" \"{}\"", description),
+            };
+            let preview = candidate.preview(FRAME_PREVIEW_CHARS);
+            writeln!(prompt, "  {}) {}{}: {}", index + 1, candidate.frame, description, preview)?;
         }
-
-        match extract_lyrics_from_file(file_path) {
-            Ok(Some(lyrics)) => {
-                all_lyrics.push_str(&lyrics);
-                all_lyrics.push('\n');
-                info!("Extracted lyrics from {}", file_path.display());
-            }
-            Ok(None) => {
-                warn!("No lyrics found in {}", file_path.display());
-                if include_names {
-                    all_lyrics.push_str("[No lyrics found]\n");
-                }
+        
+        let choice = loop {
+            write!(prompt, "Use which one? [{}] ", current + 1)?;
+            prompt.flush()?;
+            let mut answer = String::new();
+            if input.read_line(&mut answer)? == 0 || answer.trim().is_empty() {
+                break current;
             }
-            Err(e) => {
-                error!("Failed to extract lyrics from {}: {}", file_path.display(), e);
-                if include_names {
-                    all_lyrics.push_str("[Failed to extract lyrics]\n");
-                }
+            match answer.trim().parse::<usize>() {
+                Ok(number) if (1..=count).contains(&number) => break number - 1,
+                _ => writeln!(prompt, "Enter a number from 1 to {}", count)?,
             }
-        }
+        };
+        let chosen = &result.candidates[choice];
+        result.lyrics = Some(chosen.text.clone());
+        result.lyrics_frame = Some(chosen.frame.clone());
     }
+    Ok(())
+}
 
-    Ok(all_lyrics)
+/// Log the files that still couldn't be read once any retries ran out
+fn report_failures(results: &[TrackResult]) {
+    let failed: Vec<&TrackResult> = results.iter().filter(|result| result.error.is_some()).collect();
+    if failed.is_empty() {
+        return;
+    }
+    error!("{} file(s) failed:", failed.len());
+    for result in failed {
+        error!("  {}: {}", result.path.display(), result.error.as_deref().unwrap_or_default());
+    }
 }
 
-/// Extract lyrics from a single MP3 file
-fn extract_lyrics_from_file(file_path: &Path) -> Result<Option<String>> {
-    let tag = Tag::read_from_path(file_path)
-        .with_context(|| format!("Failed to read ID3 tag from {}", file_path.display()))?;
-    
-    // First check for USLT (Unsynchronized lyrics) frames
-    let mut lyrics_iter = tag.lyrics();
-    if let Some(lyrics_frame) = lyrics_iter.next() {
-        return Ok(Some(lyrics_frame.text.clone()));
+/// Print the `--dry-run` report of what would have been written
+fn print_dry_run(summary: &ExtractionSummary, audio_files: &InputFiles, output_characters: usize) {
+    println!("Files found:       {}", summary.files);
+    println!("Skipped (exclude): {}", audio_files.excluded);
+    println!("Skipped (size):    {}", audio_files.skipped);
+    println!("Skipped (since):   {}", audio_files.unmodified);
+    println!("Skipped (tags):    {}", audio_files.unreadable);
+    println!("With lyrics:       {}", summary.with_lyrics);
+    println!("Without lyrics:    {}", summary.without_lyrics);
+    println!("Errors:            {}", summary.errors);
+    println!("Output characters: {}", output_characters);
+}
+
+/// The case `--uppercase` or `--lowercase` asks for, if either
+fn text_case(args: &Args) -> Option<TextCase> {
+    match (args.uppercase, args.lowercase) {
+        (true, _) => Some(TextCase::Upper),
+        (_, true) => Some(TextCase::Lower),
+        _ => None,
     }
-    
-    // Check for COMM (Comments) frames that might contain lyrics
-    if let Some(comment) = tag.comments().find(|c| c.description == "LYRICS") {
-        return Ok(Some(comment.text.clone()));
+}
+
+/// Describe how the run went, for the end-of-run summary, including the files the filters left out
+/// when there were any
+fn format_summary(summary: &ExtractionSummary, audio_files: &InputFiles, output_bytes: usize) -> String {
+    let filtered = if audio_files.excluded + audio_files.skipped + audio_files.unmodified + audio_files.unreadable > 0 {
+        format!(
+            "; skipped {} by --exclude, {} over --max-file-size, {} not modified since --since, {} with \
+             unreadable tags",
+            audio_files.excluded, audio_files.skipped, audio_files.unmodified, audio_files.unreadable
+        )
+    } else {
+        String::new()
+    };
+    format!(
+        "Scanned {} file(s): {} with lyrics, {} without lyrics, {} error(s){}; {} bytes of output",
+        summary.files, summary.with_lyrics, summary.without_lyrics, summary.errors, filtered, output_bytes
+    )
+}
+
+/// Parse a `--since` as a time this long ago, such as "7d" or "90min", or an RFC 3339 timestamp
+fn parse_since(since: &str) -> Result<SystemTime, String> {
+    if let Ok(ago) = humantime::parse_duration(since) {
+        return SystemTime::now().checked_sub(ago).ok_or_else(|| format!("'{}' is too long ago", since));
     }
-    
-    // Check for TXXX (User defined text) frames
-    if let Some(text) = tag.extended_texts().find(|t| t.description == "LYRICS") {
-        return Ok(Some(text.value.clone()));
+    humantime::parse_rfc3339_weak(since)
+        .map_err(|_| format!("invalid time '{}' (use a duration like 7d or a time like 2024-01-31T12:00:00Z)", since))
+}
+
+/// Parse a `--tail-duration` as a positive number of seconds, such as "3" or "1.5"
+fn parse_tail_duration(seconds: &str) -> Result<Duration, String> {
+    let invalid = || format!("invalid duration '{}' (use a positive number of seconds)", seconds);
+    let seconds: f64 = seconds.trim().parse().map_err(|_| invalid())?;
+    if seconds <= 0.0 {
+        return Err(invalid());
     }
-    
-    // Check common lyric frame IDs
-    for frame_id in &["LYRICS", "SYLT", "LYRW", "UNSYNCEDLYRICS"] {
-        if let Some(frame) = TagLike::get(&tag, frame_id) {
-            if let Some(content) = frame.content().text() {
-                return Ok(Some(content.to_string()));
+    Duration::try_from_secs_f64(seconds).map_err(|_| invalid())
+}
+
+/// Parse a `--max-file-size` such as "50MB", "1.5G" or a plain number of bytes
+fn parse_size(size: &str) -> Result<u64, String> {
+    let size = size.trim();
+    let split = size.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(size.len());
+    let (number, unit) = size.split_at(split);
+    let number: f64 = number.parse().map_err(|_| format!("invalid size '{}'", size))?;
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" | "kib" => 1 << 10,
+        "m" | "mb" | "mib" => 1 << 20,
+        "g" | "gb" | "gib" => 1 << 30,
+        _ => return Err(format!("unknown size unit '{}' (use B, KB, MB or GB)", unit.trim())),
+    };
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// Expand the `\n`, `\t` and `\\` escapes in a `--track-prefix` or `--track-suffix`, leaving any
+/// other backslash as it is
+fn parse_escapes(text: &str) -> Result<String, String> {
+    let mut expanded = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            expanded.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => expanded.push('\n'),
+            Some('t') => expanded.push('\t'),
+            Some('\\') => expanded.push('\\'),
+            Some(other) => {
+                expanded.push('\\');
+                expanded.push(other);
             }
+            None => expanded.push('\\'),
         }
     }
-    
-    Ok(None)
+    Ok(expanded)
 }
 
-/// Write the extracted lyrics to a file
-fn write_to_file(output_path: &str, content: &str) -> Result<()> {
-    let mut file = File::create(output_path)
-        .with_context(|| format!("Failed to create output file {}", output_path))?;
-    
-    file.write_all(content.as_bytes())
-        .with_context(|| format!("Failed to write to output file {}", output_path))?;
-    
-    Ok(())
+/// Parse an `--encoding` or `--output-encoding` label such as "shift_jis" or "latin1"
+fn parse_encoding(label: &str) -> Result<&'static Encoding, String> {
+    Encoding::for_label(label.as_bytes()).ok_or_else(|| format!("unknown encoding '{}'", label))
+}
+
+/// Parse an `--output-encoding` label, refusing ones that encoding_rs can only decode
+///
+/// It writes UTF-16 and the "replacement" encoding as UTF-8, which isn't what was asked for.
+fn parse_output_encoding(label: &str) -> Result<&'static Encoding, String> {
+    let encoding = parse_encoding(label)?;
+    if encoding.output_encoding() != encoding {
+        return Err(format!("'{}' can be read but not written; use utf-8 or a single or multi-byte charset", label));
+    }
+    Ok(encoding)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use tempfile::tempdir;
-    use std::fs::{self, File};
-    use std::io::Write;
+    use mdmp3lyrics2txt::{LyricsCandidate, LyricsFrame};
+    use crate::test_util::track;
+    use std::fs;
 
-    // Helper function to create a test MP3 file with lyrics
-    fn create_test_mp3(dir: &Path, filename: &str, lyrics: Option<&str>) -> PathBuf {
-        let file_path = dir.join(filename);
-        
-        // Create a minimal MP3 file with a valid ID3 tag structure
-        let mut file = File::create(&file_path).unwrap();
-        
-        // ID3v2 header (10 bytes)
-        let id3_header = [
-            b'I', b'D', b'3',  // ID3 marker
-            0x04, 0x00,         // Version 2.4.0
-            0x00,               // Flags
-            0x00, 0x00, 0x00, 0x0A  // Size (10 bytes following the header)
-        ];
-        
-        // Write headers and some minimal audio-like data
-        file.write_all(&id3_header).unwrap();
-        // Add 10 bytes of empty padding to match the size in the header
-        file.write_all(&[0; 10]).unwrap();
-        // Add some MP3-like frame data
-        file.write_all(&[0xFF, 0xFB, 0x90, 0x44, 0x00]).unwrap();
-        file.flush().unwrap();
-        
-        // If lyrics are provided, create a tag with lyrics
-        if let Some(lyrics_text) = lyrics {
-            let mut tag = Tag::new();
-            
-            // Add an Unsynchronized lyrics frame
-            use id3::frame::Lyrics;
-            use id3::TagLike;
-            
-            let lyrics_frame = Lyrics {
-                lang: String::from_utf8(vec![b'e', b'n', b'g']).unwrap(),
-                description: String::new(),
-                text: lyrics_text.to_string(),
-            };
-            tag.add_frame(lyrics_frame);
-            
-            tag.write_to_path(&file_path, id3::Version::Id3v24).unwrap();
+    #[test]
+    fn test_parse_encoding() {
+        assert_eq!(parse_encoding("shift_jis").unwrap(), encoding_rs::SHIFT_JIS);
+        assert_eq!(parse_encoding("latin1").unwrap(), encoding_rs::WINDOWS_1252);
+        assert!(parse_encoding("not-a-charset").is_err());
+
+        assert_eq!(parse_output_encoding("iso-2022-jp").unwrap(), encoding_rs::ISO_2022_JP);
+        assert_eq!(parse_output_encoding("utf-8").unwrap(), encoding_rs::UTF_8);
+        for label in ["utf-16le", "utf-16be", "iso-2022-kr"] {
+            assert!(parse_output_encoding(label).is_err(), "{}", label);
         }
-        
-        file_path
     }
 
     #[test]
-    fn test_find_mp3_files_single_file() {
-        let temp_dir = tempdir().unwrap();
-        let mp3_path = create_test_mp3(temp_dir.path(), "test.mp3", None);
-        
-        let files = find_mp3_files(mp3_path.to_str().unwrap(), false).unwrap();
-        
-        assert_eq!(files.len(), 1);
-        assert_eq!(files[0], mp3_path);
+    fn test_format_summary() {
+        let summary = ExtractionSummary { files: 4, with_lyrics: 2, without_lyrics: 1, errors: 1 };
+        assert_eq!(
+            format_summary(&summary, &InputFiles::default(), 1536),
+            "Scanned 4 file(s): 2 with lyrics, 1 without lyrics, 1 error(s); 1536 bytes of output"
+        );
+        let filtered = InputFiles { excluded: 3, skipped: 1, unreadable: 2, ..Default::default() };
+        assert_eq!(
+            format_summary(&summary, &filtered, 1536),
+            "Scanned 4 file(s): 2 with lyrics, 1 without lyrics, 1 error(s); skipped 3 by --exclude, 1 over \
+             --max-file-size, 0 not modified since --since, 2 with unreadable tags; 1536 bytes of output"
+        );
     }
 
     #[test]
-    fn test_find_mp3_files_directory() {
-        let temp_dir = tempdir().unwrap();
-        let mp3_path1 = create_test_mp3(temp_dir.path(), "test1.mp3", None);
-        let mp3_path2 = create_test_mp3(temp_dir.path(), "test2.mp3", None);
-        
-        // Create a non-MP3 file
-        let txt_path = temp_dir.path().join("test.txt");
-        File::create(&txt_path).unwrap();
-        
-        let files = find_mp3_files(temp_dir.path().to_str().unwrap(), false).unwrap();
-        
-        assert_eq!(files.len(), 2);
-        assert!(files.contains(&mp3_path1));
-        assert!(files.contains(&mp3_path2));
+    fn test_parse_since() {
+        let week_ago = parse_since("7d").unwrap();
+        let elapsed = SystemTime::now().duration_since(week_ago).unwrap();
+        assert!(elapsed >= Duration::from_secs(7 * 24 * 3600) && elapsed < Duration::from_secs(7 * 24 * 3600 + 60));
+        assert_eq!(parse_since("2024-01-31T12:00:00Z").unwrap(), humantime::parse_rfc3339("2024-01-31T12:00:00Z").unwrap());
+        assert!(parse_since("last tuesday").is_err());
     }
 
     #[test]
-    fn test_find_mp3_files_recursive() {
-        let temp_dir = tempdir().unwrap();
-        let mp3_path1 = create_test_mp3(temp_dir.path(), "test1.mp3", None);
-        
-        // Create a subdirectory
-        let sub_dir = temp_dir.path().join("subdir");
-        fs::create_dir(&sub_dir).unwrap();
-        let mp3_path2 = create_test_mp3(&sub_dir, "test2.mp3", None);
-        
-        // Test non-recursive (should find only one file)
-        let files_non_recursive = find_mp3_files(temp_dir.path().to_str().unwrap(), false).unwrap();
-        assert_eq!(files_non_recursive.len(), 1);
-        assert!(files_non_recursive.contains(&mp3_path1));
-        
-        // Test recursive (should find both files)
-        let files_recursive = find_mp3_files(temp_dir.path().to_str().unwrap(), true).unwrap();
-        assert_eq!(files_recursive.len(), 2);
-        assert!(files_recursive.contains(&mp3_path1));
-        assert!(files_recursive.contains(&mp3_path2));
+    fn test_parse_tail_duration() {
+        assert_eq!(parse_tail_duration("3").unwrap(), Duration::from_secs(3));
+        assert_eq!(parse_tail_duration("1.5").unwrap(), Duration::from_millis(1500));
+        for invalid in ["0", "-2", "soon", "inf"] {
+            assert!(parse_tail_duration(invalid).is_err(), "{}", invalid);
+        }
     }
 
     #[test]
-    fn test_extract_lyrics_from_file() {
-        let temp_dir = tempdir().unwrap();
-        let test_lyrics = "This is a test lyric\nSecond line";
-        let mp3_path = create_test_mp3(temp_dir.path(), "test.mp3", Some(test_lyrics));
-        
-        let lyrics = extract_lyrics_from_file(&mp3_path).unwrap();
-        
-        assert!(lyrics.is_some());
-        assert_eq!(lyrics.unwrap(), test_lyrics);
+    fn test_parse_size() {
+        assert_eq!(parse_size("1024").unwrap(), 1024);
+        assert_eq!(parse_size("50MB").unwrap(), 50 * 1024 * 1024);
+        assert_eq!(parse_size("1.5 g").unwrap(), 3 * 1024 * 1024 * 512);
+        assert_eq!(parse_size("10KiB").unwrap(), 10 * 1024);
+        assert!(parse_size("MB").is_err());
+        assert!(parse_size("10 parsecs").is_err());
     }
 
     #[test]
-    fn test_extract_lyrics_no_lyrics() {
-        let temp_dir = tempdir().unwrap();
-        
-        // For this test, we'll create a more valid MP3-like file
-        let file_path = temp_dir.path().join("test.mp3");
-        let mut file = File::create(&file_path).unwrap();
-        
-        // ID3v2 header (10 bytes)
-        let id3_header = [
-            b'I', b'D', b'3',  // ID3 marker
-            0x04, 0x00,         // Version 2.4.0
-            0x00,               // Flags
-            0x00, 0x00, 0x00, 0x0A  // Size (10 bytes following the header)
-        ];
-        
-        // Write headers and some minimal audio-like data
-        file.write_all(&id3_header).unwrap();
-        // Add 10 bytes of empty padding to match the size in the header
-        file.write_all(&[0; 10]).unwrap();
-        // Add some MP3-like frame data
-        file.write_all(&[0xFF, 0xFB, 0x90, 0x44, 0x00]).unwrap();
-        file.flush().unwrap();
-        
-        // Now try to extract lyrics (should be None because we didn't add any)
-        let lyrics = extract_lyrics_from_file(&file_path).unwrap();
-        
-        assert!(lyrics.is_none());
+    fn test_parse_escapes() {
+        assert_eq!(parse_escapes(r"\n\tPage\\break\n").unwrap(), "\n\tPage\\break\n");
+        assert_eq!(parse_escapes(r"C:\music\").unwrap(), r"C:\music\");
+        assert_eq!(parse_escapes("plain").unwrap(), "plain");
     }
 
     #[test]
-    fn test_extract_all_lyrics() {
-        let temp_dir = tempdir().unwrap();
-        let mp3_path1 = create_test_mp3(temp_dir.path(), "test1.mp3", Some("Lyrics for song 1"));
-        let mp3_path2 = create_test_mp3(temp_dir.path(), "test2.mp3", Some("Lyrics for song 2"));
-        
-        let mp3_files = vec![mp3_path1.clone(), mp3_path2.clone()];
-        
-        // Test without names or separators
-        let lyrics1 = extract_all_lyrics(&mp3_files, false, false, "").unwrap();
-        assert!(lyrics1.contains("Lyrics for song 1"));
-        assert!(lyrics1.contains("Lyrics for song 2"));
-        assert!(!lyrics1.contains("File:"));
+    fn test_pick_lyrics() {
+        let candidate = |language: &str, text: &str| LyricsCandidate {
+            frame: LyricsFrame { id: "USLT".to_string(), language: Some(language.to_string()) },
+            description: String::new(),
+            text: text.to_string(),
+        };
+        let choice = || TrackResult {
+            lyrics_frame: Some(candidate("eng", "English").frame),
+            candidates: vec![candidate("eng", "English"), candidate("jpn", "Japanese")],
+            ..track("song.mp3", Some("English"))
+        };
         
-        // Test with names
-        let lyrics2 = extract_all_lyrics(&mp3_files, true, false, "").unwrap();
-        assert!(lyrics2.contains("File:"));
-        assert!(lyrics2.contains(mp3_path1.to_str().unwrap()));
+        let mut results = [choice(), choice()];
+        let mut prompt = Vec::new();
+        pick_lyrics(&mut results, &mut "3\n2\n\n".as_bytes(), &mut prompt).unwrap();
+        assert_eq!(results[0].lyrics.as_deref(), Some("Japanese"));
+        assert_eq!(results[1].lyrics.as_deref(), Some("English"));
         
-        // Test with separator
-        let lyrics3 = extract_all_lyrics(&mp3_files, false, true, "---").unwrap();
-        assert!(lyrics3.contains("---"));
+        let prompt = String::from_utf8(prompt).unwrap();
+        assert!(prompt.starts_with("song.mp3 has 2 lyrics frames:\n  1) USLT (eng): English\n  2) USLT (jpn): Japanese\n"));
+        assert!(prompt.contains("Enter a number from 1 to 2"));
     }
 
     #[test]
-    fn test_write_to_file() {
-        let temp_dir = tempdir().unwrap();
-        let output_path = temp_dir.path().join("output.txt");
-        let content = "Test content";
-        
-        write_to_file(output_path.to_str().unwrap(), content).unwrap();
-        
-        let read_content = fs::read_to_string(output_path).unwrap();
-        assert_eq!(read_content, content);
+    fn test_max_output_bytes() {
+        use id3::TagLike;
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let music = temp_dir.path().join("music");
+        fs::create_dir(&music).unwrap();
+        for (name, lyrics) in [("1.mp3", "One"), ("2.mp3", "Two"), ("3.mp3", "Three")] {
+            let path = music.join(name);
+            fs::write(&path, [0xFF, 0xFB, 0x90, 0x44, 0x00]).unwrap();
+            let mut tag = id3::Tag::new();
+            tag.add_frame(id3::frame::Lyrics { lang: "eng".to_string(), description: String::new(), text: lyrics.to_string() });
+            tag.write_to_path(&path, id3::Version::Id3v24).unwrap();
+        }
+        let output = temp_dir.path().join("lyrics.txt");
+        let run = |max: &str, extra: &[&str]| {
+            let mut command = vec!["mdmp3lyrics2txt", "-i", music.to_str().unwrap(), "-o", output.to_str().unwrap()];
+            command.extend(["--no-summary", "--max-output-bytes", max]);
+            command.extend(extra);
+            let args = Args::try_parse_from(command).unwrap();
+            write_lyrics(&args, &args.find, &args.input, &args.find.collect_files(&args.input, args.max_tag_bytes).unwrap())
+        };
+
+        // Only whole tracks are written, and being cut short has its own exit code
+        assert_eq!(run("9", &[]).unwrap(), ExitCode::from(EXIT_TRUNCATED));
+        assert_eq!(fs::read_to_string(&output).unwrap(), "One\nTwo\n");
+        assert_eq!(run("9", &["--raw"]).unwrap(), ExitCode::from(EXIT_TRUNCATED));
+        assert_eq!(fs::read_to_string(&output).unwrap(), "One\nTwo\n");
+        assert_eq!(run("14", &[]).unwrap(), ExitCode::SUCCESS);
+        assert_eq!(fs::read_to_string(&output).unwrap(), "One\nTwo\nThree\n");
+
+        // A table of contents would list tracks that get left out
+        assert!(run("9", &["--toc"]).is_err());
+
+        let args = Args::try_parse_from(["mdmp3lyrics2txt", "-i", "music"]).unwrap();
+        let raw_args = Args::try_parse_from(["mdmp3lyrics2txt", "-i", "music", "--raw"]).unwrap();
+        let raw = |raw: Option<&[u8]>| TrackResult { raw_lyrics: raw.map(<[u8]>::to_vec), ..track("song.mp3", None) };
+        let results = [raw(Some(b"One")), raw(None), raw(Some(b"Three"))];
+        assert_eq!(tracks_left_out(&args, &results, 1), 2);
+        assert_eq!(tracks_left_out(&raw_args, &results, 1), 1);
+    }
+
+    #[test]
+    fn test_write_lyrics_uses_watch_search_options() {
+        use id3::TagLike;
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let music = temp_dir.path().join("music");
+        fs::create_dir(&music).unwrap();
+        let song = music.join("song.mp3");
+        fs::write(&song, [0xFF, 0xFB, 0x90, 0x44, 0x00]).unwrap();
+        let mut tag = id3::Tag::new();
+        tag.add_frame(id3::frame::Lyrics { lang: "eng".to_string(), description: String::new(), text: "Lyrics".to_string() });
+        tag.write_to_path(&song, id3::Version::Id3v24).unwrap();
+        let output = temp_dir.path().join("lyrics.txt");
+        let (music, output) = (music.to_str().unwrap(), output.to_str().unwrap());
+        let command = ["mdmp3lyrics2txt", "-o", output, "--no-summary", "watch", "-i", music, "--group-by", "artist"];
+        let mut args = Args::try_parse_from(command).unwrap();
+        let Some(Commands::Watch { input, find, .. }) = args.command.take() else {
+            panic!("expected the watch subcommand");
+        };
+
+        // The subcommand's --group-by applies, not the unset top-level one
+        let files = find.collect_files(&input, args.max_tag_bytes).unwrap();
+        write_lyrics(&args, &find, &input, &files).unwrap();
+        assert_eq!(fs::read_to_string(output).unwrap(), "=== Unknown ===\n\nLyrics\n");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_apply_config_to_subcommand() {
+        let matches = Args::command().try_get_matches_from(["mdmp3lyrics2txt", "watch", "-i", "music"]).unwrap();
+        let mut args = Args::from_arg_matches(&matches).unwrap();
+        args.apply_config(Config { recursive: Some(true), ..Default::default() }, &matches);
+        assert!(matches!(args.command, Some(Commands::Watch { find: FindArgs { recursive: true, .. }, .. })));
+        assert!(!args.find.recursive);
+    }
+
+    #[test]
+    fn test_extract_options_for_subcommands() {
+        let command = ["mdmp3lyrics2txt", "--fail-fast", "--mmap", "--language", "deu", "split", "-i", "music"];
+        let args = Args::try_parse_from(command).unwrap();
+        let options = extract_options(&args, &InputFiles::default());
+        assert!(options.fail_fast && options.mmap);
+        assert_eq!(options.language.as_deref(), Some("deu"));
+
+        let command = ["mdmp3lyrics2txt", "--include-txt", "stats", "-i", "music"];
+        assert!(check_subcommand_options(&Args::try_parse_from(command).unwrap(), "stats").is_err());
+        let command = ["mdmp3lyrics2txt", "--strip-html", "count", "-i", "music"];
+        assert!(check_subcommand_options(&Args::try_parse_from(command).unwrap(), "count").is_ok());
+    }
+
+    #[test]
+    fn test_quiet_conflicts_with_verbose() {
+        assert!(Args::try_parse_from(["mdmp3lyrics2txt", "-i", "music", "--quiet"]).is_ok());
+        assert!(Args::try_parse_from(["mdmp3lyrics2txt", "-i", "music", "--quiet", "--verbose"]).is_err());
+    }
+}
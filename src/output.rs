@@ -0,0 +1,833 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Component, Path, PathBuf};
+use clap::ValueEnum;
+use encoding_rs::{EncoderResult, Encoding, UTF_8};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use log::debug;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use crate::error::{Error, Result};
+use crate::extract::TrackResult;
+use crate::template::Template;
+
+/// Write each track's lyrics to a `.txt` file, returning how many were written
+///
+/// Files go next to each audio file, or under `mirror`'s output directory when one is given. They
+/// are named like the audio file, or by `file_names` when given. Tracks that would share a name,
+/// such as `song.mp3` and `song.flac`, get a numeric suffix such as `song (2).txt`.
+pub fn split_lyrics(
+    results: &[TrackResult],
+    include_empty: bool,
+    overwrite: bool,
+    mirror: Option<&MirroredDir>,
+    file_names: Option<&Template>,
+) -> Result<usize> {
+    let target_path = |index: usize, result: &TrackResult| {
+        let sidecar = match mirror {
+            Some(mirror) => mirror.target_path(&result.path)?,
+            None => sidecar_path(&result.path),
+        };
+        let Some(template) = file_names else {
+            return Ok(sidecar);
+        };
+        let name = template.render_file_name(index, result);
+        let relative = Path::new(&name);
+        let usable = relative.file_name().is_some()
+            && relative.components().all(|component| matches!(component, Component::Normal(_)));
+        if !usable {
+            return Err(Error::BadFileName { path: result.path.clone(), name });
+        }
+        Ok(sidecar.with_file_name(relative))
+    };
+    let mut targets: Vec<(PathBuf, &str)> = results
+        .iter()
+        .enumerate()
+        .filter(|(_, result)| result.error.is_none())
+        .filter_map(|(index, result)| match &result.lyrics {
+            Some(lyrics) => Some(target_path(index, result).map(|path| (path, lyrics.as_str()))),
+            None if include_empty => Some(target_path(index, result).map(|path| (path, ""))),
+            None => None,
+        })
+        .collect::<Result<_>>()?;
+    disambiguate(&mut targets);
+    
+    // Check every target up front so a clash doesn't leave a half-finished split behind
+    if !overwrite {
+        if let Some((existing, _)) = targets.iter().find(|(path, _)| path.exists()) {
+            return Err(Error::AlreadyExists(existing.clone()));
+        }
+    }
+    
+    for (path, lyrics) in &targets {
+        if let Some(parent) = path.parent().filter(|_| mirror.is_some() || file_names.is_some()) {
+            fs::create_dir_all(parent).map_err(|e| Error::io("Failed to create directory", parent, e))?;
+        }
+        write_to_file(path, lyrics, false, false)?;
+        debug!("Wrote {}", path.display());
+    }
+    
+    Ok(targets.len())
+}
+
+/// Give each target after the first with the same path a ` (2)`, ` (3)` and so on suffix, skipping
+/// any that another target already has
+///
+/// Paths are compared ignoring case, as names differing only in case are the same file on
+/// case-insensitive file systems.
+fn disambiguate(targets: &mut [(PathBuf, &str)]) {
+    let folded = |path: &Path| path.to_string_lossy().to_lowercase();
+    let mut taken: HashSet<String> = HashSet::new();
+    let mut clashing = Vec::new();
+    for (index, (path, _)) in targets.iter().enumerate() {
+        if !taken.insert(folded(path)) {
+            clashing.push(index);
+        }
+    }
+    for index in clashing {
+        let path = &targets[index].0;
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+        let extension = path.extension().map(|extension| format!(".{}", extension.to_string_lossy())).unwrap_or_default();
+        let renamed = (2..)
+            .map(|n| path.with_file_name(format!("{} ({}){}", stem, n, extension)))
+            .find(|renamed| !taken.contains(&folded(renamed)))
+            .expect("some suffix is free");
+        debug!("{} is already taken, using {}", path.display(), renamed.display());
+        taken.insert(folded(&renamed));
+        targets[index].0 = renamed;
+    }
+}
+
+/// The `.txt` path that sits next to an audio file
+pub fn sidecar_path(file_path: &Path) -> PathBuf {
+    file_path.with_extension("txt")
+}
+
+/// An output directory that recreates the layout of the input directories the files were found in
+#[derive(Debug, Clone)]
+pub struct MirroredDir {
+    pub output_dir: PathBuf,
+    /// Directories that file paths are made relative to; the deepest one containing a file is used
+    pub input_roots: Vec<PathBuf>,
+}
+
+impl MirroredDir {
+    /// Where the `.txt` file for `file_path` goes under the output directory
+    ///
+    /// Fails for a file outside every input root, or one whose relative path would lead out of the
+    /// output directory.
+    pub fn target_path(&self, file_path: &Path) -> Result<PathBuf> {
+        let relative = relative_to_roots(file_path, &self.input_roots)
+            .ok_or_else(|| Error::OutsideOutputDir(file_path.to_path_buf()))?;
+        if !relative.components().all(|component| matches!(component, Component::Normal(_))) {
+            return Err(Error::OutsideOutputDir(file_path.to_path_buf()));
+        }
+        Ok(sidecar_path(&self.output_dir.join(relative)))
+    }
+}
+
+/// `file_path` relative to the deepest of `roots` that contains it, if any does
+pub(crate) fn relative_to_roots<'a>(file_path: &'a Path, roots: &[PathBuf]) -> Option<&'a Path> {
+    roots
+        .iter()
+        .filter_map(|root| file_path.strip_prefix(root).ok())
+        .min_by_key(|relative| relative.components().count())
+}
+
+/// Line ending style for the output file
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LineEnding {
+    /// Unix style `\n`
+    #[default]
+    Lf,
+    /// Windows style `\r\n`
+    Crlf,
+    /// Whichever is usual on this platform
+    Native,
+}
+
+impl LineEnding {
+    fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::Crlf => "\r\n",
+            LineEnding::Native if cfg!(windows) => "\r\n",
+            LineEnding::Native => "\n",
+        }
+    }
+}
+
+/// Convert every `\r\n`, lone `\r` and `\n` in `text` to `ending`
+pub fn normalize_line_endings(text: &str, ending: LineEnding) -> String {
+    let unified = text.replace("\r\n", "\n").replace('\r', "\n");
+    match ending.as_str() {
+        "\n" => unified,
+        newline => unified.replace('\n', newline),
+    }
+}
+
+/// Applies [`normalize_line_endings`] to text that arrives in pieces
+///
+/// A piece ending in `\r` is held back until the next, in case that starts with the `\n` of a
+/// `\r\n` pair, so the result matches normalising the whole text at once.
+#[derive(Debug, Clone)]
+pub struct LineEndingStream {
+    ending: LineEnding,
+    pending_cr: bool,
+}
+
+impl LineEndingStream {
+    pub fn new(ending: LineEnding) -> Self {
+        LineEndingStream { ending, pending_cr: false }
+    }
+
+    /// Normalise the next piece of text
+    pub fn push(&mut self, text: &str) -> String {
+        let mut text = if self.pending_cr { format!("\r{}", text) } else { text.to_string() };
+        self.pending_cr = text.ends_with('\r');
+        if self.pending_cr {
+            text.pop();
+        }
+        normalize_line_endings(&text, self.ending)
+    }
+
+    /// The line ending held back from the last piece, if it ended in `\r`
+    pub fn finish(self) -> String {
+        if self.pending_cr { self.ending.as_str().to_string() } else { String::new() }
+    }
+}
+
+/// Written between the existing content and the new lyrics when appending
+const APPEND_SEPARATOR: &str = "\n";
+
+/// Output path that stands for standard output
+pub const STDOUT_PATH: &str = "-";
+
+/// Whether `path` ends in `.gz`, so output to it should be compressed
+pub fn is_gzip_path(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("gz"))
+}
+
+/// Write the extracted lyrics to a file, replacing it or adding to the end when `append` is set
+///
+/// An `output_path` of [`STDOUT_PATH`] writes to standard output instead. The output is gzip
+/// compressed when `gzip` is set or the path ends in `.gz`; appending adds a new gzip member, which
+/// `gunzip` reads back as one stream.
+///
+/// The content is written byte for byte, so it needn't be UTF-8, as with `--raw` lyrics.
+pub fn write_to_file(output_path: &Path, content: impl AsRef<[u8]>, append: bool, gzip: bool) -> Result<()> {
+    let mut output = OutputWriter::open(output_path, append, gzip)?;
+    output.write_bytes(content.as_ref())?;
+    output.finish().map(|_| ())
+}
+
+/// An output file, or standard output, that the lyrics are written to a piece at a time
+///
+/// This writes exactly what [`write_to_file`] would for the pieces joined together, without
+/// needing them all in memory at once.
+pub struct OutputWriter {
+    sink: Sink,
+    /// How to describe a failed write, and what to name in it: the output path, or standard output
+    action: &'static str,
+    target: PathBuf,
+    /// Bytes of lyrics written so far, before any compression
+    bytes: usize,
+    /// Converts text out of UTF-8, when another encoding was asked for
+    encoding: Option<&'static Encoding>,
+    /// Characters the encoder had no way to write, which were written as `?`
+    unmappable: usize,
+}
+
+/// Where an [`OutputWriter`] sends its bytes
+enum Sink {
+    Plain(Box<dyn Write>),
+    Gzip(GzEncoder<Box<dyn Write>>),
+}
+
+impl OutputWriter {
+    /// Open `output_path` the same way [`write_to_file`] does, ready for the first piece
+    pub fn open(output_path: &Path, append: bool, gzip: bool) -> Result<Self> {
+        let gzip = gzip || is_gzip_path(output_path);
+        let output = if output_path == Path::new(STDOUT_PATH) {
+            OutputWriter::new(Box::new(io::stdout().lock()), "Failed to write to", "standard output", gzip)
+        } else {
+            let file = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .append(append)
+                .truncate(!append)
+                .open(output_path)
+                .map_err(|e| Error::io("Failed to create output file", output_path, e))?;
+            
+            // Only separate from earlier runs, so a brand-new file doesn't start with a blank line
+            let has_content = append
+                && file.metadata()
+                    .map_err(|e| Error::io("Failed to read output file", output_path, e))?
+                    .len() > 0;
+            let mut output = OutputWriter::new(
+                Box::new(BufWriter::new(file)),
+                "Failed to write to output file",
+                output_path,
+                gzip,
+            );
+            if has_content {
+                output.write_sink(APPEND_SEPARATOR.as_bytes())?;
+            }
+            output
+        };
+        Ok(output)
+    }
+
+    fn new(writer: Box<dyn Write>, action: &'static str, target: impl Into<PathBuf>, gzip: bool) -> Self {
+        let sink = if gzip { Sink::Gzip(GzEncoder::new(writer, Compression::default())) } else { Sink::Plain(writer) };
+        OutputWriter { sink, action, target: target.into(), bytes: 0, encoding: None, unmappable: 0 }
+    }
+
+    /// Write text in `encoding` rather than UTF-8 from here on
+    ///
+    /// Characters it can't represent are written as `?` and counted in
+    /// [`OutputWriter::unmappable`]. Each piece is encoded on its own, so stateful encodings such
+    /// as ISO-2022-JP end every piece back in ASCII.
+    pub fn with_encoding(mut self, encoding: &'static Encoding) -> Self {
+        self.encoding = (encoding != UTF_8).then_some(encoding);
+        self
+    }
+
+    /// Write the next piece of the lyrics
+    pub fn write_str(&mut self, text: &str) -> Result<()> {
+        self.write_str_within(text, None).map(|_| ())
+    }
+
+    /// Write the next piece of the lyrics if the output stays within `limit` bytes once it's
+    /// encoded, returning whether it was written
+    pub fn write_str_within(&mut self, text: &str, limit: Option<u64>) -> Result<bool> {
+        let (encoded, unmappable) = self.encode(text);
+        if limit.is_some_and(|limit| (self.bytes + encoded.len()) as u64 > limit) {
+            return Ok(false);
+        }
+        self.write_bytes(&encoded)?;
+        self.unmappable += unmappable;
+        Ok(true)
+    }
+
+    /// How many characters couldn't be written in the chosen encoding and became `?`
+    pub fn unmappable(&self) -> usize {
+        self.unmappable
+    }
+
+    /// Write the next piece of the lyrics as is, whether or not it's valid UTF-8
+    pub fn write_bytes(&mut self, bytes: &[u8]) -> Result<()> {
+        self.write_sink(bytes)?;
+        self.bytes += bytes.len();
+        Ok(())
+    }
+
+    /// How many bytes of lyrics have been written so far, before any compression
+    pub fn bytes_written(&self) -> usize {
+        self.bytes
+    }
+
+    /// Finish any compression and flush, returning how many bytes of lyrics were written
+    pub fn finish(self) -> Result<usize> {
+        let flushed = match self.sink {
+            Sink::Plain(mut writer) => writer.flush(),
+            Sink::Gzip(encoder) => encoder.finish().and_then(|mut writer| writer.flush()),
+        };
+        flushed.map_err(|e| Error::io(self.action, &self.target, e))?;
+        Ok(self.bytes)
+    }
+
+    /// `text` in the output encoding, with how many characters in it had to become `?`
+    fn encode<'a>(&self, text: &'a str) -> (Cow<'a, [u8]>, usize) {
+        let Some(encoding) = self.encoding else {
+            return (Cow::Borrowed(text.as_bytes()), 0);
+        };
+        let mut encoder = encoding.new_encoder();
+        let mut encoded = Vec::with_capacity(text.len() + 16);
+        let mut unmappable = 0;
+        let mut rest = text;
+        loop {
+            let (result, read) = encoder.encode_from_utf8_to_vec_without_replacement(rest, &mut encoded, true);
+            rest = &rest[read..];
+            match result {
+                EncoderResult::InputEmpty => return (Cow::Owned(encoded), unmappable),
+                EncoderResult::OutputFull => encoded.reserve(rest.len() + 16),
+                EncoderResult::Unmappable(_) => {
+                    encoded.push(b'?');
+                    unmappable += 1;
+                }
+            }
+        }
+    }
+
+    fn write_sink(&mut self, bytes: &[u8]) -> Result<()> {
+        let written = match &mut self.sink {
+            Sink::Plain(writer) => writer.write_all(bytes),
+            Sink::Gzip(encoder) => encoder.write_all(bytes),
+        };
+        written.map_err(|e| Error::io(self.action, &self.target, e))
+    }
+}
+
+/// Write the extracted lyrics to any output and flush it
+pub fn write_to<W: Write>(writer: &mut W, content: &str) -> io::Result<()> {
+    writer.write_all(content.as_bytes())?;
+    writer.flush()
+}
+
+/// Write a TSV manifest of the source files behind an output file
+///
+/// Each row has the source path, its SHA-256, whether lyrics were found and the byte length of the
+/// extracted lyrics, so runs can be compared with a plain diff.
+pub fn write_manifest(manifest_path: &Path, results: &[TrackResult]) -> Result<()> {
+    let mut manifest = String::from("path\tsha256\tlyrics_found\tlyrics_bytes\n");
+    for result in results {
+        let lyrics_bytes = result.lyrics.as_ref().map_or(0, String::len);
+        manifest.push_str(&format!(
+            "{}\t{}\t{}\t{}\n",
+            result.path.display(),
+            sha256_file(result.local_path.as_ref().unwrap_or(&result.path))?,
+            result.lyrics.is_some(),
+            lyrics_bytes,
+        ));
+    }
+    write_to_file(manifest_path, &manifest, false, false)
+}
+
+/// Write a TSV census of the distinct artists and albums in the tags, with how many tracks each has
+///
+/// Artist rows come before album rows, each sorted by most tracks then by name. Tracks without
+/// lyrics count too, as their tags were still read.
+pub fn write_tag_summary(summary_path: &Path, results: &[TrackResult]) -> Result<()> {
+    let mut summary = String::from("tag\tvalue\ttracks\n");
+    let artists = count_values(results.iter().filter_map(|result| result.artist.as_deref()));
+    let albums = count_values(results.iter().filter_map(|result| result.album.as_deref()));
+    for (tag, counts) in [("artist", artists), ("album", albums)] {
+        for (value, count) in counts {
+            // A tab or line break in a tag would throw the columns out
+            let value = value.replace(['\t', '\n', '\r'], " ");
+            summary.push_str(&format!("{}\t{}\t{}\n", tag, value, count));
+        }
+    }
+    write_to_file(summary_path, &summary, false, false)
+}
+
+/// Write a report of the artist and title pairs that more than one file is tagged with, and the
+/// files for each, sorted by artist then title
+///
+/// Pairs are compared ignoring case and differences in whitespace, and named as the first file
+/// tags them. Files without both tags are left out.
+pub fn write_duplicates_report(report_path: &Path, results: &[TrackResult]) -> Result<()> {
+    let normalize = |value: &str| value.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase();
+    let mut tracks: BTreeMap<(String, String), Vec<&TrackResult>> = BTreeMap::new();
+    for result in results {
+        if let (Some(artist), Some(title)) = (&result.artist, &result.title) {
+            tracks.entry((normalize(artist), normalize(title))).or_default().push(result);
+        }
+    }
+
+    let mut report = String::new();
+    for duplicates in tracks.values().filter(|duplicates| duplicates.len() > 1) {
+        let first = duplicates[0];
+        report.push_str(&format!(
+            "{} - {} ({} files)\n",
+            first.artist.as_deref().unwrap_or_default(),
+            first.title.as_deref().unwrap_or_default(),
+            duplicates.len(),
+        ));
+        for duplicate in duplicates {
+            report.push_str(&format!("  {}\n", duplicate.path.display()));
+        }
+    }
+    write_to_file(report_path, &report, false, false)
+}
+
+/// Write a JSON-lines log with a `{"path": ..., "error": ...}` record for each file that couldn't
+/// be read, leaving the file empty when none failed
+pub fn write_error_log(log_path: &Path, results: &[TrackResult]) -> Result<()> {
+    #[derive(Serialize)]
+    struct Failure<'a> {
+        path: String,
+        error: &'a str,
+    }
+
+    let mut log = String::new();
+    for result in results {
+        if let Some(error) = &result.error {
+            let failure = Failure { path: result.path.display().to_string(), error };
+            log.push_str(&serde_json::to_string(&failure)?);
+            log.push('\n');
+        }
+    }
+    write_to_file(log_path, &log, false, false)
+}
+
+/// Count how many times each value turns up, most common first
+fn count_values<'a>(values: impl Iterator<Item = &'a str>) -> Vec<(&'a str, usize)> {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for value in values {
+        *counts.entry(value).or_default() += 1;
+    }
+    let mut counts: Vec<(&str, usize)> = counts.into_iter().collect();
+    counts.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+    counts
+}
+
+/// Hash a file's contents as lowercase hex
+fn sha256_file(path: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+    File::open(path)
+        .and_then(|mut file| io::copy(&mut file, &mut hasher))
+        .map_err(|e| Error::io("Failed to read", path, e))?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extract::{extract_all_lyrics, ExtractOptions};
+    use crate::render::{render_text, render_text_pieces, TextOptions};
+    use crate::test_util::{create_test_flac, create_test_mp3, track};
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_split_lyrics() {
+        let temp_dir = tempdir().unwrap();
+        let with_lyrics = create_test_mp3(temp_dir.path(), "song1.mp3", Some("Lyrics for song 1"));
+        let without = create_test_mp3(temp_dir.path(), "song2.mp3", None);
+        let results = extract_all_lyrics(&[with_lyrics, without], &ExtractOptions::default(), 1).unwrap();
+        
+        assert_eq!(split_lyrics(&results, false, false, None, None).unwrap(), 1);
+        assert_eq!(fs::read_to_string(temp_dir.path().join("song1.txt")).unwrap(), "Lyrics for song 1");
+        assert!(!temp_dir.path().join("song2.txt").exists());
+        
+        // Existing files are only replaced with --overwrite
+        assert!(split_lyrics(&results, true, false, None, None).is_err());
+        assert!(!temp_dir.path().join("song2.txt").exists());
+        assert_eq!(split_lyrics(&results, true, true, None, None).unwrap(), 2);
+        assert_eq!(fs::read_to_string(temp_dir.path().join("song2.txt")).unwrap(), "");
+
+        // Files that only differ by extension, or by the case of their name, don't overwrite each other
+        let flac = create_test_flac(temp_dir.path(), "song1.flac", Some(("LYRICS", "FLAC lyrics")));
+        let upper = create_test_mp3(temp_dir.path(), "SONG1.mp3", Some("Shouted lyrics"));
+        let files = [temp_dir.path().join("song1.mp3"), flac, upper];
+        let results = extract_all_lyrics(&files, &ExtractOptions::default(), 1).unwrap();
+        assert_eq!(split_lyrics(&results, false, true, None, None).unwrap(), 3);
+        assert_eq!(fs::read_to_string(temp_dir.path().join("song1.txt")).unwrap(), "Lyrics for song 1");
+        assert_eq!(fs::read_to_string(temp_dir.path().join("song1 (2).txt")).unwrap(), "FLAC lyrics");
+        assert_eq!(fs::read_to_string(temp_dir.path().join("SONG1 (3).txt")).unwrap(), "Shouted lyrics");
+    }
+
+    #[test]
+    fn test_split_lyrics_output_dir() {
+        let temp_dir = tempdir().unwrap();
+        let library = temp_dir.path().join("library");
+        let album = library.join("album");
+        fs::create_dir_all(&album).unwrap();
+        let top = create_test_mp3(&library, "top.mp3", Some("Top lyrics"));
+        let nested = create_test_mp3(&album, "nested.mp3", Some("Nested lyrics"));
+        let results = extract_all_lyrics(&[top, nested.clone()], &ExtractOptions::default(), 1).unwrap();
+
+        let output_dir = temp_dir.path().join("out");
+        let mirror = MirroredDir { output_dir: output_dir.clone(), input_roots: vec![library.clone()] };
+        assert_eq!(split_lyrics(&results, false, false, Some(&mirror), None).unwrap(), 2);
+        assert_eq!(fs::read_to_string(output_dir.join("top.txt")).unwrap(), "Top lyrics");
+        assert_eq!(fs::read_to_string(output_dir.join("album").join("nested.txt")).unwrap(), "Nested lyrics");
+        assert!(!album.join("nested.txt").exists());
+
+        // The deepest root wins, and paths that climb out are refused
+        let nested_root = MirroredDir { output_dir: output_dir.clone(), input_roots: vec![library.clone(), album] };
+        assert_eq!(nested_root.target_path(&nested).unwrap(), output_dir.join("nested.txt"));
+        let escaping = library.join("..").join("elsewhere.mp3");
+        assert!(matches!(mirror.target_path(&escaping), Err(Error::OutsideOutputDir(_))));
+        assert!(matches!(mirror.target_path(Path::new("/other/song.mp3")), Err(Error::OutsideOutputDir(_))));
+    }
+
+    #[test]
+    fn test_split_lyrics_filename_template() {
+        use id3::TagLike;
+
+        let temp_dir = tempdir().unwrap();
+        let mut files = Vec::new();
+        for (filename, track, title) in [("a.mp3", 1, "Intro"), ("b.mp3", 2, "Same"), ("c.mp3", 3, "Same")] {
+            let path = create_test_mp3(temp_dir.path(), filename, Some(&format!("{} lyrics", filename)));
+            let mut tag = id3::Tag::read_from_path(&path).unwrap();
+            tag.set_title(title);
+            tag.set_track(track);
+            tag.write_to_path(&path, id3::Version::Id3v24).unwrap();
+            files.push(path);
+        }
+        let results = extract_all_lyrics(&files, &ExtractOptions::default(), 1).unwrap();
+
+        let numbered = Template::parse("{track:02} - {title}.txt").unwrap();
+        assert_eq!(split_lyrics(&results, false, false, None, Some(&numbered)).unwrap(), 3);
+        assert_eq!(fs::read_to_string(temp_dir.path().join("01 - Intro.txt")).unwrap(), "a.mp3 lyrics");
+        assert_eq!(fs::read_to_string(temp_dir.path().join("03 - Same.txt")).unwrap(), "c.mp3 lyrics");
+
+        // Tracks whose names clash are numbered after the first
+        let by_title = Template::parse("{title}.txt").unwrap();
+        assert_eq!(split_lyrics(&results, false, false, None, Some(&by_title)).unwrap(), 3);
+        assert_eq!(fs::read_to_string(temp_dir.path().join("Same.txt")).unwrap(), "b.mp3 lyrics");
+        assert_eq!(fs::read_to_string(temp_dir.path().join("Same (2).txt")).unwrap(), "c.mp3 lyrics");
+
+        let escaping = Template::parse("../{title}.txt").unwrap();
+        let error = split_lyrics(&results, false, true, None, Some(&escaping)).unwrap_err();
+        assert!(matches!(error, Error::BadFileName { .. }), "{:?}", error);
+    }
+
+    #[test]
+    fn test_write_to_file() {
+        let temp_dir = tempdir().unwrap();
+        let output_path = temp_dir.path().join("output.txt");
+        let content = "Test content";
+        
+        write_to_file(&output_path, content, false, false).unwrap();
+        
+        let read_content = fs::read_to_string(output_path).unwrap();
+        assert_eq!(read_content, content);
+    }
+
+    #[test]
+    fn test_write_to() {
+        let mut output = Vec::new();
+        write_to(&mut output, "Piped lyrics\n").unwrap();
+        assert_eq!(output, b"Piped lyrics\n");
+    }
+
+    #[test]
+    fn test_write_to_file_append() {
+        let temp_dir = tempdir().unwrap();
+        let output_path = temp_dir.path().join("output.txt");
+        
+        write_to_file(&output_path, "First run\n", true, false).unwrap();
+        assert_eq!(fs::read_to_string(&output_path).unwrap(), "First run\n");
+        
+        write_to_file(&output_path, "Second run\n", true, false).unwrap();
+        assert_eq!(fs::read_to_string(&output_path).unwrap(), "First run\n\nSecond run\n");
+        
+        // Without the flag the file is replaced as before
+        write_to_file(&output_path, "Fresh\n", false, false).unwrap();
+        assert_eq!(fs::read_to_string(&output_path).unwrap(), "Fresh\n");
+    }
+
+    #[test]
+    fn test_write_to_file_gzip() {
+        use flate2::read::MultiGzDecoder;
+        use std::io::Read;
+
+        let temp_dir = tempdir().unwrap();
+        let read_gzip = |path: &Path| {
+            let mut text = String::new();
+            MultiGzDecoder::new(File::open(path).unwrap()).read_to_string(&mut text).unwrap();
+            text
+        };
+
+        // Compressed because of the extension, and appending adds a second member
+        let gz_path = temp_dir.path().join("lyrics.txt.gz");
+        assert!(is_gzip_path(&gz_path));
+        write_to_file(&gz_path, "First run\n", true, false).unwrap();
+        write_to_file(&gz_path, "Second run\n", true, false).unwrap();
+        assert_eq!(read_gzip(&gz_path), "First run\n\nSecond run\n");
+
+        // Or because of the flag
+        let flagged = temp_dir.path().join("lyrics.txt");
+        write_to_file(&flagged, "Flagged\n", false, true).unwrap();
+        assert_eq!(read_gzip(&flagged), "Flagged\n");
+    }
+
+    #[test]
+    fn test_output_writer_matches_write_to_file() {
+        use flate2::read::MultiGzDecoder;
+        use std::io::Read;
+
+        let results = [
+            track("one.mp3", Some("Windows line\r\nUnix line")),
+            track("two.mp3", None),
+            track("three.mp3", Some("Ends in a carriage return\r")),
+        ];
+        // A suffix ending in \r splits a \r\n pair between one piece and the next
+        let options = TextOptions {
+            include_names: true,
+            add_separator: true,
+            track_suffix: "\r".to_string(),
+            toc: true,
+            ..Default::default()
+        };
+
+        let temp_dir = tempdir().unwrap();
+        for (ending, gzip) in [(LineEnding::Lf, false), (LineEnding::Crlf, false), (LineEnding::Crlf, true)] {
+            let buffered_path = temp_dir.path().join("buffered.txt");
+            let buffered = normalize_line_endings(&render_text(&results, &options), ending);
+            write_to_file(&buffered_path, &buffered, true, gzip).unwrap();
+            write_to_file(&buffered_path, &buffered, true, gzip).unwrap();
+
+            let streamed_path = temp_dir.path().join("streamed.txt");
+            for _ in 0..2 {
+                let mut output = OutputWriter::open(&streamed_path, true, gzip).unwrap();
+                let mut line_endings = LineEndingStream::new(ending);
+                for piece in render_text_pieces(&results, &options) {
+                    output.write_str(&line_endings.push(&piece)).unwrap();
+                }
+                output.write_str(&line_endings.finish()).unwrap();
+                assert_eq!(output.bytes_written(), buffered.len());
+                assert_eq!(output.finish().unwrap(), buffered.len());
+            }
+
+            let read = |path: &Path| {
+                let bytes = fs::read(path).unwrap();
+                if !gzip {
+                    return bytes;
+                }
+                let mut decoded = Vec::new();
+                MultiGzDecoder::new(&bytes[..]).read_to_end(&mut decoded).unwrap();
+                decoded
+            };
+            assert_eq!(read(&streamed_path), read(&buffered_path), "{:?}", ending);
+            fs::remove_file(buffered_path).unwrap();
+            fs::remove_file(streamed_path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_output_writer_encoding() {
+        let temp_dir = tempdir().unwrap();
+        let mp3_path = create_test_mp3(temp_dir.path(), "song.mp3", Some("さくら さくら\nやよいの空は 🌸"));
+        let results = extract_all_lyrics(&[mp3_path], &ExtractOptions::default(), 1).unwrap();
+        let rendered = render_text(&results, &TextOptions::default());
+
+        let output_path = temp_dir.path().join("lyrics.txt");
+        let mut output = OutputWriter::open(&output_path, false, false).unwrap().with_encoding(encoding_rs::SHIFT_JIS);
+        output.write_str(&rendered).unwrap();
+        assert_eq!(output.unmappable(), 1);
+        output.finish().unwrap();
+
+        // Reading it back as Shift_JIS gives the lyrics, with '?' for the emoji it has no way to write
+        let written = fs::read(&output_path).unwrap();
+        let (decoded, _, had_errors) = encoding_rs::SHIFT_JIS.decode(&written);
+        assert!(!had_errors);
+        assert_eq!(decoded, "さくら さくら\nやよいの空は ?\n");
+
+        // Limits count the encoded bytes, including the switch back to ASCII that ends each piece
+        let mut output = OutputWriter::open(&output_path, false, false).unwrap().with_encoding(encoding_rs::ISO_2022_JP);
+        assert!(!output.write_str_within("あ\n", Some(8)).unwrap());
+        assert!(output.write_str_within("あ\n", Some(9)).unwrap());
+        assert!(output.write_str_within("い\n", Some(18)).unwrap());
+        assert_eq!(output.finish().unwrap(), 18);
+        let written = fs::read(&output_path).unwrap();
+        assert_eq!(written.len(), 18);
+        assert_eq!(encoding_rs::ISO_2022_JP.decode(&written).0, "あ\nい\n");
+
+        // UTF-8 is written as is
+        let mut output = OutputWriter::open(&output_path, false, false).unwrap().with_encoding(UTF_8);
+        output.write_str(&rendered).unwrap();
+        assert_eq!((output.unmappable(), output.finish().unwrap()), (0, rendered.len()));
+        assert_eq!(fs::read_to_string(&output_path).unwrap(), rendered);
+    }
+
+    #[test]
+    fn test_normalize_line_endings() {
+        let mixed = "Windows line\r\nUnix line\nOld Mac line\rLast";
+        assert_eq!(normalize_line_endings(mixed, LineEnding::Lf), "Windows line\nUnix line\nOld Mac line\nLast");
+        assert_eq!(
+            normalize_line_endings(mixed, LineEnding::Crlf),
+            "Windows line\r\nUnix line\r\nOld Mac line\r\nLast"
+        );
+        
+        // Separators and headers added when rendering are normalized along with the lyrics
+        let temp_dir = tempdir().unwrap();
+        let with_crlf = create_test_mp3(temp_dir.path(), "song1.mp3", Some("One\r\nTwo"));
+        let with_lf = create_test_mp3(temp_dir.path(), "song2.mp3", Some("Three\nFour"));
+        let results = extract_all_lyrics(&[with_crlf, with_lf], &ExtractOptions::default(), 1).unwrap();
+        let rendered = render_text(&results, &TextOptions { add_separator: true, ..Default::default() });
+        assert_eq!(normalize_line_endings(&rendered, LineEnding::Crlf), "One\r\nTwo\r\n\r\n---\r\nThree\r\nFour\r\n");
+    }
+
+    #[test]
+    fn test_write_manifest() {
+        let temp_dir = tempdir().unwrap();
+        let with_lyrics = create_test_mp3(temp_dir.path(), "song1.mp3", Some("Lyrics for song 1"));
+        let without = create_test_mp3(temp_dir.path(), "song2.mp3", None);
+        let results = extract_all_lyrics(&[with_lyrics.clone(), without], &ExtractOptions::default(), 1).unwrap();
+        let manifest_path = temp_dir.path().join("manifest.tsv");
+        
+        write_manifest(&manifest_path, &results).unwrap();
+        
+        let manifest = fs::read_to_string(&manifest_path).unwrap();
+        let rows: Vec<Vec<&str>> = manifest.lines().map(|line| line.split('\t').collect()).collect();
+        assert_eq!(rows[0], ["path", "sha256", "lyrics_found", "lyrics_bytes"]);
+        assert_eq!(rows[1][0], with_lyrics.display().to_string());
+        assert_eq!(rows[1][1], format!("{:x}", Sha256::digest(fs::read(&with_lyrics).unwrap())));
+        assert_eq!(rows[1][2..], ["true", "17"]);
+        assert_eq!(rows[2][2..], ["false", "0"]);
+    }
+
+    #[test]
+    fn test_write_duplicates_report() {
+        let tagged = |path: &str, artist: &str, title: Option<&str>| TrackResult {
+            artist: Some(artist.to_string()),
+            title: title.map(str::to_string),
+            ..track(path, None)
+        };
+        let results = [
+            tagged("b/song.mp3", "The Band", Some("Song  Title")),
+            tagged("a.mp3", "Other", Some("Single")),
+            tagged("b/song (1).mp3", "the band", Some(" song title")),
+            tagged("untitled.mp3", "The Band", None),
+            tagged("untitled (1).mp3", "The Band", None),
+        ];
+        let temp_dir = tempdir().unwrap();
+        let report_path = temp_dir.path().join("duplicates.txt");
+
+        write_duplicates_report(&report_path, &results).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&report_path).unwrap(),
+            "The Band - Song  Title (2 files)\n  b/song.mp3\n  b/song (1).mp3\n"
+        );
+    }
+
+    #[test]
+    fn test_write_error_log() {
+        let temp_dir = tempdir().unwrap();
+        let log_path = temp_dir.path().join("errors.jsonl");
+
+        let failed = TrackResult { error: Some("Failed to read ID3 tag".to_string()), ..track("bad \"one\".mp3", None) };
+        let results = [track("good.mp3", None), failed];
+        write_error_log(&log_path, &results).unwrap();
+        assert_eq!(
+            fs::read_to_string(&log_path).unwrap(),
+            "{\"path\":\"bad \\\"one\\\".mp3\",\"error\":\"Failed to read ID3 tag\"}\n"
+        );
+
+        write_error_log(&log_path, &results[..1]).unwrap();
+        assert_eq!(fs::metadata(&log_path).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn test_write_tag_summary() {
+        let tagged = |artist: Option<&str>, album: Option<&str>| TrackResult {
+            artist: artist.map(str::to_string),
+            album: album.map(str::to_string),
+            ..track("song.mp3", None)
+        };
+        let results = [
+            tagged(Some("Band"), Some("First")),
+            tagged(Some("Another\tBand"), Some("Second")),
+            tagged(Some("Band"), None),
+            tagged(None, Some("First")),
+        ];
+        let temp_dir = tempdir().unwrap();
+        let summary_path = temp_dir.path().join("tags.tsv");
+
+        write_tag_summary(&summary_path, &results).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&summary_path).unwrap(),
+            "tag\tvalue\ttracks\nartist\tBand\t2\nartist\tAnother Band\t1\nalbum\tFirst\t2\nalbum\tSecond\t1\n"
+        );
+    }
+}
@@ -0,0 +1,123 @@
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use log::debug;
+use reqwest::Url;
+use tempfile::TempDir;
+use crate::archive::{name_in_dir, relabel_in_dir};
+use crate::error::{Error, Result};
+use crate::extract::TrackResult;
+use crate::find::FindOptions;
+
+/// How long to wait for a remote input when no timeout is given
+pub const DEFAULT_DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// An audio file downloaded from a URL into a temporary directory
+///
+/// The directory, and the file in it, are removed when this is dropped.
+#[derive(Debug)]
+pub struct DownloadedFile {
+    dir: TempDir,
+    file: PathBuf,
+}
+
+/// Whether `input` is an http(s) URL that should be downloaded rather than read from disk
+pub fn is_remote_url(input: &str) -> bool {
+    let scheme = input.split_once("://").map(|(scheme, _)| scheme);
+    scheme.is_some_and(|scheme| scheme.eq_ignore_ascii_case("http") || scheme.eq_ignore_ascii_case("https"))
+}
+
+/// Download the audio file at `url`, which must be named like one `options` matches
+///
+/// The file keeps the last segment of the URL's path as its name.
+pub fn download(url: &str, timeout: Duration, options: &FindOptions) -> Result<DownloadedFile> {
+    let download_error = |source| Error::Download { url: url.to_string(), source };
+    let parsed = Url::parse(url).map_err(|_| Error::UnsupportedFile(PathBuf::from(url)))?;
+    let name = parsed
+        .path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .filter(|name| options.matcher.is_match(name))
+        .ok_or_else(|| Error::UnsupportedFile(PathBuf::from(url)))?
+        .to_string();
+
+    let client = reqwest::blocking::Client::builder().timeout(timeout).build().map_err(download_error)?;
+    let mut response = client.get(parsed).send().and_then(|r| r.error_for_status()).map_err(download_error)?;
+    let dir = TempDir::new().map_err(|e| Error::io("Failed to create a temporary directory for", Path::new(url), e))?;
+    let file = dir.path().join(&name);
+    let mut out = File::create(&file).map_err(|e| Error::io("Failed to create", &file, e))?;
+    response.copy_to(&mut out).map_err(download_error)?;
+    debug!("Downloaded {} to {}", url, file.display());
+
+    Ok(DownloadedFile { dir, file })
+}
+
+impl DownloadedFile {
+    /// The downloaded file, in its temporary directory
+    pub fn path(&self) -> &Path {
+        &self.file
+    }
+
+    /// The name to report the downloaded file under, the last segment of its URL
+    pub fn entry_name(&self, path: &Path) -> Option<PathBuf> {
+        name_in_dir(&self.dir, path)
+    }
+
+    /// Report the result for the downloaded file under its name from the URL
+    pub fn relabel(&self, results: &mut [TrackResult]) {
+        relabel_in_dir(&self.dir, results);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extract::{extract_all_lyrics, ExtractOptions};
+    use crate::test_util::create_test_mp3;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::{fs, thread};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_is_remote_url() {
+        assert!(is_remote_url("https://example.com/song.mp3"));
+        assert!(is_remote_url("HTTP://example.com/song.mp3"));
+        assert!(!is_remote_url("ftp://example.com/song.mp3"));
+        assert!(!is_remote_url("music/song.mp3"));
+    }
+
+    #[test]
+    fn test_download() {
+        let temp_dir = tempdir().unwrap();
+        let mp3_path = create_test_mp3(temp_dir.path(), "song.mp3", Some("Remote lyrics"));
+        let body = fs::read(&mp3_path).unwrap();
+
+        // Serve the file once over plain HTTP
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/albums/song.mp3?download=1", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            write!(stream, "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len()).unwrap();
+            stream.write_all(&body).unwrap();
+        });
+
+        let downloaded = download(&url, DEFAULT_DOWNLOAD_TIMEOUT, &FindOptions::default()).unwrap();
+        server.join().unwrap();
+        let local = downloaded.path().to_path_buf();
+        let mut results = extract_all_lyrics(std::slice::from_ref(&local), &ExtractOptions::default(), 1).unwrap();
+        downloaded.relabel(&mut results);
+        assert_eq!(results[0].path, PathBuf::from("song.mp3"));
+        assert_eq!(results[0].lyrics.as_deref(), Some("Remote lyrics"));
+
+        drop(downloaded);
+        assert!(!local.exists());
+
+        let not_audio = download("http://127.0.0.1:1/cover.jpg", DEFAULT_DOWNLOAD_TIMEOUT, &FindOptions::default());
+        assert!(matches!(not_audio, Err(Error::UnsupportedFile(_))));
+    }
+}
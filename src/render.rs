@@ -0,0 +1,781 @@
+use std::path::PathBuf;
+use std::time::Duration;
+use clap::ValueEnum;
+use crate::error::Result;
+use crate::extract::{TextCase, TimedLine, TrackResult};
+use crate::find::GroupBy;
+use crate::output::relative_to_roots;
+use crate::template::Template;
+
+/// Header used for each track when `--include-names` is set
+pub const DEFAULT_HEADER_FORMAT: &str = "{artist} - {title}";
+
+/// Page width, in characters, that `--columns` lays lyrics out across when none is given
+pub const DEFAULT_PAGE_WIDTH: usize = 80;
+
+/// Spaces between columns of lyrics
+const COLUMN_GAP: usize = 4;
+
+/// Output formats supported by the extractor
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Plain lyric text
+    #[default]
+    Text,
+    /// LRC with `[mm:ss.xx]` timestamps taken from SYLT frames
+    Lrc,
+    /// SRT subtitles with one cue per SYLT entry
+    Srt,
+    /// Markdown with a heading per track
+    Markdown,
+    /// JSON array with one object per track
+    Json,
+    /// CSV with a row per track
+    Csv,
+    /// Each track as YAML front matter (file, artist, title, album) followed by its lyrics
+    YamlFrontmatter,
+}
+
+/// Settings for the plain text document
+#[derive(Debug, Clone)]
+pub struct TextOptions {
+    /// Put a header naming the track above its lyrics
+    pub include_names: bool,
+    /// Put `separator_text` on its own line between tracks
+    pub add_separator: bool,
+    pub separator_text: String,
+    /// Template for the header, see [`format_header`]
+    pub header_format: String,
+    /// Put the headers in this case too, to match lyrics given the same case
+    pub header_case: Option<TextCase>,
+    /// Show the path in `File:` headers relative to the deepest of these directories that holds the
+    /// file, rather than as found
+    pub path_roots: Vec<PathBuf>,
+    /// Start each track's lyrics with a `# source:` line naming the frame they came from
+    pub annotate: bool,
+    /// Text written just before each track's lyrics
+    pub track_prefix: String,
+    /// Text written just after each track's lyrics
+    pub track_suffix: String,
+    /// Text written for each track without lyrics, whether or not `include_names` is set
+    ///
+    /// This replaces the `[No lyrics found]` and `[Failed to extract lyrics]` markers.
+    pub placeholder: Option<String>,
+    /// Text written for each instrumental track in place of its lyrics or the placeholder, see
+    /// [`TrackResult::instrumental`]
+    pub instrumental_marker: Option<String>,
+    /// Lay out every track with this instead, ignoring the options above
+    pub template: Option<Template>,
+    /// Start the document with a numbered list of the tracks in it and the line each starts on
+    pub toc: bool,
+    /// Lay each track's lyrics out in this many side-by-side columns (1 leaves them as they are)
+    pub columns: usize,
+    /// Characters per line that the columns have to fit in
+    pub page_width: usize,
+    /// Put a heading above each group of tracks, which must already be in group order
+    pub group_by: Option<GroupBy>,
+}
+
+impl Default for TextOptions {
+    fn default() -> Self {
+        TextOptions {
+            include_names: false,
+            add_separator: false,
+            separator_text: "---".to_string(),
+            header_format: DEFAULT_HEADER_FORMAT.to_string(),
+            header_case: None,
+            path_roots: Vec::new(),
+            annotate: false,
+            track_prefix: String::new(),
+            track_suffix: String::new(),
+            placeholder: None,
+            instrumental_marker: None,
+            template: None,
+            toc: false,
+            columns: 1,
+            page_width: DEFAULT_PAGE_WIDTH,
+            group_by: None,
+        }
+    }
+}
+
+/// Concatenate extracted lyrics into a single text document
+pub fn render_text(results: &[TrackResult], options: &TextOptions) -> String {
+    render_text_pieces(results, options).collect()
+}
+
+/// Lay out the same document as [`render_text`] a piece at a time, so it can be written out as it
+/// goes: the table of contents if there is one, then each track with the separator before it
+pub fn render_text_pieces<'a>(
+    results: &'a [TrackResult],
+    options: &'a TextOptions,
+) -> impl Iterator<Item = String> + 'a {
+    let toc = options.toc.then(|| render_toc(results, options));
+    toc.into_iter().chain((0..results.len()).map(|index| {
+        let (separator, text) = render_track(results, index, options);
+        separator + &text
+    }))
+}
+
+/// The `index`th track's part of the text document, as the separator (and any group heading) that
+/// goes before it and its own text
+fn render_track(results: &[TrackResult], index: usize, options: &TextOptions) -> (String, String) {
+    let result = &results[index];
+    let heading = options
+        .group_by
+        .filter(|group_by| index == 0 || group_by.key(&results[index - 1]) != group_by.key(result))
+        .map(|group_by| {
+            let name = group_by.key(result).unwrap_or(GroupBy::UNKNOWN);
+            format!("{}=== {} ===\n\n", if index > 0 { "\n" } else { "" }, name)
+        })
+        .unwrap_or_default();
+    if let Some(template) = &options.template {
+        return (heading, template.render(index, result));
+    }
+    let separator = if index > 0 && options.add_separator {
+        format!("\n{}\n", options.separator_text)
+    } else {
+        String::new()
+    } + &heading;
+
+    let mut text = String::new();
+    if options.include_names {
+        let mut header = format_header(&options.header_format, result)
+            .unwrap_or_else(|| {
+                let path = relative_to_roots(&result.path, &options.path_roots).unwrap_or(&result.path);
+                format!("File: {}", path.display())
+            });
+        if let Some(case) = options.header_case {
+            header = case.apply(&header);
+        }
+        if let Some(language) = result.lyrics_frame.as_ref().and_then(|frame| frame.language.as_deref()) {
+            header.push_str(&format!(" [{}]", language));
+        }
+        text.push_str(&format!("{}\n\n", header));
+    }
+
+    match (&result.lyrics, &result.error) {
+        (_, None) if result.instrumental && options.instrumental_marker.is_some() => {
+            text.push_str(options.instrumental_marker.as_deref().unwrap_or_default());
+            text.push('\n');
+        }
+        (Some(lyrics), _) => {
+            text.push_str(&options.track_prefix);
+            if let Some(frame) = result.lyrics_frame.as_ref().filter(|_| options.annotate) {
+                match result.synced_lyrics.as_ref().filter(|_| frame.id == "SYLT") {
+                    Some(sylt) => text.push_str(&format!("# source: {}, {}\n", frame, sylt)),
+                    None => text.push_str(&format!("# source: {}\n", frame)),
+                }
+            }
+            if options.columns > 1 {
+                text.push_str(&layout_columns(lyrics, options.columns, options.page_width));
+            } else {
+                text.push_str(lyrics);
+            }
+            text.push('\n');
+            text.push_str(&options.track_suffix);
+        }
+        (None, _) if options.placeholder.is_some() => {
+            text.push_str(options.placeholder.as_deref().unwrap_or_default());
+            text.push('\n');
+        }
+        (None, None) => {
+            if options.include_names {
+                text.push_str("[No lyrics found]\n");
+            }
+        }
+        (None, Some(_)) => {
+            if options.include_names {
+                text.push_str("[Failed to extract lyrics]\n");
+            }
+        }
+    }
+    (separator, text)
+}
+
+/// Spread `lyrics` over `columns` columns of equal height that fit in `page_width` characters,
+/// reading down each column in turn
+///
+/// Lines too long for a column are wrapped at spaces, or broken mid-word when a word is too long.
+fn layout_columns(lyrics: &str, columns: usize, page_width: usize) -> String {
+    let column_width = (page_width.saturating_sub(COLUMN_GAP * (columns - 1)) / columns).max(1);
+    let lines: Vec<String> = lyrics.lines().flat_map(|line| wrap_line(line, column_width)).collect();
+    let rows = lines.len().div_ceil(columns);
+
+    let mut laid_out = Vec::with_capacity(rows);
+    for row in 0..rows {
+        let mut text = String::new();
+        for column in 0..columns {
+            let cell = lines.get(column * rows + row).map_or("", String::as_str);
+            if column > 0 {
+                text.push_str(&" ".repeat(COLUMN_GAP));
+            }
+            text.push_str(cell);
+            text.push_str(&" ".repeat(column_width - cell.chars().count()));
+        }
+        laid_out.push(text.trim_end().to_string());
+    }
+    laid_out.join("\n")
+}
+
+/// Break `line` into pieces of at most `width` characters, preferring to break at spaces
+fn wrap_line(line: &str, width: usize) -> Vec<String> {
+    let mut pieces = Vec::new();
+    let mut current = String::new();
+    for word in line.split_whitespace() {
+        let mut word: Vec<char> = word.chars().collect();
+        let length = current.chars().count();
+        if length > 0 && length + 1 + word.len() > width {
+            pieces.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push(' ');
+        }
+        while word.len() > width - current.chars().count() {
+            let rest = word.split_off(width - current.chars().count());
+            current.extend(word);
+            pieces.push(std::mem::take(&mut current));
+            word = rest;
+        }
+        current.extend(word);
+    }
+    // Blank lines are kept, to separate verses
+    if !current.is_empty() || pieces.is_empty() {
+        pieces.push(current);
+    }
+    pieces
+}
+
+/// The numbered list of tracks that wrote anything, with the line each one starts on
+fn render_toc(results: &[TrackResult], options: &TextOptions) -> String {
+    // Lay the tracks out once just to count lines, so the document never has to be held whole
+    let mut tracks = Vec::new();
+    let mut lines = 0;
+    for (index, result) in results.iter().enumerate() {
+        let (separator, text) = render_track(results, index, options);
+        lines += separator.matches('\n').count();
+        if !text.is_empty() {
+            tracks.push((lines, result));
+        }
+        lines += text.matches('\n').count();
+    }
+
+    // The contents take one line per track plus a blank line, and push the body down by that much
+    let mut toc = String::new();
+    for (number, (start, result)) in tracks.iter().enumerate() {
+        let name = format_header(&options.header_format, result).unwrap_or_else(|| file_name(result));
+        toc.push_str(&format!("{}. {} (line {})\n", number + 1, name, tracks.len() + 2 + start));
+    }
+    toc.push('\n');
+    toc
+}
+
+/// Fill in a header template, or return `None` if the track lacks metadata it refers to
+pub fn format_header(template: &str, result: &TrackResult) -> Option<String> {
+    fill_header(template, result, str::to_string)
+}
+
+/// Fill in a header template, passing each substituted value through `escape`
+fn fill_header(template: &str, result: &TrackResult, escape: fn(&str) -> String) -> Option<String> {
+    let mut header = template.to_string();
+    for (placeholder, value) in [("{artist}", &result.artist), ("{title}", &result.title)] {
+        if header.contains(placeholder) {
+            header = header.replace(placeholder, &escape(value.as_deref()?));
+        }
+    }
+    Some(header.replace("{filename}", &escape(&file_name(result))))
+}
+
+pub(crate) fn file_name(result: &TrackResult) -> String {
+    result.path.file_name().unwrap_or(result.path.as_os_str()).to_string_lossy().into_owned()
+}
+
+/// Render extracted lyrics as Markdown, with a `##` heading per track and rules between tracks
+pub fn render_markdown(results: &[TrackResult], header_format: &str) -> String {
+    let mut markdown = String::new();
+
+    for (index, result) in results.iter().enumerate() {
+        if index > 0 {
+            markdown.push_str("\n---\n\n");
+        }
+
+        let heading = fill_header(header_format, result, escape_markdown)
+            .unwrap_or_else(|| escape_markdown(&file_name(result)));
+        markdown.push_str(&format!("## {}\n\n", heading));
+
+        match (&result.lyrics, &result.error) {
+            (Some(lyrics), _) => markdown.push_str(lyrics.trim_end()),
+            (None, None) => markdown.push_str("*No lyrics found*"),
+            (None, Some(_)) => markdown.push_str("*Failed to extract lyrics*"),
+        }
+        markdown.push('\n');
+    }
+
+    markdown
+}
+
+/// Render each track as a `---` fenced block of YAML front matter followed by its lyrics
+///
+/// Only the tags a track has are included. A track without lyrics still gets its front matter, with
+/// an empty body.
+pub fn render_yaml_frontmatter(results: &[TrackResult]) -> String {
+    let mut output = String::new();
+
+    for result in results {
+        output.push_str("---\n");
+        output.push_str(&format!("file: {}\n", yaml_string(&file_name(result))));
+        for (key, value) in [("artist", &result.artist), ("title", &result.title), ("album", &result.album)] {
+            if let Some(value) = value {
+                output.push_str(&format!("{}: {}\n", key, yaml_string(value)));
+            }
+        }
+        output.push_str("---\n");
+        output.push_str(result.lyrics.as_deref().unwrap_or_default().trim_end());
+        output.push('\n');
+    }
+
+    output
+}
+
+/// Quote a value as a YAML double-quoted scalar, whose escapes are a superset of JSON's
+fn yaml_string(value: &str) -> String {
+    serde_json::Value::from(value).to_string()
+}
+
+/// Backslash-escape characters that Markdown would otherwise treat as formatting
+fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "\\`*_{}[]<>()#+-.!|~".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Serialize extraction results as a JSON array
+pub fn render_json(results: &[TrackResult], compact: bool) -> Result<String> {
+    let mut json = if compact {
+        serde_json::to_string(results)?
+    } else {
+        serde_json::to_string_pretty(results)?
+    };
+    json.push('\n');
+    Ok(json)
+}
+
+/// Render extracted lyrics as CSV with a header row and one row per track
+///
+/// With `include_names`, artist, title and album columns are added between the file and the lyrics.
+/// Tracks without lyrics get an empty lyrics cell so they don't drop out of joins.
+pub fn render_csv(results: &[TrackResult], include_names: bool) -> Result<String> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    if include_names {
+        writer.write_record(["file", "artist", "title", "album", "lyrics"])?;
+    } else {
+        writer.write_record(["file", "lyrics"])?;
+    }
+
+    for result in results {
+        let file = result.path.display().to_string();
+        let lyrics = result.lyrics.as_deref().unwrap_or_default();
+        if include_names {
+            let field = |value: &Option<String>| value.clone().unwrap_or_default();
+            writer.write_record([
+                file,
+                field(&result.artist),
+                field(&result.title),
+                field(&result.album),
+                lyrics.to_string(),
+            ])?;
+        } else {
+            writer.write_record([file.as_str(), lyrics])?;
+        }
+    }
+
+    let bytes = writer.into_inner().map_err(|e| csv::Error::from(e.into_error()))?;
+    // Every field came from a `String`, so the output is valid UTF-8
+    Ok(String::from_utf8(bytes).expect("CSV output is UTF-8"))
+}
+
+/// Render timed lyrics as LRC text, one `[mm:ss.xx]` line per entry
+///
+/// An empty line `tail` after the last entry clears it from the screen; a zero `tail` leaves it out.
+pub(crate) fn format_lrc(lines: &[TimedLine], tail: Duration) -> String {
+    let mut lrc: Vec<String> = lines
+        .iter()
+        .map(|line| format!("{}{}", format_lrc_timestamp(line.start_ms), line.text.trim_end_matches(['\r', '\n'])))
+        .collect();
+    if let Some(last) = lines.last().filter(|_| !tail.is_zero()) {
+        let tail_ms = u32::try_from(tail.as_millis()).unwrap_or(u32::MAX);
+        lrc.push(format_lrc_timestamp(last.start_ms.saturating_add(tail_ms)));
+    }
+    lrc.join("\n")
+}
+
+/// Convert a millisecond offset into an LRC `[mm:ss.xx]` timestamp
+fn format_lrc_timestamp(ms: u32) -> String {
+    let minutes = ms / 60_000;
+    let seconds = (ms % 60_000) / 1000;
+    let hundredths = (ms % 1000) / 10;
+    format!("[{:02}:{:02}.{:02}]", minutes, seconds, hundredths)
+}
+
+/// Render timed lyrics as numbered SRT cues
+///
+/// Each cue ends where the next one starts; the last cue stays up for `tail`.
+pub(crate) fn format_srt(lines: &[TimedLine], tail: Duration) -> String {
+    lines
+        .iter()
+        .enumerate()
+        .map(|(index, line)| {
+            let start = u64::from(line.start_ms);
+            let end = match lines.get(index + 1) {
+                Some(next) => u64::from(next.start_ms),
+                None => start.saturating_add(tail.as_millis() as u64),
+            };
+            format!(
+                "{}\n{} --> {}\n{}",
+                index + 1,
+                format_srt_timestamp(start),
+                format_srt_timestamp(end),
+                line.text.trim_matches(['\r', '\n']),
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// Convert a millisecond offset into an SRT `HH:MM:SS,mmm` timestamp
+fn format_srt_timestamp(ms: u64) -> String {
+    let hours = ms / 3_600_000;
+    let minutes = (ms % 3_600_000) / 60_000;
+    let seconds = (ms % 60_000) / 1000;
+    format!("{:02}:{:02}:{:02},{:03}", hours, minutes, seconds, ms % 1000)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extract::{extract_all_lyrics, ExtractOptions, LyricsFrame};
+    use crate::test_util::{create_corrupt_mp3, create_test_mp3, track};
+    use std::path::PathBuf;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_extract_all_lyrics() {
+        let temp_dir = tempdir().unwrap();
+        let mp3_path1 = create_test_mp3(temp_dir.path(), "test1.mp3", Some("Lyrics for song 1"));
+        let mp3_path2 = create_test_mp3(temp_dir.path(), "test2.mp3", Some("Lyrics for song 2"));
+        
+        let mp3_files = vec![mp3_path1.clone(), mp3_path2.clone()];
+        let results = extract_all_lyrics(&mp3_files, &ExtractOptions::default(), 1).unwrap();
+        
+        // Test without names or separators
+        let lyrics1 = render_text(&results, &TextOptions::default());
+        assert!(lyrics1.contains("Lyrics for song 1"));
+        assert!(lyrics1.contains("Lyrics for song 2"));
+        assert!(!lyrics1.contains("File:"));
+        
+        // Test with names
+        let lyrics2 = render_text(&results, &TextOptions { include_names: true, ..Default::default() });
+        assert!(lyrics2.contains("File:"));
+        assert!(lyrics2.contains(mp3_path1.to_str().unwrap()));
+        
+        // Test with separator
+        let lyrics3 = render_text(&results, &TextOptions { add_separator: true, ..Default::default() });
+        assert!(lyrics3.contains("---"));
+    }
+
+    #[test]
+    fn test_header_falls_back_to_filename() {
+        let result = TrackResult {
+            path: PathBuf::from("music/song.mp3"),
+            title: Some("Song".to_string()),
+            ..Default::default()
+        };
+        
+        assert_eq!(format_header(DEFAULT_HEADER_FORMAT, &result), None);
+        assert_eq!(format_header("{title}", &result).as_deref(), Some("Song"));
+        
+        let output = render_text(&[result], &TextOptions { include_names: true, ..Default::default() });
+        assert!(output.starts_with("File: music/song.mp3\n\n"));
+    }
+
+    #[test]
+    fn test_render_text_annotate() {
+        let result = TrackResult {
+            path: PathBuf::from("song.mp3"),
+            lyrics: Some("Line one".to_string()),
+            lyrics_frame: Some(LyricsFrame { id: "USLT".to_string(), language: Some("eng".to_string()) }),
+            ..Default::default()
+        };
+        let results = [result];
+        
+        assert_eq!(render_text(&results, &TextOptions::default()), "Line one\n");
+        let annotated = render_text(&results, &TextOptions { annotate: true, ..Default::default() });
+        assert_eq!(annotated, "# source: USLT (eng)\nLine one\n");
+    }
+
+    #[test]
+    fn test_render_text_toc() {
+        let titled = |name: &str, title: Option<&str>, lyrics: Option<&str>| TrackResult {
+            title: title.map(str::to_string),
+            artist: Some("Band".to_string()),
+            ..track(name, lyrics)
+        };
+        let results = [
+            titled("one.mp3", Some("One"), Some("First\nverse")),
+            titled("two.mp3", Some("Two"), None),
+            titled("three.mp3", None, Some("Third")),
+        ];
+        let options = TextOptions { add_separator: true, toc: true, ..Default::default() };
+        let output = render_text(&results, &options);
+        assert_eq!(
+            output,
+            "1. Band - One (line 4)\n2. three.mp3 (line 10)\n\nFirst\nverse\n\n---\n\n---\nThird\n"
+        );
+        assert_eq!(output.lines().nth(9), Some("Third"));
+    }
+
+    #[test]
+    fn test_render_text_track_prefix_and_suffix() {
+        let results = [track("one.mp3", Some("First")), track("two.mp3", None), track("three.mp3", Some("Third"))];
+        let options = TextOptions {
+            add_separator: true,
+            track_prefix: ">> ".to_string(),
+            track_suffix: "\x0c\n".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(render_text(&results, &options), ">> First\n\x0c\n\n---\n\n---\n>> Third\n\x0c\n");
+    }
+
+    #[test]
+    fn test_render_text_placeholder() {
+        let failed = TrackResult { error: Some("oops".to_string()), ..track("bad.mp3", None) };
+        let results = [track("one.mp3", Some("First")), track("two.mp3", None), failed];
+        let placeholder = Some("(none)".to_string());
+
+        assert_eq!(render_text(&results, &TextOptions::default()), "First\n");
+        let plain = TextOptions { placeholder: placeholder.clone(), ..Default::default() };
+        assert_eq!(render_text(&results, &plain), "First\n(none)\n(none)\n");
+
+        let named = TextOptions { include_names: true, header_format: "{filename}".to_string(), ..Default::default() };
+        assert_eq!(
+            render_text(&results, &named),
+            "one.mp3\n\nFirst\ntwo.mp3\n\n[No lyrics found]\nbad.mp3\n\n[Failed to extract lyrics]\n"
+        );
+        let named_with_placeholder = TextOptions { placeholder, ..named };
+        assert_eq!(
+            render_text(&results, &named_with_placeholder),
+            "one.mp3\n\nFirst\ntwo.mp3\n\n(none)\nbad.mp3\n\n(none)\n"
+        );
+
+        // Instrumental tracks get their own marker, instead of the placeholder if there is one
+        let results = [
+            TrackResult { instrumental: true, ..track("inst.mp3", None) },
+            TrackResult { instrumental: true, ..track("said.mp3", Some("[instrumental]")) },
+            track("two.mp3", None),
+        ];
+        let marked = TextOptions {
+            instrumental_marker: Some("[Instrumental]".to_string()),
+            placeholder: Some("(none)".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(render_text(&results, &marked), "[Instrumental]\n[Instrumental]\n(none)\n");
+        assert_eq!(render_text(&results, &TextOptions { placeholder: None, ..marked }), "[Instrumental]\n[Instrumental]\n");
+        assert_eq!(render_text(&results, &TextOptions::default()), "[instrumental]\n");
+    }
+
+    #[test]
+    fn test_render_text_language() {
+        let framed = |name: &str, frame: LyricsFrame| TrackResult { lyrics_frame: Some(frame), ..track(name, Some("Words")) };
+        let results = [framed("one.mp3", LyricsFrame::new("USLT", Some("deu"))), framed("two.flac", LyricsFrame::new("LYRICS", None))];
+        let named = TextOptions { include_names: true, header_format: "{filename}".to_string(), ..Default::default() };
+        assert_eq!(render_text(&results, &named), "one.mp3 [deu]\n\nWords\ntwo.flac\n\nWords\n");
+        assert_eq!(render_text(&results, &TextOptions::default()), "Words\nWords\n");
+    }
+
+    #[test]
+    fn test_render_text_header_case() {
+        let results = [TrackResult {
+            lyrics: Some("LOUD".to_string()),
+            artist: Some("Émilie".to_string()),
+            title: Some("Straße".to_string()),
+            ..Default::default()
+        }];
+        let options = TextOptions { include_names: true, header_case: Some(TextCase::Upper), ..Default::default() };
+        assert_eq!(render_text(&results, &options), "ÉMILIE - STRASSE\n\nLOUD\n");
+    }
+
+    #[test]
+    fn test_render_text_relative_paths() {
+        let results = [
+            track("/music/rock/a.mp3", Some("Words")),
+            track("/music/rock/live/b.mp3", Some("Words")),
+            track("/other/c.mp3", Some("Words")),
+        ];
+        let options = TextOptions {
+            include_names: true,
+            path_roots: vec![PathBuf::from("/music"), PathBuf::from("/music/rock"), PathBuf::from("/other")],
+            ..Default::default()
+        };
+        let output = render_text(&results, &options);
+        assert_eq!(output, "File: a.mp3\n\nWords\nFile: live/b.mp3\n\nWords\nFile: c.mp3\n\nWords\n");
+        assert!(!output.contains("/music") && !output.contains("/other"));
+        assert!(render_text(&results, &TextOptions { include_names: true, ..Default::default() }).contains("File: /music/rock/a.mp3"));
+    }
+
+    #[test]
+    fn test_layout_columns() {
+        let lyrics = "One\nTwo two\n\nThree\nFour";
+        assert_eq!(layout_columns(lyrics, 2, 20), "One         Three\nTwo two     Four\n");
+        // Long lines wrap at spaces, and words longer than a column are broken
+        assert_eq!(
+            layout_columns("a long line here\nsupercalifragilistic", 2, 24),
+            "a long        supercalif\nline here     ragilistic"
+        );
+        assert_eq!(wrap_line("a long line here", 10), ["a long", "line here"]);
+        assert_eq!(wrap_line("abcdefghijkl mn", 5), ["abcde", "fghij", "kl mn"]);
+        assert_eq!(wrap_line("", 5), [""]);
+
+        let results = [TrackResult { lyrics: Some("A\nB\nC".to_string()), ..Default::default() }];
+        let options = TextOptions { columns: 3, page_width: 14, ..Default::default() };
+        assert_eq!(render_text(&results, &options), "A     B     C\n");
+    }
+
+    #[test]
+    fn test_render_text_grouped() {
+        let on_album = |album: Option<&str>, lyrics: &str| TrackResult {
+            album: album.map(str::to_string),
+            ..track("song.mp3", Some(lyrics))
+        };
+        let results = [on_album(Some("First"), "One"), on_album(Some("First"), "Two"), on_album(None, "Three")];
+        let options = TextOptions { group_by: Some(GroupBy::Album), ..Default::default() };
+
+        assert_eq!(
+            render_text(&results, &options),
+            "=== First ===\n\nOne\nTwo\n\n=== Unknown ===\n\nThree\n"
+        );
+        let separated = TextOptions { add_separator: true, ..options };
+        assert_eq!(
+            render_text(&results, &separated),
+            "=== First ===\n\nOne\n\n---\nTwo\n\n---\n\n=== Unknown ===\n\nThree\n"
+        );
+    }
+
+    #[test]
+    fn test_render_markdown() {
+        let titled = |title: Option<&str>, lyrics: Option<&str>| TrackResult {
+            title: title.map(str::to_string),
+            artist: Some("The *Stars*".to_string()),
+            ..track("music/my_song.mp3", lyrics)
+        };
+        let results = [titled(Some("Song [Live]"), Some("Line one\nLine two\n")), titled(None, None)];
+        
+        assert_eq!(
+            render_markdown(&results, DEFAULT_HEADER_FORMAT),
+            "## The \\*Stars\\* - Song \\[Live\\]\n\nLine one\nLine two\n\n---\n\n## my\\_song\\.mp3\n\n*No lyrics found*\n"
+        );
+        assert!(render_markdown(&results, "{title}").starts_with("## Song \\[Live\\]\n"));
+    }
+
+    #[test]
+    fn test_render_yaml_frontmatter() {
+        let results = [
+            TrackResult {
+                path: PathBuf::from("music/song.mp3"),
+                lyrics: Some("Line one\nLine two\n\n".to_string()),
+                title: Some("Song: \"Live\"".to_string()),
+                artist: Some("Band # 1".to_string()),
+                ..Default::default()
+            },
+            TrackResult { path: PathBuf::from("music/empty.mp3"), ..Default::default() },
+        ];
+
+        assert_eq!(
+            render_yaml_frontmatter(&results),
+            "---\nfile: \"song.mp3\"\nartist: \"Band # 1\"\ntitle: \"Song: \\\"Live\\\"\"\n---\nLine one\nLine two\n\
+             ---\nfile: \"empty.mp3\"\n---\n\n"
+        );
+    }
+
+    #[test]
+    fn test_render_csv() {
+        let results = [
+            TrackResult {
+                path: PathBuf::from("a.mp3"),
+                lyrics: Some("Line one, with \"quotes\"\nLine two".to_string()),
+                title: Some("Song".to_string()),
+                artist: Some("Band".to_string()),
+                ..Default::default()
+            },
+            TrackResult { path: PathBuf::from("b.mp3"), ..Default::default() },
+        ];
+        
+        assert_eq!(
+            render_csv(&results, false).unwrap(),
+            "file,lyrics\na.mp3,\"Line one, with \"\"quotes\"\"\nLine two\"\nb.mp3,\n"
+        );
+        let with_names = render_csv(&results, true).unwrap();
+        assert!(with_names.starts_with("file,artist,title,album,lyrics\na.mp3,Band,Song,,\"Line one"));
+        assert!(with_names.ends_with("\nb.mp3,,,,\n"));
+    }
+
+    #[test]
+    fn test_render_json() {
+        let temp_dir = tempdir().unwrap();
+        let with_lyrics = create_test_mp3(temp_dir.path(), "test1.mp3", Some("Lyrics for song 1"));
+        let broken = create_corrupt_mp3(temp_dir.path(), "broken.mp3");
+        
+        let results = extract_all_lyrics(&[with_lyrics.clone(), broken.clone()], &ExtractOptions { format: OutputFormat::Json, ..Default::default() }, 1).unwrap();
+        assert_eq!(results[0].lyrics.as_deref(), Some("Lyrics for song 1"));
+        assert!(results[0].error.is_none());
+        assert!(results[1].lyrics.is_none());
+        assert!(results[1].error.is_some());
+        
+        let json: serde_json::Value = serde_json::from_str(&render_json(&results, false).unwrap()).unwrap();
+        let tracks = json.as_array().unwrap();
+        assert_eq!(tracks.len(), 2);
+        assert_eq!(tracks[0]["path"], with_lyrics.to_str().unwrap());
+        assert_eq!(tracks[0]["lyrics"], "Lyrics for song 1");
+        assert!(tracks[0].get("error").is_none());
+        assert!(tracks[1]["lyrics"].is_null());
+        assert!(tracks[1]["error"].is_string());
+        
+        let compact = render_json(&results, true).unwrap();
+        assert_eq!(compact.lines().count(), 1);
+    }
+
+    #[test]
+    fn test_format_lrc_timestamp() {
+        assert_eq!(format_lrc_timestamp(0), "[00:00.00]");
+        assert_eq!(format_lrc_timestamp(12_345), "[00:12.34]");
+        assert_eq!(format_lrc_timestamp(61_000), "[01:01.00]");
+    }
+
+    #[test]
+    fn test_format_srt() {
+        let lines = vec![
+            TimedLine { start_ms: 1_500, text: "First line".to_string() },
+            TimedLine { start_ms: 3_725_250, text: "\nSecond line".to_string() },
+        ];
+
+        let srt = format_srt(&lines, Duration::from_secs(3));
+        assert_eq!(
+            srt,
+            "1\n00:00:01,500 --> 01:02:05,250\nFirst line\n\n2\n01:02:05,250 --> 01:02:08,250\nSecond line"
+        );
+        assert_eq!(format_srt(&[], Duration::from_secs(3)), "");
+    }
+
+    #[test]
+    fn test_format_lrc() {
+        let lines = vec![TimedLine { start_ms: 59_000, text: "Only line\r\n".to_string() }];
+
+        assert_eq!(format_lrc(&lines, Duration::from_millis(1_500)), "[00:59.00]Only line\n[01:00.50]");
+        assert_eq!(format_lrc(&lines, Duration::ZERO), "[00:59.00]Only line");
+        assert_eq!(format_lrc(&[], Duration::from_secs(3)), "");
+    }
+}
@@ -0,0 +1,113 @@
+use std::borrow::Cow;
+use regex::{Regex, RegexBuilder};
+use crate::error::{Error, Result};
+
+/// A sed-style substitution such as `s/pattern/replacement/g`, applied to each track's lyrics
+///
+/// Any character can stand in for the `/` delimiter, and `\/` is a literal one. The flags are `g`
+/// to replace every match rather than the first and `i` to ignore case. The replacement can refer
+/// to groups as `\1` or `$1`.
+#[derive(Debug, Clone)]
+pub struct Substitution {
+    pattern: Regex,
+    replacement: String,
+    global: bool,
+}
+
+impl Substitution {
+    /// Parse and compile a substitution, rejecting bad syntax and invalid patterns up front
+    pub fn parse(expression: &str) -> Result<Self> {
+        let invalid = |reason: String| Error::InvalidSubstitution { expression: expression.to_string(), reason };
+        let mut chars = expression.chars();
+        if chars.next() != Some('s') {
+            return Err(invalid("expected s/pattern/replacement/".to_string()));
+        }
+        let delimiter = chars.next().filter(|c| !c.is_alphanumeric() && *c != '\\' && !c.is_whitespace());
+        let delimiter = delimiter.ok_or_else(|| invalid("expected a delimiter such as '/' after 's'".to_string()))?;
+
+        // Split into the pattern, the replacement and the flags, unescaping the delimiter
+        let mut parts = vec![String::new()];
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some(next) if next == delimiter => parts.last_mut().unwrap().push(next),
+                    Some(next) => parts.last_mut().unwrap().extend(['\\', next]),
+                    None => parts.last_mut().unwrap().push('\\'),
+                },
+                _ if c == delimiter && parts.len() < 3 => parts.push(String::new()),
+                _ => parts.last_mut().unwrap().push(c),
+            }
+        }
+        let [pattern, replacement, flags] = <[String; 3]>::try_from(parts)
+            .map_err(|_| invalid(format!("expected s{0}pattern{0}replacement{0}", delimiter)))?;
+
+        let mut builder = RegexBuilder::new(&pattern);
+        let mut global = false;
+        for flag in flags.chars() {
+            match flag {
+                'g' => global = true,
+                'i' => {
+                    builder.case_insensitive(true);
+                }
+                _ => return Err(invalid(format!("unknown flag '{}' (use g or i)", flag))),
+            }
+        }
+        let pattern = builder.build().map_err(|e| invalid(e.to_string()))?;
+        Ok(Substitution { pattern, replacement: sed_replacement(&replacement), global })
+    }
+
+    /// Apply the substitution to `text`
+    pub fn apply<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        if self.global {
+            self.pattern.replace_all(text, self.replacement.as_str())
+        } else {
+            self.pattern.replace(text, self.replacement.as_str())
+        }
+    }
+}
+
+/// Turn sed's `\1` group references into the `${1}` form the regex crate expects
+fn sed_replacement(replacement: &str) -> String {
+    let mut converted = String::with_capacity(replacement.len());
+    let mut chars = replacement.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('\\', Some(digit)) if digit.is_ascii_digit() => {
+                converted.push_str(&format!("${{{}}}", digit));
+                chars.next();
+            }
+            ('\\', Some('\\')) => {
+                converted.push('\\');
+                chars.next();
+            }
+            _ => converted.push(c),
+        }
+    }
+    converted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_substitution() {
+        let apply_all = |expressions: &[&str], text: &str| {
+            expressions
+                .iter()
+                .map(|expression| Substitution::parse(expression).unwrap())
+                .fold(text.to_string(), |text, substitution| substitution.apply(&text).into_owned())
+        };
+
+        assert_eq!(apply_all(&["s/a/o/"], "banana"), "bonana");
+        assert_eq!(apply_all(&["s/a/o/g"], "banana"), "bonono");
+        let chained = ["s/\\r//g", "s/LYRICS BY .*//i", "s|(\\w+) (\\w+)|\\2 $1|"];
+        assert_eq!(apply_all(&chained, "one two\r\nlyrics by x"), "two one\n");
+        assert_eq!(apply_all(&["s/a\\/b/c/"], "a/b"), "c");
+        assert_eq!(apply_all(&["s/(o)/[\\1]/g", "s/\\[o\\]/0/"], "foo"), "f0[o]");
+
+        for invalid in ["a/b/c/", "s/b/c", "s/(/x/", "s/a/b/q", "s/a/b/g/"] {
+            assert!(matches!(Substitution::parse(invalid), Err(Error::InvalidSubstitution { .. })), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,706 @@
+use std::fs::File;
+use std::io::{self, Cursor, Read};
+use std::path::Path;
+use id3::{Tag, TagLike};
+use id3::frame::{Content, TimestampFormat};
+use lofty::config::ParseOptions;
+use lofty::file::{AudioFile, TaggedFileExt};
+use lofty::ogg::VorbisFile;
+use lofty::probe::Probe;
+use lofty::tag::{Accessor, ItemKey, ItemValue};
+use log::{debug, warn};
+use memmap2::Mmap;
+use crate::error::{Error, Result};
+use crate::extract::{
+    ExtractOptions, ExtractedTrack, FrameInfo, FrameSelector, LyricsCandidate, LyricsFrame, LyricsSourceKind,
+    SyncedLyricsInfo, TimedLine,
+};
+
+/// A container format that lyrics can be read from
+pub(crate) trait LyricsSource {
+    /// Read the lyrics and metadata stored in the file at `file_path`
+    fn read_track(&self, file_path: &Path, options: &ExtractOptions) -> Result<ExtractedTrack>;
+
+    /// Read the tag values used to sort and filter files, without looking at the lyrics
+    ///
+    /// `max_tag_bytes` is the largest ID3 tag to read, as for [`ExtractOptions::max_tag_bytes`].
+    fn read_metadata(&self, file_path: &Path, max_tag_bytes: u64) -> Result<TrackMetadata>;
+
+    /// List every frame or field in the file's tags, for diagnosing where lyrics are stored
+    fn read_frames(&self, file_path: &Path, max_tag_bytes: u64) -> Result<Vec<FrameInfo>>;
+}
+
+/// Descriptive tag values of a file
+#[derive(Debug, Default, Clone, PartialEq)]
+pub(crate) struct TrackMetadata {
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub title: Option<String>,
+    pub genre: Option<String>,
+    pub track_number: Option<u32>,
+}
+
+/// Reads lyrics from ID3v2 tags in MP3 files, or in the `id3 ` chunk of WAV and AIFF files
+pub(crate) struct Id3Source;
+
+/// Reads lyrics from Vorbis comments in FLAC files
+pub(crate) struct FlacSource;
+
+/// Reads lyrics through lofty, for container formats the other sources don't handle
+pub(crate) struct LoftySource;
+
+/// Extensions read through lofty: the MP4 family, whose lyrics are in `©lyr` atoms, and OGG, whose
+/// lyrics are in a `LYRICS` Vorbis comment
+const LOFTY_EXTENSIONS: &[&str] = &["m4a", "m4b", "mp4", "ogg", "oga"];
+
+/// Pick the lyrics source for a file based on its extension
+///
+/// Anything that isn't FLAC, MP4 or OGG is read as ID3, which can be attached to most audio containers.
+pub(crate) fn source_for_path(file_path: &Path) -> &'static dyn LyricsSource {
+    match file_path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) if ext.eq_ignore_ascii_case("flac") => &FlacSource,
+        Some(ext) if LOFTY_EXTENSIONS.iter().any(|known| ext.eq_ignore_ascii_case(known)) => &LoftySource,
+        _ => &Id3Source,
+    }
+}
+
+/// Treat blank metadata values the same as missing ones
+fn non_empty(value: Option<&str>) -> Option<String> {
+    value.map(str::trim).filter(|v| !v.is_empty()).map(str::to_string)
+}
+
+/// Read a file's ID3 tag, treating a file without one as having no metadata rather than an error
+///
+/// A tag whose header claims more than `max_tag_bytes` is an error rather than read, to guard
+/// against truncated or corrupt files. With `mmap`, the tag is parsed straight from a memory map of
+/// the file when it can be mapped.
+fn read_id3_tag(file_path: &Path, mmap: bool, max_tag_bytes: u64) -> Result<Option<Tag>> {
+    if let Some(size) = peek_id3_tag_size(file_path)? {
+        if size > max_tag_bytes {
+            return Err(Error::TagTooLarge { path: file_path.to_path_buf(), size, limit: max_tag_bytes });
+        }
+    }
+    let read = match mmap.then(|| map_file(file_path)).flatten() {
+        Some(map) => Tag::read_from2(Cursor::new(&map[..])),
+        None => Tag::read_from_path(file_path),
+    };
+    let tag = id3::no_tag_ok(read)
+        .map_err(|source| Error::Id3 { path: file_path.to_path_buf(), source })?;
+    if tag.is_none() {
+        debug!("No ID3 tag in {}", file_path.display());
+    }
+    Ok(tag)
+}
+
+/// Map a file into memory, or log why it can't be and return `None`
+fn map_file(file_path: &Path) -> Option<Mmap> {
+    // SAFETY: the map is only read while parsing the tag. Another process truncating the file in
+    // the meantime is undefined behaviour, which is why mapping is opt-in.
+    let map = File::open(file_path).and_then(|file| unsafe { Mmap::map(&file) });
+    match map {
+        Ok(map) => Some(map),
+        Err(e) => {
+            debug!("Failed to map {} into memory, reading it instead: {}", file_path.display(), e);
+            None
+        }
+    }
+}
+
+/// Read the total tag size declared by a file's ID3v2 header, without parsing the tag
+fn peek_id3_tag_size(file_path: &Path) -> Result<Option<u64>> {
+    let mut header = [0; 10];
+    let read = File::open(file_path).and_then(|mut file| {
+        match file.read_exact(&mut header) {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+            other => other.map(|_| true),
+        }
+    });
+    let complete = read.map_err(|e| Error::io("Failed to read", file_path, e))?;
+    if !complete || &header[..3] != b"ID3" {
+        return Ok(None);
+    }
+    
+    // The size is a 28-bit "synchsafe" integer and excludes the 10 byte header
+    let size = header[6..10].iter().fold(0u64, |size, byte| (size << 7) | u64::from(byte & 0x7F));
+    Ok(Some(size + 10))
+}
+
+/// Whether a `.mp3` file starts like one, with an ID3v2 header or an MPEG frame sync
+///
+/// Catches things like a renamed JPEG or a saved HTML error page, which would otherwise fail to
+/// parse with a confusing error.
+fn looks_like_mp3(file_path: &Path) -> Result<bool> {
+    let mut start = Vec::with_capacity(3);
+    File::open(file_path)
+        .and_then(|file| file.take(3).read_to_end(&mut start))
+        .map_err(|e| Error::io("Failed to read", file_path, e))?;
+    Ok(match start[..] {
+        [b'I', b'D', b'3'] => true,
+        [0xFF, second, ..] => second & 0xE0 == 0xE0,
+        _ => false,
+    })
+}
+
+impl LyricsSource for Id3Source {
+    fn read_track(&self, file_path: &Path, options: &ExtractOptions) -> Result<ExtractedTrack> {
+        let is_mp3 = file_path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("mp3"));
+        if is_mp3 && !looks_like_mp3(file_path)? {
+            warn!("{} is not a valid MP3 (no ID3 header or MPEG frame sync), skipping it", file_path.display());
+            return Ok(ExtractedTrack::default());
+        }
+        let Some(tag) = read_id3_tag(file_path, options.mmap, options.max_tag_bytes)? else {
+            if options.id3v1_comments {
+                return read_id3v1_track(file_path);
+            }
+            return Ok(ExtractedTrack::default());
+        };
+        
+        let mut candidates = match &options.frame {
+            Some(selector) => find_id3_frame(&tag, selector),
+            None => find_id3_lyrics(&tag, &options.source_priority),
+        };
+        if options.merge_frames {
+            merge_uslt_candidates(&mut candidates);
+        }
+        if candidates.is_empty() && options.id3v1_comments && options.frame.is_none() {
+            candidates.extend(read_id3v1_tag(file_path)?.as_ref().and_then(find_id3v1_lyrics));
+        }
+        let mut title = non_empty(tag.title());
+        if candidates.is_empty() && options.desperate && options.frame.is_none() {
+            if let Some(candidate) = find_desperate_lyrics(&tag) {
+                let (path, id) = (file_path.display(), &candidate.frame.id);
+                warn!("No lyrics frames in {}, so using the multi-line {} frame as lyrics (--desperate)", path, id);
+                // A title stuffed with lyrics is no use as a title
+                if candidate.frame.id == "TIT2" {
+                    title = None;
+                }
+                candidates.push(candidate);
+            }
+        }
+        let chosen = select_lyrics(&candidates, file_path, options.language.as_deref());
+        let (synced, synced_info) = find_id3_synced_lyrics(&tag, file_path).unzip();
+        let raw_lyrics = match chosen {
+            Some(chosen) if options.raw => read_raw_id3_lyrics(file_path, &tag, chosen)?,
+            _ => None,
+        };
+        Ok(ExtractedTrack {
+            lyrics: chosen.map(|candidate| candidate.text.clone()),
+            lyrics_frame: chosen.map(|candidate| candidate.frame.clone()),
+            candidates,
+            has_tag: true,
+            instrumental: is_marked_instrumental(&tag),
+            synced,
+            synced_info,
+            raw_lyrics,
+            title,
+            artist: non_empty(tag.artist()),
+            album: non_empty(tag.album()),
+            track_number: tag.track(),
+        })
+    }
+
+    fn read_metadata(&self, file_path: &Path, max_tag_bytes: u64) -> Result<TrackMetadata> {
+        let Some(tag) = read_id3_tag(file_path, false, max_tag_bytes)? else {
+            return Ok(TrackMetadata::default());
+        };
+        
+        Ok(TrackMetadata {
+            artist: non_empty(tag.artist()),
+            album: non_empty(tag.album()),
+            title: non_empty(tag.title()),
+            // Resolves ID3v1 style numeric genres such as "(17)" to their names
+            genre: non_empty(tag.genre_parsed().as_deref()),
+            track_number: tag.track(),
+        })
+    }
+
+    fn read_frames(&self, file_path: &Path, max_tag_bytes: u64) -> Result<Vec<FrameInfo>> {
+        let Some(tag) = read_id3_tag(file_path, false, max_tag_bytes)? else {
+            return Ok(Vec::new());
+        };
+        Ok(tag.frames()
+            .map(|frame| FrameInfo { id: frame.id().to_string(), content: frame.content().to_string() })
+            .collect())
+    }
+}
+
+/// Read a file's ID3v1 tag, the 128 bytes at the end of the file, if it has one
+fn read_id3v1_tag(file_path: &Path) -> Result<Option<id3::v1::Tag>> {
+    match id3::v1::Tag::read_from_path(file_path) {
+        Ok(tag) => Ok(Some(tag)),
+        Err(e) if matches!(e.kind, id3::ErrorKind::NoTag) => Ok(None),
+        Err(source) => Err(Error::Id3 { path: file_path.to_path_buf(), source }),
+    }
+}
+
+/// Take an ID3v1 comment as lyrics if it reads like a line of them rather than a rip note or URL
+///
+/// ID3v1 has no lyrics field, but some old rips squeeze a first line into the 30 byte comment.
+fn find_id3v1_lyrics(tag: &id3::v1::Tag) -> Option<LyricsCandidate> {
+    let comment = tag.comment.trim();
+    let words = comment.split_whitespace().filter(|word| word.chars().any(char::is_alphabetic)).count();
+    let lowercase = comment.to_lowercase();
+    let is_link = ["://", "www.", ".com"].iter().any(|marker| lowercase.contains(marker));
+    (words >= 2 && !is_link).then(|| LyricsCandidate {
+        frame: LyricsFrame::new("ID3v1 comment", None),
+        description: String::new(),
+        text: comment.to_string(),
+    })
+}
+
+/// Read the lyrics and metadata of a file that only has an ID3v1 tag
+fn read_id3v1_track(file_path: &Path) -> Result<ExtractedTrack> {
+    let Some(tag) = read_id3v1_tag(file_path)? else {
+        return Ok(ExtractedTrack::default());
+    };
+    let candidates: Vec<LyricsCandidate> = find_id3v1_lyrics(&tag).into_iter().collect();
+    Ok(ExtractedTrack {
+        lyrics: candidates.first().map(|candidate| candidate.text.clone()),
+        lyrics_frame: candidates.first().map(|candidate| candidate.frame.clone()),
+        candidates,
+        has_tag: true,
+        instrumental: false,
+        synced: None,
+        synced_info: None,
+        raw_lyrics: None,
+        title: non_empty(Some(&tag.title)),
+        artist: non_empty(Some(&tag.artist)),
+        album: non_empty(Some(&tag.album)),
+        track_number: tag.track.map(u32::from),
+    })
+}
+
+/// Read the timed entries of the first SYLT (Synchronised lyrics) frame with millisecond timestamps
+fn find_id3_synced_lyrics(tag: &Tag, file_path: &Path) -> Option<(Vec<TimedLine>, SyncedLyricsInfo)> {
+    for sylt in tag.synchronised_lyrics() {
+        if sylt.timestamp_format != TimestampFormat::Ms {
+            debug!("Skipping SYLT frame with MPEG frame timestamps in {}", file_path.display());
+            continue;
+        }
+        let mut lines: Vec<TimedLine> = sylt.content
+            .iter()
+            .map(|(start_ms, text)| TimedLine { start_ms: *start_ms, text: text.clone() })
+            .collect();
+        // Entries should already be in order, but cue end times rely on it
+        lines.sort_by_key(|line| line.start_ms);
+        let info = SyncedLyricsInfo {
+            content_type: sylt.content_type.to_string().to_lowercase(),
+            description: non_empty(Some(&sylt.description)),
+            language: non_empty(Some(&sylt.lang)),
+        };
+        return Some((lines, info));
+    }
+    None
+}
+
+/// Whether a TXXX frame described `INSTRUMENTAL` has the value `1`
+fn is_marked_instrumental(tag: &Tag) -> bool {
+    tag.extended_texts()
+        .any(|text| text.description.trim().eq_ignore_ascii_case("INSTRUMENTAL") && text.value.trim() == "1")
+}
+
+/// COMM and TXXX descriptions that mark a frame as holding lyrics, compared ignoring case
+const LYRICS_DESCRIPTIONS: &[&str] = &["LYRICS", "UNSYNCED LYRICS", "UNSYNCEDLYRICS", "LYRICS3"];
+
+fn is_lyrics_description(description: &str) -> bool {
+    LYRICS_DESCRIPTIONS.iter().any(|known| description.trim().eq_ignore_ascii_case(known))
+}
+
+/// Collect every ID3 frame that could hold lyrics, in the order of the kinds in `priority`
+fn find_id3_lyrics(tag: &Tag, priority: &[LyricsSourceKind]) -> Vec<LyricsCandidate> {
+    // Common non-standard lyric frame IDs
+    let non_standard = |frame_id: &str| {
+        TagLike::get(tag, frame_id).and_then(|frame| frame.content().text()).map(|content| LyricsCandidate {
+            frame: LyricsFrame::new(frame_id, None),
+            description: String::new(),
+            text: content.to_string(),
+        })
+    };
+
+    let mut candidates = Vec::new();
+    for kind in priority {
+        match kind {
+            // USLT (Unsynchronized lyrics) frames
+            LyricsSourceKind::Uslt => candidates.extend(tag.lyrics().map(|lyrics| LyricsCandidate {
+                frame: LyricsFrame::new("USLT", Some(&lyrics.lang)),
+                description: lyrics.description.clone(),
+                text: lyrics.text.clone(),
+            })),
+            // COMM (Comments) frames that might contain lyrics
+            LyricsSourceKind::Comm => candidates.extend(
+                tag.comments().filter(|c| is_lyrics_description(&c.description)).map(|comment| LyricsCandidate {
+                    frame: LyricsFrame::new("COMM", Some(&comment.lang)),
+                    description: comment.description.clone(),
+                    text: comment.text.clone(),
+                }),
+            ),
+            // TXXX (User defined text) frames
+            LyricsSourceKind::Txxx => candidates.extend(
+                tag.extended_texts().filter(|t| is_lyrics_description(&t.description)).map(|text| LyricsCandidate {
+                    frame: LyricsFrame::new("TXXX", None),
+                    description: text.description.clone(),
+                    text: text.value.clone(),
+                }),
+            ),
+            LyricsSourceKind::Lyrics => candidates.extend(non_standard("LYRICS")),
+            LyricsSourceKind::Lyrw => candidates.extend(non_standard("LYRW")),
+            LyricsSourceKind::Unsyncedlyrics => candidates.extend(non_standard("UNSYNCEDLYRICS")),
+        }
+    }
+    
+    candidates
+}
+
+/// Replace the USLT frames among `candidates` with one holding all their text, in the place of the first
+///
+/// The merged frame keeps the first one's language and description.
+fn merge_uslt_candidates(candidates: &mut Vec<LyricsCandidate>) {
+    let is_uslt = |candidate: &LyricsCandidate| candidate.frame.id == "USLT";
+    let texts: Vec<&str> = candidates.iter().filter(|c| is_uslt(c)).map(|c| c.text.trim_end_matches('\n')).collect();
+    if texts.len() < 2 {
+        return;
+    }
+    let Some(first) = candidates.iter().position(is_uslt) else {
+        return;
+    };
+    let merged = LyricsCandidate { text: texts.join("\n\n"), ..candidates[first].clone() };
+    candidates.retain(|candidate| !is_uslt(candidate));
+    candidates.insert(first, merged);
+}
+
+/// Fewest non-blank lines a comment or title needs for `--desperate` to take it as lyrics
+const DESPERATE_MIN_LINES: usize = 4;
+
+/// Find lyrics stuffed into a COMM frame of any description, or failing that the title, for
+/// `--desperate`; only text of at least [`DESPERATE_MIN_LINES`] lines counts
+fn find_desperate_lyrics(tag: &Tag) -> Option<LyricsCandidate> {
+    let is_multi_line = |text: &str| text.lines().filter(|line| !line.trim().is_empty()).count() >= DESPERATE_MIN_LINES;
+    let comment = tag.comments().find(|comment| is_multi_line(&comment.text)).map(|comment| LyricsCandidate {
+        frame: LyricsFrame::new("COMM", Some(&comment.lang)),
+        description: comment.description.clone(),
+        text: comment.text.clone(),
+    });
+    comment.or_else(|| {
+        tag.title().filter(|title| is_multi_line(title)).map(|title| LyricsCandidate {
+            frame: LyricsFrame::new("TIT2", None),
+            description: String::new(),
+            text: title.to_string(),
+        })
+    })
+}
+
+/// Collect the frames picked out by a `--frame-id`, skipping the usual search
+fn find_id3_frame(tag: &Tag, selector: &FrameSelector) -> Vec<LyricsCandidate> {
+    let candidate = |language: Option<&str>, description: &str, text: &str| LyricsCandidate {
+        frame: LyricsFrame::new(selector.id.as_str(), language),
+        description: description.to_string(),
+        text: text.to_string(),
+    };
+    match selector.id.as_str() {
+        "USLT" => tag.lyrics()
+            .filter(|lyrics| selector.matches_description(&lyrics.description))
+            .map(|lyrics| candidate(Some(&lyrics.lang), &lyrics.description, &lyrics.text))
+            .collect(),
+        "COMM" => tag.comments()
+            .filter(|comment| selector.matches_description(&comment.description))
+            .map(|comment| candidate(Some(&comment.lang), &comment.description, &comment.text))
+            .collect(),
+        "TXXX" => tag.extended_texts()
+            .filter(|text| selector.matches_description(&text.description))
+            .map(|text| candidate(None, &text.description, &text.value))
+            .collect(),
+        id => tag.frames()
+            .filter(|frame| frame.id() == id)
+            .filter_map(|frame| frame.content().text())
+            .map(|text| candidate(None, "", text))
+            .collect(),
+    }
+}
+
+/// Read the bytes a lyrics candidate's text is stored as, before the id3 crate decodes them
+///
+/// The candidate's frame is found again by its position among the tag's frames with the same ID.
+/// This only walks ID3v2.3 and v2.4 tags, and gives `None` for a frame it can't read byte for byte,
+/// such as a compressed or unsynchronised one.
+fn read_raw_id3_lyrics(file_path: &Path, tag: &Tag, chosen: &LyricsCandidate) -> Result<Option<Vec<u8>>> {
+    let id = chosen.frame.id.as_str();
+    let position = tag.frames().filter(|frame| frame.id() == id).position(|frame| {
+        let (description, text) = match frame.content() {
+            Content::Lyrics(lyrics) => (lyrics.description.as_str(), lyrics.text.as_str()),
+            Content::Comment(comment) => (comment.description.as_str(), comment.text.as_str()),
+            Content::ExtendedText(text) => (text.description.as_str(), text.value.as_str()),
+            content => ("", content.text().unwrap_or_default()),
+        };
+        description == chosen.description && text == chosen.text
+    });
+    let Some(position) = position else {
+        return Ok(None);
+    };
+    let Some(size) = peek_id3_tag_size(file_path)? else {
+        return Ok(None);
+    };
+    let mut tag_bytes = Vec::new();
+    File::open(file_path)
+        .and_then(|file| file.take(size).read_to_end(&mut tag_bytes))
+        .map_err(|e| Error::io("Failed to read", file_path, e))?;
+    let raw = raw_id3_frame_body(&tag_bytes, id, position).and_then(|body| raw_frame_text(id, body));
+    if raw.is_none() {
+        debug!("Can't read the {} frame of {} byte for byte, using the decoded text", id, file_path.display());
+    }
+    Ok(raw)
+}
+
+/// The body of the `position`th frame with ID `id` in an undecoded ID3v2.3 or v2.4 tag
+fn raw_id3_frame_body<'a>(tag: &'a [u8], id: &str, position: usize) -> Option<&'a [u8]> {
+    let (version, flags) = (*tag.get(3)?, *tag.get(5)?);
+    // An unsynchronised tag has had bytes inserted throughout, so nothing in it is as written
+    if !matches!(version, 3 | 4) || flags & 0x80 != 0 {
+        return None;
+    }
+    let synchsafe = |bytes: &[u8]| bytes.iter().fold(0, |size, byte| (size << 7) | usize::from(byte & 0x7F));
+    let plain = |bytes: &[u8]| bytes.iter().fold(0, |size, byte| (size << 8) | usize::from(*byte));
+    let mut offset = 10;
+    if flags & 0x40 != 0 {
+        // The extended header's size counts itself in v2.4 but not in v2.3
+        let size = tag.get(10..14)?;
+        offset += if version == 4 { synchsafe(size) } else { plain(size) + 4 };
+    }
+
+    let mut seen = 0;
+    while let Some(header) = tag.get(offset..offset + 10) {
+        if header[0] == 0 {
+            break; // Padding
+        }
+        let size = if version == 4 { synchsafe(&header[4..8]) } else { plain(&header[4..8]) };
+        let body = tag.get(offset + 10..offset + 10 + size)?;
+        offset += 10 + size;
+        if &header[..4] != id.as_bytes() {
+            continue;
+        }
+        if seen < position {
+            seen += 1;
+            continue;
+        }
+        // Compression, encryption and (in v2.4) per-frame unsynchronisation all change the bytes
+        let format = header[9];
+        return match version {
+            4 if format & 0x0E != 0 => None,
+            4 if format & 0x01 != 0 => body.get(4..), // Data length indicator
+            3 if format & 0xC0 != 0 => None,
+            _ => Some(body),
+        };
+    }
+    None
+}
+
+/// The text of a frame body, after its encoding byte and any language and description
+fn raw_frame_text(id: &str, body: &[u8]) -> Option<Vec<u8>> {
+    let (&encoding, rest) = body.split_first()?;
+    let described = match id {
+        "USLT" | "COMM" => rest.get(3..)?,
+        "TXXX" => rest,
+        _ => return Some(rest.to_vec()),
+    };
+    // The description ends in a null, which is two (aligned) bytes in UTF-16
+    let text_start = match encoding {
+        1 | 2 => described.chunks(2).position(|pair| pair == [0, 0])? * 2 + 2,
+        _ => described.iter().position(|&byte| byte == 0)? + 1,
+    };
+    Some(described[text_start..].to_vec())
+}
+
+/// Pick the USLT frame matching `language`, or the first candidate if none match
+fn select_lyrics<'a>(
+    candidates: &'a [LyricsCandidate],
+    file_path: &Path,
+    language: Option<&str>,
+) -> Option<&'a LyricsCandidate> {
+    if let Some(language) = language {
+        let mut uslt = candidates.iter().filter(|candidate| candidate.frame.id == "USLT");
+        let matching = uslt.clone().find(|candidate| {
+            candidate.frame.language.as_deref().is_some_and(|lang| lang.eq_ignore_ascii_case(language))
+        });
+        if matching.is_some() {
+            return matching;
+        }
+        if uslt.next().is_some() {
+            warn!("No '{}' lyrics in {}, using the first available frame", language, file_path.display());
+        }
+    }
+    candidates.first()
+}
+
+/// Read a `TRACKNUMBER` comment, which is often written as "3/12"
+fn parse_track_number(value: &str) -> Option<u32> {
+    value.split('/').next().and_then(|number| number.trim().parse().ok())
+}
+
+impl LyricsSource for FlacSource {
+    fn read_track(&self, file_path: &Path, _options: &ExtractOptions) -> Result<ExtractedTrack> {
+        let tag = metaflac::Tag::read_from_path(file_path)
+            .map_err(|source| Error::Flac { path: file_path.to_path_buf(), source })?;
+        let first_value = |key: &str| tag.get_vorbis(key).and_then(|mut values| values.next());
+        let candidates: Vec<LyricsCandidate> = ["LYRICS", "UNSYNCEDLYRICS"]
+            .into_iter()
+            .flat_map(|key| tag.get_vorbis(key).into_iter().flatten().map(move |value| (key, value)))
+            .map(|(key, value)| LyricsCandidate {
+                frame: LyricsFrame::new(key, None),
+                description: String::new(),
+                text: value.to_string(),
+            })
+            .collect();
+        let chosen = candidates.first();
+        
+        Ok(ExtractedTrack {
+            lyrics: chosen.map(|candidate| candidate.text.clone()),
+            lyrics_frame: chosen.map(|candidate| candidate.frame.clone()),
+            candidates,
+            has_tag: tag.vorbis_comments().is_some(),
+            instrumental: first_value("INSTRUMENTAL").is_some_and(|value| value.trim() == "1"),
+            synced: None,
+            synced_info: None,
+            raw_lyrics: None,
+            title: non_empty(first_value("TITLE")),
+            artist: non_empty(first_value("ARTIST")),
+            album: non_empty(first_value("ALBUM")),
+            track_number: first_value("TRACKNUMBER").and_then(parse_track_number),
+        })
+    }
+
+    fn read_metadata(&self, file_path: &Path, _max_tag_bytes: u64) -> Result<TrackMetadata> {
+        let tag = metaflac::Tag::read_from_path(file_path)
+            .map_err(|source| Error::Flac { path: file_path.to_path_buf(), source })?;
+        let first_value = |key: &str| tag.get_vorbis(key).and_then(|mut values| values.next());
+        
+        Ok(TrackMetadata {
+            artist: non_empty(first_value("ARTIST")),
+            album: non_empty(first_value("ALBUM")),
+            title: non_empty(first_value("TITLE")),
+            genre: non_empty(first_value("GENRE")),
+            track_number: first_value("TRACKNUMBER").and_then(parse_track_number),
+        })
+    }
+
+    fn read_frames(&self, file_path: &Path, _max_tag_bytes: u64) -> Result<Vec<FrameInfo>> {
+        let tag = metaflac::Tag::read_from_path(file_path)
+            .map_err(|source| Error::Flac { path: file_path.to_path_buf(), source })?;
+        
+        let mut frames = Vec::new();
+        if let Some(comments) = tag.vorbis_comments() {
+            // Comments are stored in a map, so sort them for stable output
+            let mut keys: Vec<&String> = comments.comments.keys().collect();
+            keys.sort();
+            for key in keys {
+                for value in &comments.comments[key] {
+                    frames.push(FrameInfo { id: key.clone(), content: value.clone() });
+                }
+            }
+        }
+        for picture in tag.pictures() {
+            let content = format!("{} {:?}: {}", picture.mime_type, picture.picture_type, picture.description);
+            frames.push(FrameInfo { id: "PICTURE".to_string(), content });
+        }
+        Ok(frames)
+    }
+}
+
+impl LoftySource {
+    /// Read the file's primary tag, falling back to whichever tag it has
+    fn read_tag(file_path: &Path) -> Result<Option<lofty::tag::Tag>> {
+        // Audio properties aren't needed, and skipping them tolerates files with unusual streams
+        let tagged_file = Probe::open(file_path)
+            .map(|probe| probe.options(ParseOptions::new().read_properties(false)))
+            .and_then(|probe| probe.read())
+            .map_err(|source| Error::Lofty { path: file_path.to_path_buf(), source })?;
+        let tag = tagged_file.primary_tag().or_else(|| tagged_file.first_tag()).cloned();
+        Ok(tag)
+    }
+}
+
+/// Whether a file is read as OGG Vorbis, rather than as one of the MP4 family
+fn is_vorbis_file(file_path: &Path) -> bool {
+    let extension = file_path.extension().and_then(|ext| ext.to_str());
+    extension.is_some_and(|ext| ["ogg", "oga"].iter().any(|known| ext.eq_ignore_ascii_case(known)))
+}
+
+/// Whether an OGG Vorbis file's comments say `INSTRUMENTAL=1`
+///
+/// lofty's generic tag drops comments it has no key for, so this reads the comments themselves.
+fn is_ogg_marked_instrumental(file_path: &Path) -> bool {
+    let comments = File::open(file_path).map_err(|e| e.to_string()).and_then(|mut file| {
+        VorbisFile::read_from(&mut file, ParseOptions::new().read_properties(false)).map_err(|e| e.to_string())
+    });
+    match comments {
+        Ok(vorbis) => vorbis.vorbis_comments().get("INSTRUMENTAL").is_some_and(|value| value.trim() == "1"),
+        Err(e) => {
+            debug!("Couldn't read the Vorbis comments of {}: {}", file_path.display(), e);
+            false
+        }
+    }
+}
+
+impl LyricsSource for LoftySource {
+    fn read_track(&self, file_path: &Path, _options: &ExtractOptions) -> Result<ExtractedTrack> {
+        let Some(tag) = Self::read_tag(file_path)? else {
+            return Ok(ExtractedTrack::default());
+        };
+        let candidates: Vec<LyricsCandidate> = [ItemKey::Lyrics, ItemKey::UnsyncLyrics]
+            .into_iter()
+            .filter_map(|key| {
+                let text = tag.get_string(key)?.to_string();
+                Some(LyricsCandidate { frame: LyricsFrame::new(format!("{:?}", key), None), description: String::new(), text })
+            })
+            .collect();
+        let chosen = candidates.first();
+        
+        Ok(ExtractedTrack {
+            lyrics: chosen.map(|candidate| candidate.text.clone()),
+            lyrics_frame: chosen.map(|candidate| candidate.frame.clone()),
+            candidates,
+            has_tag: true,
+            instrumental: is_vorbis_file(file_path) && is_ogg_marked_instrumental(file_path),
+            synced: None,
+            synced_info: None,
+            raw_lyrics: None,
+            title: non_empty(tag.title().as_deref()),
+            artist: non_empty(tag.artist().as_deref()),
+            album: non_empty(tag.album().as_deref()),
+            track_number: tag.track(),
+        })
+    }
+
+    fn read_metadata(&self, file_path: &Path, _max_tag_bytes: u64) -> Result<TrackMetadata> {
+        let Some(tag) = Self::read_tag(file_path)? else {
+            return Ok(TrackMetadata::default());
+        };
+        
+        Ok(TrackMetadata {
+            artist: non_empty(tag.artist().as_deref()),
+            album: non_empty(tag.album().as_deref()),
+            title: non_empty(tag.title().as_deref()),
+            genre: non_empty(tag.genre().as_deref()),
+            track_number: tag.track(),
+        })
+    }
+
+    fn read_frames(&self, file_path: &Path, _max_tag_bytes: u64) -> Result<Vec<FrameInfo>> {
+        let Some(tag) = Self::read_tag(file_path)? else {
+            return Ok(Vec::new());
+        };
+        
+        let mut frames: Vec<FrameInfo> = tag.items()
+            .map(|item| {
+                let content = match item.value() {
+                    ItemValue::Text(text) | ItemValue::Locator(text) => text.clone(),
+                    ItemValue::Binary(data) => format!("<{} bytes>", data.len()),
+                };
+                FrameInfo { id: format!("{:?}", item.key()), content }
+            })
+            .collect();
+        for picture in tag.pictures() {
+            let content = format!("{:?}: {}", picture.pic_type(), picture.description().unwrap_or_default());
+            frames.push(FrameInfo { id: "Picture".to_string(), content });
+        }
+        Ok(frames)
+    }
+}
@@ -0,0 +1,150 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+use clap::ValueEnum;
+use serde::Serialize;
+use crate::error::Result;
+use crate::extract::TrackResult;
+
+/// Output formats for the `stats`, `validate` and `count` subcommands
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StatsFormat {
+    /// A table with one row per track
+    #[default]
+    Text,
+    /// JSON object with per-track stats and totals
+    Json,
+}
+
+/// Line and word counts for a body of lyrics
+#[derive(Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LyricsStats {
+    /// Non-blank lines
+    pub lines: usize,
+    pub words: usize,
+    /// Distinct words, ignoring case and surrounding punctuation
+    pub unique_words: usize,
+}
+
+impl LyricsStats {
+    /// Count the lines and words in `lyrics`
+    pub fn from_lyrics(lyrics: &str) -> Self {
+        let words: Vec<&str> = lyrics.split_whitespace().collect();
+        LyricsStats {
+            lines: lyrics.lines().filter(|line| !line.trim().is_empty()).count(),
+            words: words.len(),
+            unique_words: unique_words(&words).len(),
+        }
+    }
+}
+
+/// Stats for a single track
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct TrackStats {
+    pub path: PathBuf,
+    #[serde(flatten)]
+    pub stats: LyricsStats,
+}
+
+/// Stats for every track with lyrics, plus the files that were skipped
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct StatsReport {
+    pub tracks: Vec<TrackStats>,
+    /// Files with no lyrics, or that couldn't be read
+    pub without_lyrics: Vec<PathBuf>,
+    /// Sums over all tracks, except `unique_words` which counts distinct words across all of them
+    pub totals: LyricsStats,
+}
+
+impl StatsReport {
+    /// Compute stats for each track that has lyrics
+    pub fn from_results(results: &[TrackResult]) -> Self {
+        let mut tracks = Vec::new();
+        let mut without_lyrics = Vec::new();
+        let mut totals = LyricsStats::default();
+        let mut all_words = HashSet::new();
+
+        for result in results {
+            let Some(lyrics) = &result.lyrics else {
+                without_lyrics.push(result.path.clone());
+                continue;
+            };
+            let stats = LyricsStats::from_lyrics(lyrics);
+            totals.lines += stats.lines;
+            totals.words += stats.words;
+            all_words.extend(unique_words(&lyrics.split_whitespace().collect::<Vec<_>>()));
+            tracks.push(TrackStats { path: result.path.clone(), stats });
+        }
+        totals.unique_words = all_words.len();
+
+        StatsReport { tracks, without_lyrics, totals }
+    }
+
+    /// Render the report as a table, with skipped files listed after the totals
+    pub fn render_text(&self) -> String {
+        let mut output = format!("{:>7} {:>7} {:>7}  File\n", "Lines", "Words", "Unique");
+        for track in &self.tracks {
+            output.push_str(&format_row(&track.stats, &track.path.display().to_string()));
+        }
+        output.push_str(&format_row(&self.totals, "Total"));
+        for path in &self.without_lyrics {
+            output.push_str(&format!("No lyrics: {}\n", path.display()));
+        }
+        output
+    }
+
+    /// Serialize the report as pretty-printed JSON
+    pub fn render_json(&self) -> Result<String> {
+        let mut json = serde_json::to_string_pretty(self)?;
+        json.push('\n');
+        Ok(json)
+    }
+}
+
+fn format_row(stats: &LyricsStats, label: &str) -> String {
+    format!("{:>7} {:>7} {:>7}  {}\n", stats.lines, stats.words, stats.unique_words, label)
+}
+
+/// Normalize words for uniqueness counting, so "Love," and "love" are the same word
+fn unique_words(words: &[&str]) -> HashSet<String> {
+    words
+        .iter()
+        .map(|word| word.trim_matches(|c: char| !c.is_alphanumeric()).to_lowercase())
+        .filter(|word| !word.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::track;
+
+    #[test]
+    fn test_lyrics_stats() {
+        let stats = LyricsStats::from_lyrics("Love, love me do\n\nYou know I love you\n");
+        assert_eq!(stats, LyricsStats { lines: 2, words: 9, unique_words: 6 });
+        assert_eq!(LyricsStats::from_lyrics(""), LyricsStats::default());
+    }
+
+    #[test]
+    fn test_stats_report() {
+        let results = vec![
+            track("a.mp3", Some("Hello world")),
+            track("b.mp3", None),
+            track("c.mp3", Some("Hello again\nworld")),
+        ];
+
+        let report = StatsReport::from_results(&results);
+        assert_eq!(report.tracks.len(), 2);
+        assert_eq!(report.without_lyrics, vec![PathBuf::from("b.mp3")]);
+        assert_eq!(report.totals, LyricsStats { lines: 3, words: 5, unique_words: 3 });
+
+        let text = report.render_text();
+        assert!(text.contains("Total"));
+        assert!(text.contains("No lyrics: b.mp3"));
+
+        let json: serde_json::Value = serde_json::from_str(&report.render_json().unwrap()).unwrap();
+        assert_eq!(json["tracks"][1]["path"], "c.mp3");
+        assert_eq!(json["tracks"][1]["lines"], 2);
+        assert_eq!(json["totals"]["unique_words"], 3);
+    }
+}
@@ -0,0 +1,214 @@
+use crate::error::{Error, Result};
+use crate::extract::TrackResult;
+use crate::render::file_name;
+
+/// A value that can be substituted into a template
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Placeholder {
+    Filename,
+    /// The file name without its extension
+    Stem,
+    Artist,
+    Album,
+    Title,
+    Lyrics,
+    /// 1-based position of the track in the output
+    Index,
+    /// The track number tag, as read during extraction
+    Track,
+}
+
+impl Placeholder {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "filename" => Some(Placeholder::Filename),
+            "stem" => Some(Placeholder::Stem),
+            "artist" => Some(Placeholder::Artist),
+            "album" => Some(Placeholder::Album),
+            "title" => Some(Placeholder::Title),
+            "lyrics" => Some(Placeholder::Lyrics),
+            "index" => Some(Placeholder::Index),
+            "track" => Some(Placeholder::Track),
+            _ => None,
+        }
+    }
+
+    fn is_number(self) -> bool {
+        matches!(self, Placeholder::Index | Placeholder::Track)
+    }
+}
+
+/// How wide to pad a number, from a spec such as the `02` in `{track:02}`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Width {
+    width: usize,
+    /// Pad with zeros rather than spaces
+    zeros: bool,
+}
+
+impl Width {
+    fn pad(self, value: String) -> String {
+        if self.zeros {
+            format!("{:0>1$}", value, self.width)
+        } else {
+            format!("{:>1$}", value, self.width)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Text(String),
+    Placeholder(Placeholder, Option<Width>),
+}
+
+/// Characters left out of file names built by [`Template::render_file_name`], as they aren't
+/// allowed on one common filesystem or another
+const INVALID_FILE_NAME_CHARS: &[char] = &['/', '\\', ':', '*', '?', '"', '<', '>', '|'];
+
+/// A per-track output layout such as `"{index}. {title}\n{lyrics}\n"`
+///
+/// Recognised placeholders are `{filename}`, `{stem}`, `{artist}`, `{album}`, `{title}`, `{lyrics}`,
+/// `{index}` and `{track}`; missing values become empty. The numbers can be padded to a width, with
+/// zeros as in `{track:02}` or spaces as in `{index:3}`. `{{` and `}}` stand for literal braces.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    segments: Vec<Segment>,
+}
+
+impl Template {
+    /// Parse a template, rejecting unknown placeholders and unbalanced braces up front
+    pub fn parse(template: &str) -> Result<Self> {
+        let invalid = |reason: String| Error::InvalidTemplate { template: template.to_string(), reason };
+        let mut segments = Vec::new();
+        let mut text = String::new();
+        let mut rest = template;
+
+        while let Some(index) = rest.find(['{', '}']) {
+            text.push_str(&rest[..index]);
+            let brace = &rest[index..];
+            if brace.starts_with("{{") || brace.starts_with("}}") {
+                text.push_str(&brace[..1]);
+                rest = &brace[2..];
+            } else if brace.starts_with('}') {
+                return Err(invalid("unmatched '}'".to_string()));
+            } else {
+                let end = brace.find('}').ok_or_else(|| invalid("unclosed '{'".to_string()))?;
+                let (name, spec) = match brace[1..end].split_once(':') {
+                    Some((name, spec)) => (name, Some(spec)),
+                    None => (&brace[1..end], None),
+                };
+                let placeholder = Placeholder::from_name(name)
+                    .ok_or_else(|| invalid(format!("unknown placeholder {{{}}}", name)))?;
+                let width = match spec {
+                    None => None,
+                    Some(_) if !placeholder.is_number() => {
+                        return Err(invalid(format!("only {{index}} and {{track}} take a width, not {{{}}}", name)));
+                    }
+                    Some(spec) => match spec.parse() {
+                        Ok(width) if spec.bytes().all(|b| b.is_ascii_digit()) => {
+                            Some(Width { width, zeros: spec.starts_with('0') })
+                        }
+                        _ => return Err(invalid(format!("bad width '{}' in {{{}}}", spec, name))),
+                    },
+                };
+                if !text.is_empty() {
+                    segments.push(Segment::Text(std::mem::take(&mut text)));
+                }
+                segments.push(Segment::Placeholder(placeholder, width));
+                rest = &brace[end + 1..];
+            }
+        }
+        text.push_str(rest);
+        if !text.is_empty() {
+            segments.push(Segment::Text(text));
+        }
+        Ok(Template { segments })
+    }
+
+    /// Lay out one track, where `index` is its 0-based position in the output
+    pub fn render(&self, index: usize, result: &TrackResult) -> String {
+        self.render_with(index, result, |value| value)
+    }
+
+    /// Name the file for one track, where `index` is its 0-based position
+    ///
+    /// Characters that can't go in a file name are replaced with `_` in the values substituted, and
+    /// trailing dots and spaces are dropped from them, while the template's own text is kept as is.
+    pub fn render_file_name(&self, index: usize, result: &TrackResult) -> String {
+        self.render_with(index, result, |value| {
+            let value: String =
+                value.chars().map(|c| if INVALID_FILE_NAME_CHARS.contains(&c) || c.is_control() { '_' } else { c }).collect();
+            value.trim_end_matches(['.', ' ']).to_string()
+        })
+    }
+
+    fn render_with(&self, index: usize, result: &TrackResult, escape: impl Fn(String) -> String) -> String {
+        let mut output = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Text(text) => output.push_str(text),
+                Segment::Placeholder(placeholder, width) => {
+                    let value = match placeholder {
+                        Placeholder::Filename => file_name(result),
+                        Placeholder::Stem => {
+                            result.path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default()
+                        }
+                        Placeholder::Artist => result.artist.clone().unwrap_or_default(),
+                        Placeholder::Album => result.album.clone().unwrap_or_default(),
+                        Placeholder::Title => result.title.clone().unwrap_or_default(),
+                        Placeholder::Lyrics => result.lyrics.clone().unwrap_or_default(),
+                        Placeholder::Index => (index + 1).to_string(),
+                        Placeholder::Track => result.track_number.map(|track| track.to_string()).unwrap_or_default(),
+                    };
+                    let value = match width {
+                        Some(width) if !value.is_empty() => width.pad(value),
+                        _ => value,
+                    };
+                    output.push_str(&escape(value));
+                }
+            }
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_template() {
+        let result = TrackResult {
+            path: PathBuf::from("music/song.mp3"),
+            lyrics: Some("Line one".to_string()),
+            title: Some("Song".to_string()),
+            ..Default::default()
+        };
+        let template = Template::parse("{index}. {title} [{artist}] {{{filename}}}\n{lyrics}\n").unwrap();
+        assert_eq!(template.render(1, &result), "2. Song [] {song.mp3}\nLine one\n");
+
+        assert!(matches!(Template::parse("{lyric}"), Err(Error::InvalidTemplate { .. })));
+        assert!(Template::parse("{title").is_err());
+        assert!(Template::parse("title}").is_err());
+    }
+
+    #[test]
+    fn test_template_file_name() {
+        let result = TrackResult {
+            path: PathBuf::from("music/song.mp3"),
+            title: Some("What? / Why: \"Now\"...".to_string()),
+            artist: Some("AC/DC".to_string()),
+            ..Default::default()
+        };
+        let template = Template::parse("{artist}/{index:03} - {title} ({stem}){track:02}.txt").unwrap();
+        assert_eq!(template.render_file_name(4, &result), "AC_DC/005 - What_ _ Why_ _Now_ (song).txt");
+        assert_eq!(Template::parse("{index:4}").unwrap().render(8, &result), "   9");
+        let numbered = TrackResult { track_number: Some(3), ..result };
+        assert_eq!(Template::parse("{track:02}").unwrap().render(0, &numbered), "03");
+
+        assert!(Template::parse("{title:02}").is_err());
+        assert!(Template::parse("{track:x}").is_err());
+    }
+}
@@ -0,0 +1,220 @@
+//! Fixtures shared by the unit tests in each module
+
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use id3::Tag;
+use crate::TrackResult;
+
+/// A track at `path` with `lyrics`, for tests to fill in other fields with struct-update syntax
+pub(crate) fn track(path: impl AsRef<Path>, lyrics: Option<&str>) -> TrackResult {
+    TrackResult { path: path.as_ref().to_path_buf(), lyrics: lyrics.map(str::to_string), ..Default::default() }
+}
+
+// Helper function to create a test MP3 file with lyrics
+pub(crate) fn create_test_mp3(dir: &Path, filename: &str, lyrics: Option<&str>) -> PathBuf {
+    let file_path = dir.join(filename);
+    
+    // Create a minimal MP3 file with a valid ID3 tag structure
+    let mut file = File::create(&file_path).unwrap();
+    
+    // ID3v2 header (10 bytes)
+    let id3_header = [
+        b'I', b'D', b'3',  // ID3 marker
+        0x04, 0x00,         // Version 2.4.0
+        0x00,               // Flags
+        0x00, 0x00, 0x00, 0x0A  // Size (10 bytes following the header)
+    ];
+    
+    // Write headers and some minimal audio-like data
+    file.write_all(&id3_header).unwrap();
+    // Add 10 bytes of empty padding to match the size in the header
+    file.write_all(&[0; 10]).unwrap();
+    // Add some MP3-like frame data
+    file.write_all(&[0xFF, 0xFB, 0x90, 0x44, 0x00]).unwrap();
+    file.flush().unwrap();
+    
+    // If lyrics are provided, create a tag with lyrics
+    if let Some(lyrics_text) = lyrics {
+        let mut tag = Tag::new();
+        
+        // Add an Unsynchronized lyrics frame
+        use id3::frame::Lyrics;
+        use id3::TagLike;
+        
+        let lyrics_frame = Lyrics {
+            lang: String::from_utf8(vec![b'e', b'n', b'g']).unwrap(),
+            description: String::new(),
+            text: lyrics_text.to_string(),
+        };
+        tag.add_frame(lyrics_frame);
+        
+        tag.write_to_path(&file_path, id3::Version::Id3v24).unwrap();
+    }
+    
+    file_path
+}
+
+// Helper function to create a test FLAC file with an optional Vorbis comment
+pub(crate) fn create_test_flac(dir: &Path, filename: &str, comment: Option<(&str, &str)>) -> PathBuf {
+    let file_path = dir.join(filename);
+    
+    let mut tag = metaflac::Tag::new();
+    tag.vorbis_comments_mut().vendor_string = "test".to_string();
+    if let Some((key, value)) = comment {
+        tag.set_vorbis(key, vec![value]);
+    }
+    tag.write_to_path(&file_path).unwrap();
+    
+    file_path
+}
+
+// Helper function to create a test M4A file with an optional lyrics item
+pub(crate) fn create_test_m4a(dir: &Path, filename: &str, lyrics: Option<&str>) -> PathBuf {
+    use lofty::config::WriteOptions;
+    use lofty::tag::{ItemKey, Tag, TagExt, TagType};
+    
+    let file_path = dir.join(filename);
+    
+    // Just enough of an MP4 container for the tag to be read back: ftyp plus a moov with an empty mvhd
+    let atom = |name: &[u8; 4], body: &[u8]| {
+        let mut atom = (body.len() as u32 + 8).to_be_bytes().to_vec();
+        atom.extend_from_slice(name);
+        atom.extend_from_slice(body);
+        atom
+    };
+    let ftyp = atom(b"ftyp", b"M4A \0\0\0\0M4A isom");
+    let moov = atom(b"moov", &atom(b"mvhd", &[0; 100]));
+    File::create(&file_path).unwrap().write_all(&[ftyp, moov].concat()).unwrap();
+    
+    if let Some(lyrics_text) = lyrics {
+        let mut tag = Tag::new(TagType::Mp4Ilst);
+        tag.insert_text(ItemKey::Lyrics, lyrics_text.to_string());
+        tag.save_to_path(&file_path, WriteOptions::default()).unwrap();
+    }
+    
+    file_path
+}
+
+// Helper function to create a test OGG Vorbis file with an optional Vorbis comment
+pub(crate) fn create_test_ogg(dir: &Path, filename: &str, comment: Option<(&str, &str)>) -> PathBuf {
+    let file_path = dir.join(filename);
+    
+    // Identification header: version 0, 2 channels, 44.1kHz, no bitrates, 256/2048 sample blocks
+    let mut identification = b"\x01vorbis".to_vec();
+    identification.extend_from_slice(&0u32.to_le_bytes());
+    identification.push(2);
+    identification.extend_from_slice(&44_100u32.to_le_bytes());
+    identification.extend_from_slice(&[0; 12]);
+    identification.extend_from_slice(&[0xB8, 0x01]);
+    
+    let mut comments = b"\x03vorbis".to_vec();
+    comments.extend_from_slice(&4u32.to_le_bytes());
+    comments.extend_from_slice(b"test");
+    let fields: Vec<String> = comment.into_iter().map(|(key, value)| format!("{}={}", key, value)).collect();
+    comments.extend_from_slice(&(fields.len() as u32).to_le_bytes());
+    for field in &fields {
+        comments.extend_from_slice(&(field.len() as u32).to_le_bytes());
+        comments.extend_from_slice(field.as_bytes());
+    }
+    comments.push(1);
+    
+    // The setup header isn't parsed when only reading tags
+    let setup = b"\x05vorbis\x01".to_vec();
+    
+    let mut file = File::create(&file_path).unwrap();
+    file.write_all(&ogg_page(0x02, 0, &[&identification])).unwrap();
+    file.write_all(&ogg_page(0x00, 1, &[&comments, &setup])).unwrap();
+    
+    file_path
+}
+
+/// Wrap whole packets in a single OGG page
+fn ogg_page(header_type: u8, sequence: u32, packets: &[&[u8]]) -> Vec<u8> {
+    let mut segments = Vec::new();
+    for packet in packets {
+        segments.extend(std::iter::repeat_n(255, packet.len() / 255));
+        segments.push((packet.len() % 255) as u8);
+    }
+    
+    let mut page = b"OggS\0".to_vec();
+    page.push(header_type);
+    page.extend_from_slice(&0u64.to_le_bytes());
+    page.extend_from_slice(&1u32.to_le_bytes());
+    page.extend_from_slice(&sequence.to_le_bytes());
+    page.extend_from_slice(&[0; 4]);
+    page.push(segments.len() as u8);
+    page.extend_from_slice(&segments);
+    for packet in packets {
+        page.extend_from_slice(packet);
+    }
+    
+    // CRC-32 with polynomial 0x04C11DB7, computed with the checksum field zeroed
+    let crc = page.iter().fold(0u32, |crc, byte| {
+        (0..8).fold(crc ^ (u32::from(*byte) << 24), |crc, _| {
+            if crc & 0x8000_0000 != 0 { (crc << 1) ^ 0x04C1_1DB7 } else { crc << 1 }
+        })
+    });
+    page[22..26].copy_from_slice(&crc.to_le_bytes());
+    page
+}
+
+// Helper function to create an MP3 file with a corrupt ID3 tag, so reading it fails
+pub(crate) fn create_corrupt_mp3(dir: &Path, filename: &str) -> PathBuf {
+    let file_path = dir.join(filename);
+    
+    // An ID3v2.4 header with undefined flag bits set
+    let mut file = File::create(&file_path).unwrap();
+    file.write_all(&[b'I', b'D', b'3', 0x04, 0x00, 0x0F, 0x00, 0x00, 0x00, 0x0A]).unwrap();
+    file.write_all(&[0; 10]).unwrap();
+    
+    file_path
+}
+
+// Helper function to create an MP3 file with only an ID3v1 tag, holding `comment` in its comment field
+pub(crate) fn create_id3v1_mp3(dir: &Path, filename: &str, title: &str, comment: &str) -> PathBuf {
+    let file_path = dir.join(filename);
+    
+    // The 128 byte tag at the end of the file: "TAG", then fixed-width, NUL-padded fields
+    let mut tag = [0u8; 128];
+    tag[..3].copy_from_slice(b"TAG");
+    tag[3..3 + title.len()].copy_from_slice(title.as_bytes());
+    tag[97..97 + comment.len()].copy_from_slice(comment.as_bytes());
+    tag[127] = 255;  // No genre
+    
+    let mut file = File::create(&file_path).unwrap();
+    file.write_all(&[0xFF, 0xFB, 0x90, 0x44, 0x00]).unwrap();
+    file.write_all(&tag).unwrap();
+    
+    file_path
+}
+
+// Helper function to create a silent WAV file, with an ID3 chunk holding the lyrics if given
+pub(crate) fn create_test_wav(dir: &Path, filename: &str, lyrics: Option<&str>) -> PathBuf {
+    let file_path = dir.join(filename);
+    
+    // A RIFF header, a 16-bit mono PCM format chunk and an empty data chunk
+    let mut file = File::create(&file_path).unwrap();
+    file.write_all(b"RIFF").unwrap();
+    file.write_all(&36u32.to_le_bytes()).unwrap();
+    file.write_all(b"WAVEfmt ").unwrap();
+    file.write_all(&16u32.to_le_bytes()).unwrap();
+    file.write_all(&[1, 0, 1, 0]).unwrap();  // PCM, one channel
+    file.write_all(&44_100u32.to_le_bytes()).unwrap();
+    file.write_all(&88_200u32.to_le_bytes()).unwrap();
+    file.write_all(&[2, 0, 16, 0]).unwrap();  // Block align, bits per sample
+    file.write_all(b"data").unwrap();
+    file.write_all(&0u32.to_le_bytes()).unwrap();
+    file.flush().unwrap();
+    
+    if let Some(lyrics_text) = lyrics {
+        use id3::frame::Lyrics;
+        use id3::TagLike;
+        
+        let mut tag = Tag::new();
+        tag.add_frame(Lyrics { lang: "eng".to_string(), description: String::new(), text: lyrics_text.to_string() });
+        tag.write_to_path(&file_path, id3::Version::Id3v24).unwrap();
+    }
+    
+    file_path
+}
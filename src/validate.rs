@@ -0,0 +1,156 @@
+use std::path::{Path, PathBuf};
+use serde::Serialize;
+use crate::error::Result;
+use crate::extract::{extract_lyrics_from_file, ExtractOptions};
+
+/// What reading a file's tags turned up
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum TagHealth {
+    HasLyrics,
+    TagWithoutLyrics,
+    NoTag,
+    ParseError,
+}
+
+impl TagHealth {
+    fn label(self) -> &'static str {
+        match self {
+            TagHealth::HasLyrics => "has-lyrics",
+            TagHealth::TagWithoutLyrics => "tag-without-lyrics",
+            TagHealth::NoTag => "no-tag",
+            TagHealth::ParseError => "parse-error",
+        }
+    }
+}
+
+/// The tag health of a single file
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct FileHealth {
+    pub path: PathBuf,
+    pub health: TagHealth,
+    /// Why the tags couldn't be read, for [`TagHealth::ParseError`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl FileHealth {
+    /// Read a file's tags, looking for lyrics the same way extraction does
+    ///
+    /// An ID3 tag over `max_tag_bytes` counts as one that can't be read.
+    pub fn check(path: &Path, max_tag_bytes: u64) -> Self {
+        let options = ExtractOptions { max_tag_bytes, ..Default::default() };
+        let (health, error) = match extract_lyrics_from_file(path, &options) {
+            Ok(track) if track.lyrics.is_some() => (TagHealth::HasLyrics, None),
+            Ok(track) if track.has_tag => (TagHealth::TagWithoutLyrics, None),
+            Ok(_) => (TagHealth::NoTag, None),
+            Err(e) => (TagHealth::ParseError, Some(e.to_string())),
+        };
+        FileHealth { path: path.to_path_buf(), health, error }
+    }
+}
+
+/// How many files fell into each [`TagHealth`] category
+#[derive(Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct HealthCounts {
+    pub has_lyrics: usize,
+    pub tag_without_lyrics: usize,
+    pub no_tag: usize,
+    pub parse_error: usize,
+}
+
+/// Tag health of every file, for the `validate` subcommand
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ValidationReport {
+    pub files: Vec<FileHealth>,
+    pub summary: HealthCounts,
+}
+
+impl ValidationReport {
+    /// Check each file's tags, reading ID3 tags of up to `max_tag_bytes`, without writing anything
+    pub fn from_files(paths: &[PathBuf], max_tag_bytes: u64) -> Self {
+        let files: Vec<FileHealth> = paths.iter().map(|path| FileHealth::check(path, max_tag_bytes)).collect();
+        let mut summary = HealthCounts::default();
+        for file in &files {
+            match file.health {
+                TagHealth::HasLyrics => summary.has_lyrics += 1,
+                TagHealth::TagWithoutLyrics => summary.tag_without_lyrics += 1,
+                TagHealth::NoTag => summary.no_tag += 1,
+                TagHealth::ParseError => summary.parse_error += 1,
+            }
+        }
+        ValidationReport { files, summary }
+    }
+
+    /// Render the report as a table of files followed by the counts
+    pub fn render_text(&self) -> String {
+        let mut output = format!("{:<18}  File\n", "Status");
+        for file in &self.files {
+            output.push_str(&format!("{:<18}  {}", file.health.label(), file.path.display()));
+            if let Some(error) = &file.error {
+                output.push_str(&format!(" ({})", error));
+            }
+            output.push('\n');
+        }
+        let summary = &self.summary;
+        output.push_str(&format!(
+            "\nHas lyrics: {}, tag without lyrics: {}, no tag: {}, parse errors: {}\n",
+            summary.has_lyrics, summary.tag_without_lyrics, summary.no_tag, summary.parse_error,
+        ));
+        output
+    }
+
+    /// Serialize the report as pretty-printed JSON
+    pub fn render_json(&self) -> Result<String> {
+        let mut json = serde_json::to_string_pretty(self)?;
+        json.push('\n');
+        Ok(json)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extract::DEFAULT_MAX_TAG_BYTES;
+    use crate::test_util::{create_corrupt_mp3, create_test_flac, create_test_mp3};
+    use id3::TagLike;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_validation_report() {
+        let temp_dir = tempdir().unwrap();
+        let with_lyrics = create_test_mp3(temp_dir.path(), "a.mp3", Some("Lyrics"));
+        let tagged = create_test_mp3(temp_dir.path(), "b.mp3", None);
+        let mut tag = id3::Tag::new();
+        tag.set_title("Song");
+        tag.write_to_path(&tagged, id3::Version::Id3v24).unwrap();
+        let untagged = temp_dir.path().join("c.mp3");
+        std::fs::write(&untagged, [0xFF, 0xFB, 0x90, 0x00]).unwrap();
+        let corrupt = create_corrupt_mp3(temp_dir.path(), "d.mp3");
+        let flac = create_test_flac(temp_dir.path(), "e.flac", Some(("TITLE", "Song")));
+
+        let files = [with_lyrics, tagged, untagged, corrupt, flac];
+        let report = ValidationReport::from_files(&files, DEFAULT_MAX_TAG_BYTES);
+        let health: Vec<TagHealth> = report.files.iter().map(|file| file.health).collect();
+        assert_eq!(health, [
+            TagHealth::HasLyrics,
+            TagHealth::TagWithoutLyrics,
+            TagHealth::NoTag,
+            TagHealth::ParseError,
+            TagHealth::TagWithoutLyrics,
+        ]);
+        assert!(report.files[3].error.is_some());
+        assert_eq!(report.summary, HealthCounts { has_lyrics: 1, tag_without_lyrics: 2, no_tag: 1, parse_error: 1 });
+        // A tag over the size limit can't be read either
+        let limited = ValidationReport::from_files(&files[..1], 16);
+        assert_eq!(limited.files[0].health, TagHealth::ParseError);
+
+        let text = report.render_text();
+        assert!(text.contains("parse-error         "));
+        assert!(text.ends_with("Has lyrics: 1, tag without lyrics: 2, no tag: 1, parse errors: 1\n"));
+
+        let json: serde_json::Value = serde_json::from_str(&report.render_json().unwrap()).unwrap();
+        assert_eq!(json["files"][2]["health"], "no-tag");
+        assert_eq!(json["summary"]["parse_error"], 1);
+    }
+}
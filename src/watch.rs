@@ -0,0 +1,112 @@
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::time::Duration;
+use log::{debug, warn};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use crate::error::{Error, Result};
+use crate::find::FindOptions;
+
+/// How long the inputs must be quiet before a burst of changes is reported, when no delay is given
+pub const DEFAULT_DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Watches input directories for audio files being added or changed, for the `watch` subcommand
+pub struct InputWatcher {
+    /// Kept so the watch lasts as long as this does
+    _watcher: RecommendedWatcher,
+    messages: Receiver<Message>,
+    sender: Sender<Message>,
+    options: FindOptions,
+    debounce: Duration,
+}
+
+enum Message {
+    Event(notify::Result<Event>),
+    Stop,
+}
+
+impl InputWatcher {
+    /// Start watching each of `inputs`, directories recursively, for files that `options` matches
+    pub fn new(inputs: &[PathBuf], options: &FindOptions, debounce: Duration) -> Result<Self> {
+        let (sender, messages) = mpsc::channel();
+        let events = sender.clone();
+        let mut watcher = notify::recommended_watcher(move |event| {
+            let _ = events.send(Message::Event(event));
+        })
+        .map_err(|source| Error::Watch { path: inputs.first().cloned().unwrap_or_default(), source })?;
+        for input in inputs {
+            let mode = if input.is_dir() { RecursiveMode::Recursive } else { RecursiveMode::NonRecursive };
+            watcher.watch(input, mode).map_err(|source| Error::Watch { path: input.clone(), source })?;
+            debug!("Watching {}", input.display());
+        }
+        Ok(InputWatcher { _watcher: watcher, messages, sender, options: options.clone(), debounce })
+    }
+
+    /// A function that makes [`InputWatcher::next_change`] return `None`, such as from a Ctrl-C handler
+    pub fn stopper(&self) -> impl Fn() + Send + 'static {
+        let sender = self.sender.clone();
+        move || {
+            let _ = sender.send(Message::Stop);
+        }
+    }
+
+    /// Wait for audio files to be added or changed, returning them once the inputs have been quiet
+    /// for the debounce delay, or `None` once stopped
+    pub fn next_change(&self) -> Option<Vec<PathBuf>> {
+        let mut changed = BTreeSet::new();
+        loop {
+            let message = if changed.is_empty() {
+                self.messages.recv().ok()
+            } else {
+                match self.messages.recv_timeout(self.debounce) {
+                    Ok(message) => Some(message),
+                    Err(RecvTimeoutError::Timeout) => return Some(changed.into_iter().collect()),
+                    Err(RecvTimeoutError::Disconnected) => None,
+                }
+            };
+            match message {
+                Some(Message::Event(Ok(event))) if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) => {
+                    changed.extend(event.paths.into_iter().filter(|path| self.is_audio_file(path)));
+                }
+                Some(Message::Event(Ok(_))) => {}
+                Some(Message::Event(Err(e))) => warn!("Error while watching for changes: {}", e),
+                Some(Message::Stop) | None => return None,
+            }
+        }
+    }
+
+    fn is_audio_file(&self, path: &Path) -> bool {
+        path.file_name().is_some_and(|name| self.options.matcher.is_match(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::create_test_mp3;
+    use std::{fs, thread};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_input_watcher() {
+        let temp_dir = tempdir().unwrap();
+        let dir = temp_dir.path().canonicalize().unwrap();
+        let watcher = InputWatcher::new(std::slice::from_ref(&dir), &FindOptions::default(), Duration::from_millis(200)).unwrap();
+
+        // A burst of writes, including one to a file that isn't audio, is reported once
+        let writer = {
+            let dir = dir.clone();
+            thread::spawn(move || {
+                create_test_mp3(&dir, "a.mp3", Some("First"));
+                fs::write(dir.join("notes.txt"), "not audio").unwrap();
+                create_test_mp3(&dir, "b.mp3", Some("Second"));
+            })
+        };
+        let changed = watcher.next_change().unwrap();
+        writer.join().unwrap();
+        assert_eq!(changed, [dir.join("a.mp3"), dir.join("b.mp3")]);
+
+        watcher.stopper()();
+        assert_eq!(watcher.next_change(), None);
+    }
+}